@@ -0,0 +1,235 @@
+use prelude::*;
+use libc::{c_int, c_long, c_void};
+use std::cmp;
+use std::mem;
+use std::rc::Rc;
+
+impl ForeignType {
+    /// Gets how many bytes a value of this type takes up in a C struct. Bools are laid out as C's
+    /// one byte `_Bool`, even though they're passed to and from functions as `int`s.
+    pub fn size(&self) -> usize {
+        match *self {
+            ForeignType::I8 | ForeignType::U8 | ForeignType::Char | ForeignType::Bool => 1,
+            ForeignType::I16 | ForeignType::U16 => 2,
+            ForeignType::I32 | ForeignType::U32 | ForeignType::Float => 4,
+            ForeignType::I64 | ForeignType::U64 | ForeignType::Double => 8,
+            ForeignType::Int => mem::size_of::<c_int>(),
+            ForeignType::Long => mem::size_of::<c_long>(),
+            ForeignType::SizeT => mem::size_of::<usize>(),
+            ForeignType::String | ForeignType::Ptr => mem::size_of::<*mut c_void>(),
+            ForeignType::Void => 0,
+        }
+    }
+
+    /// Gets the alignment that C gives this type in a struct.
+    pub fn align(&self) -> usize {
+        match *self {
+            ForeignType::I8 | ForeignType::U8 | ForeignType::Char | ForeignType::Bool => 1,
+            ForeignType::I16 | ForeignType::U16 => mem::align_of::<i16>(),
+            ForeignType::I32 | ForeignType::U32 => mem::align_of::<i32>(),
+            ForeignType::I64 | ForeignType::U64 => mem::align_of::<i64>(),
+            ForeignType::Float => mem::align_of::<f32>(),
+            ForeignType::Double => mem::align_of::<f64>(),
+            ForeignType::Int => mem::align_of::<c_int>(),
+            ForeignType::Long => mem::align_of::<c_long>(),
+            ForeignType::SizeT => mem::align_of::<usize>(),
+            ForeignType::String | ForeignType::Ptr => mem::align_of::<*mut c_void>(),
+            ForeignType::Void => 1,
+        }
+    }
+}
+
+/// Rounds an offset up to the next multiple of an alignment.
+fn align_to(offset: usize, align: usize) -> usize {
+    (offset + align - 1) / align * align
+}
+
+/// A field of a foreign struct, along with where it sits in the struct.
+#[derive(PartialEq, Clone, Debug)]
+pub struct StructField {
+    pub name: String,
+    pub field_type: ForeignType,
+    /// How many bytes from the start of the struct this field begins.
+    pub offset: usize,
+}
+
+/// A C struct type that has been declared in a foreign block, laid out the way that C would lay it
+/// out.
+#[derive(PartialEq, Clone, Debug)]
+pub struct StructType {
+    pub name: String,
+    pub fields: Vec<StructField>,
+    /// The size of the struct, including any padding at the end.
+    pub size: usize,
+    pub align: usize,
+}
+
+impl StructType {
+    /// Lays out a struct's fields in order, padding each one to its alignment.
+    pub fn new(name: String, fields: &[(String, ForeignType)]) -> Self {
+        let mut offset = 0;
+        let mut align = 1;
+        let fields = fields
+            .iter()
+            .map(|&(ref name, field_type)| {
+                offset = align_to(offset, field_type.align());
+                align = cmp::max(align, field_type.align());
+                let field = StructField {
+                    name: name.clone(),
+                    field_type,
+                    offset,
+                };
+                offset += field_type.size();
+                field
+            })
+            .collect();
+        StructType {
+            name,
+            fields,
+            size: align_to(offset, align),
+            align,
+        }
+    }
+}
+
+impl<'a> From<&'a ForeignStruct> for StructType {
+    fn from(other: &'a ForeignStruct) -> Self {
+        StructType::new(other.name.clone(), &other.fields)
+    }
+}
+
+/// The different functions that are generated for a foreign struct.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum StructFunKind {
+    /// Pushes a new buffer that's big enough to hold the struct, with every byte set to zero.
+    New,
+    /// Pushes the size of the struct, in bytes.
+    Size,
+    /// Pushes the value of a field, keeping the struct on the stack.
+    Get(usize),
+    /// Replaces the struct on the stack with the value of one of its fields.
+    Take(usize),
+    /// Pops a value and writes it to a field of the struct below it.
+    Set(usize),
+}
+
+/// A function that was generated by a foreign struct declaration. Structs are read and written
+/// through either a pointer or a buffer.
+#[derive(PartialEq, Clone, Debug)]
+pub struct StructFun {
+    pub struct_type: Rc<StructType>,
+    pub kind: StructFunKind,
+}
+
+impl StructFun {
+    pub fn new(struct_type: Rc<StructType>, kind: StructFunKind) -> Self {
+        StructFun { struct_type, kind }
+    }
+
+    /// Gets all of the functions for a struct type, along with their names.
+    ///
+    /// For a struct `timeval` with a field `tv_sec`, these are `timeval`, `timeval-size`,
+    /// `^timeval-tv_sec`, `!timeval-tv_sec`, and `set-timeval-tv_sec`.
+    pub fn all(struct_type: &Rc<StructType>) -> Vec<(String, StructFun)> {
+        let name = &struct_type.name;
+        let mut funs = vec![
+            (name.clone(), StructFun::new(struct_type.clone(), StructFunKind::New)),
+            (
+                format!("{}-size", name),
+                StructFun::new(struct_type.clone(), StructFunKind::Size),
+            ),
+        ];
+        for (index, field) in struct_type.fields.iter().enumerate() {
+            funs.push((
+                format!("^{}-{}", name, field.name),
+                StructFun::new(struct_type.clone(), StructFunKind::Get(index)),
+            ));
+            funs.push((
+                format!("!{}-{}", name, field.name),
+                StructFun::new(struct_type.clone(), StructFunKind::Take(index)),
+            ));
+            funs.push((
+                format!("set-{}-{}", name, field.name),
+                StructFun::new(struct_type.clone(), StructFunKind::Set(index)),
+            ));
+        }
+        funs
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+    use libc;
+    use std::mem;
+
+    fn layout(fields: &[(&str, ForeignType)]) -> StructType {
+        let fields = fields
+            .iter()
+            .map(|&(name, t)| (name.to_string(), t))
+            .collect::<Vec<_>>();
+        StructType::new("test".to_string(), &fields)
+    }
+
+    fn offsets(struct_type: &StructType) -> Vec<usize> {
+        struct_type.fields.iter().map(|f| f.offset).collect()
+    }
+
+    #[test]
+    fn test_struct_layout() {
+        // fields are padded up to their alignment
+        let s = layout(&[("a", ForeignType::Char), ("b", ForeignType::I32)]);
+        assert_eq!(offsets(&s), vec![0, 4]);
+        assert_eq!((s.size, s.align), (8, 4));
+        let s = layout(&[("a", ForeignType::U8), ("b", ForeignType::Double), ("c", ForeignType::I16)]);
+        assert_eq!(offsets(&s), vec![0, 8, 16]);
+        assert_eq!((s.size, s.align), (24, 8));
+        // and so is the end of the struct
+        let s = layout(&[("a", ForeignType::I16), ("b", ForeignType::I8)]);
+        assert_eq!(offsets(&s), vec![0, 2]);
+        assert_eq!((s.size, s.align), (4, 2));
+        let s = layout(&[("a", ForeignType::I8), ("b", ForeignType::I8), ("c", ForeignType::I8)]);
+        assert_eq!((s.size, s.align), (3, 1));
+    }
+
+    #[test]
+    fn test_struct_layout_libc() {
+        // these should line up with the structs that libc declares
+        let s = layout(&[("tv_sec", ForeignType::Long), ("tv_usec", ForeignType::Long)]);
+        assert_eq!(s.size, mem::size_of::<libc::timeval>());
+        assert_eq!(s.align, mem::align_of::<libc::timeval>());
+        let s = layout(&[("tv_sec", ForeignType::Long), ("tv_nsec", ForeignType::Long)]);
+        assert_eq!(s.size, mem::size_of::<libc::timespec>());
+        let s = layout(&[
+            ("rlim_cur", ForeignType::U64),
+            ("rlim_max", ForeignType::U64),
+        ]);
+        assert_eq!(s.size, mem::size_of::<libc::rlimit>());
+        let s = layout(&[
+            ("l_type", ForeignType::I16),
+            ("l_whence", ForeignType::I16),
+            ("l_start", ForeignType::I64),
+            ("l_len", ForeignType::I64),
+            ("l_pid", ForeignType::I32),
+        ]);
+        assert_eq!(s.size, mem::size_of::<libc::flock>());
+        assert_eq!(offsets(&s), vec![0, 2, 8, 16, 24]);
+        // bools are C's `_Bool`, which is what Rust's bool is laid out as too
+        #[repr(C)]
+        struct Flags {
+            a: bool,
+            b: bool,
+            c: libc::c_int,
+            d: bool,
+        }
+        let s = layout(&[
+            ("a", ForeignType::Bool),
+            ("b", ForeignType::Bool),
+            ("c", ForeignType::Int),
+            ("d", ForeignType::Bool),
+        ]);
+        assert_eq!(s.size, mem::size_of::<Flags>());
+        assert_eq!(s.align, mem::align_of::<Flags>());
+        assert_eq!(offsets(&s), vec![0, 1, 4, 8]);
+    }
+}
@@ -4,7 +4,6 @@ use std::collections::BTreeMap;
 use std::rc::Rc;
 
 pub type BCFunTable = BTreeMap<String, BCFun>;
-pub type BCFunRcTable = BTreeMap<String, Rc<BCFun>>;
 
 #[derive(Clone, Debug)]
 pub struct BCUserFun {
@@ -111,7 +110,11 @@ impl From<IRFun> for BCFun {
         match other {
             Fun::UserFun(u) => Fun::UserFun(u.into()),
             Fun::ForeignFun(f) => Fun::ForeignFun(f),
+            Fun::RecordFun(r) => Fun::RecordFun(r),
+            Fun::StructFun(s) => Fun::StructFun(s),
+            Fun::CallbackFun(c) => Fun::CallbackFun(c),
             Fun::BuiltinFun(b) => Fun::BuiltinFun(b),
+            Fun::HostFun(h) => Fun::HostFun(h),
         }
     }
 }
@@ -1,7 +1,11 @@
+pub mod foreign_struct;
 pub mod fun;
+pub mod record;
 pub mod val;
 
+pub use self::foreign_struct::*;
 pub use self::fun::*;
+pub use self::record::*;
 pub use self::val::*;
 
 use prelude::*;
@@ -11,6 +15,7 @@ use std::fmt::{self, Formatter, Display};
 pub enum BCType {
     Push,           // push
     PushL,          // push list
+    PushM,          // push map entry
     Pop,            // pop
     PopN,           // pop N items
     PopDiscard,     // pop, discarding the value
@@ -21,9 +26,30 @@ pub enum BCType {
     SymJmp,         // symbolic jump
     SymJmpZ,        // symbolic jump zero
     Call,           // call
+    TailCall,       // call, reusing the current function's frame
     Ret,            // return
     Label,          // label (for symbolic jumps)
+    IsType,         // check the type of a value
+    Try,            // start a try block
+    SymTry,         // start a try block, with a symbolic catch address
+    EndTry,         // end a try block
+    Spawn,          // create a coroutine
+    Resume,         // run a coroutine until it yields or returns
+    Yield,          // hand a value back to whatever resumed this coroutine
     Nop,            // no-op
+    // native versions of builtin functions
+    Add,            // add
+    Sub,            // subtract
+    Mul,            // multiply
+    Div,            // divide
+    Eq,             // equal
+    Ne,             // not equal
+    Lt,             // less than
+    Gt,             // greater than
+    Le,             // less than or equal
+    Ge,             // greater than or equal
+    Dup,            // duplicate the top of the stack
+    Depth,          // push the size of the stack
 }
 
 impl Display for BCType {
@@ -34,6 +60,7 @@ impl Display for BCType {
             match self {
                 &BCType::Push => "PUSH",
                 &BCType::PushL => "PUSHL",
+                &BCType::PushM => "PUSHM",
                 &BCType::Pop => "POP",
                 &BCType::PopN => "POPN",
                 &BCType::PopDiscard => "POP_DISCARD",
@@ -44,9 +71,29 @@ impl Display for BCType {
                 &BCType::SymJmp => "SYM_JMP",
                 &BCType::SymJmpZ => "SYM_JMPZ",
                 &BCType::Call => "CALL",
+                &BCType::TailCall => "TAIL_CALL",
                 &BCType::Ret => "RET",
                 &BCType::Label => "LABEL",
+                &BCType::IsType => "IS_TYPE",
+                &BCType::Try => "TRY",
+                &BCType::SymTry => "SYM_TRY",
+                &BCType::EndTry => "END_TRY",
+                &BCType::Spawn => "SPAWN",
+                &BCType::Resume => "RESUME",
+                &BCType::Yield => "YIELD",
                 &BCType::Nop => "NOP",
+                &BCType::Add => "ADD",
+                &BCType::Sub => "SUB",
+                &BCType::Mul => "MUL",
+                &BCType::Div => "DIV",
+                &BCType::Eq => "EQ",
+                &BCType::Ne => "NE",
+                &BCType::Lt => "LT",
+                &BCType::Gt => "GT",
+                &BCType::Le => "LE",
+                &BCType::Ge => "GE",
+                &BCType::Dup => "DUP",
+                &BCType::Depth => "DEPTH",
             }
         )
     }
@@ -102,6 +149,16 @@ impl BC {
         }
     }
 
+    pub fn enter_try(tokens: Tokens, val: BCVal) -> BC {
+        assert_matches!(val, BCVal::Address(_));
+        BC {
+            bc_type: BCType::Try,
+            tokens,
+            target: None,
+            val: Some(val),
+        }
+    }
+
     pub fn ret(tokens: Tokens) -> BC {
         BC {
             bc_type: BCType::Ret,
@@ -125,6 +182,7 @@ impl From<IR> for BC {
                                        .expect("BCType::Push expects a value")])),
             },
             IRType::PushL => BCType::PushL,
+            IRType::PushM => BCType::PushM,
             IRType::Pop => match other.val {
                 Some(IRVal::Ident(_)) => BCType::Pop,
                 Some(IRVal::Int(_)) => BCType::PopN,
@@ -140,6 +198,12 @@ impl From<IR> for BC {
             IRType::Ret => BCType::Ret,
             IRType::Bake => panic!("IRType::Bake instructions cannot be converted to any BCType instruction"),
             IRType::Label => BCType::Label,
+            IRType::IsType => BCType::IsType,
+            IRType::Try => BCType::SymTry,
+            IRType::EndTry => BCType::EndTry,
+            IRType::Spawn => BCType::Spawn,
+            IRType::Resume => BCType::Resume,
+            IRType::Yield => BCType::Yield,
             IRType::Nop => BCType::Nop,
         };
         BC {
@@ -0,0 +1,115 @@
+use prelude::*;
+use std::fmt::{self, Formatter, Display};
+use std::rc::Rc;
+
+/// A record type that has been declared with a `record` top-level statement.
+#[derive(PartialEq, Clone, Debug)]
+pub struct RecordType {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+impl RecordType {
+    pub fn new(name: String, fields: Vec<String>) -> Self {
+        RecordType { name, fields }
+    }
+}
+
+impl<'a> From<&'a RecordDef> for RecordType {
+    fn from(other: &'a RecordDef) -> Self {
+        RecordType::new(other.name.clone(), other.fields.clone())
+    }
+}
+
+/// An instance of a record type.
+///
+/// Field values are stored in the same order as the record type's field names.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Record {
+    pub record_type: Rc<RecordType>,
+    pub fields: Vec<BCVal>,
+}
+
+impl Record {
+    pub fn new(record_type: Rc<RecordType>, fields: Vec<BCVal>) -> Self {
+        assert_eq!(record_type.fields.len(), fields.len());
+        Record { record_type, fields }
+    }
+
+    pub fn type_name(&self) -> &str {
+        &self.record_type.name
+    }
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{{{}}}",
+            self.type_name(),
+            self.record_type
+                .fields
+                .iter()
+                .zip(self.fields.iter())
+                .map(|(name, val)| format!("{}:{}", name, val))
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    }
+}
+
+/// The different functions that are generated for a record type.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RecordFunKind {
+    /// Pops all fields off of the stack, pushing a new record. The last field is on top of the
+    /// stack.
+    Construct,
+    /// Pushes the value of a field, keeping the record on the stack.
+    Get(usize),
+    /// Replaces the record on the stack with the value of one of its fields.
+    Take(usize),
+    /// Pops a value and sets a field of the record below it.
+    Set(usize),
+}
+
+/// A function that was generated by a record declaration.
+#[derive(PartialEq, Clone, Debug)]
+pub struct RecordFun {
+    pub record_type: Rc<RecordType>,
+    pub kind: RecordFunKind,
+}
+
+impl RecordFun {
+    pub fn new(record_type: Rc<RecordType>, kind: RecordFunKind) -> Self {
+        RecordFun { record_type, kind }
+    }
+
+    /// Gets all of the functions for a record type, along with their names.
+    ///
+    /// For a record `point` with a field `x`, these are `point`, `^point-x`, `!point-x`, and
+    /// `set-point-x`.
+    pub fn all(record_type: &Rc<RecordType>) -> Vec<(String, RecordFun)> {
+        let name = &record_type.name;
+        let mut funs = vec![
+            (
+                name.clone(),
+                RecordFun::new(record_type.clone(), RecordFunKind::Construct)
+            ),
+        ];
+        for (index, field) in record_type.fields.iter().enumerate() {
+            funs.push((
+                format!("^{}-{}", name, field),
+                RecordFun::new(record_type.clone(), RecordFunKind::Get(index)),
+            ));
+            funs.push((
+                format!("!{}-{}", name, field),
+                RecordFun::new(record_type.clone(), RecordFunKind::Take(index)),
+            ));
+            funs.push((
+                format!("set-{}-{}", name, field),
+                RecordFun::new(record_type.clone(), RecordFunKind::Set(index)),
+            ));
+        }
+        funs
+    }
+}
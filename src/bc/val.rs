@@ -1,17 +1,96 @@
 use prelude::*;
+use std::borrow::Cow;
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt::{self, Formatter, Display};
+use std::rc::Rc;
 
+/// A value that may be used as a map key.
+///
+/// Keys are kept in sorted order, so iterating over a map (and displaying it) doesn't depend on the
+/// order that its entries were inserted in.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug)]
+pub enum MapKey {
+    Bool(bool),
+    Int(i64),
+    Char(char),
+    String(Rc<str>),
+}
+
+impl From<MapKey> for BCVal {
+    fn from(other: MapKey) -> Self {
+        match other {
+            MapKey::Bool(b) => BCVal::Bool(b),
+            MapKey::Int(i) => BCVal::Int(i),
+            MapKey::Char(c) => BCVal::Char(c),
+            MapKey::String(s) => BCVal::String(s),
+        }
+    }
+}
+
+impl Display for MapKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", BCVal::from(self.clone()))
+    }
+}
+
+pub type BCMap = BTreeMap<MapKey, BCVal>;
+
+/// An error that was raised by a running program, which may be handled by a `catch` block.
+#[derive(Clone, Debug)]
+pub struct ErrorVal {
+    pub message: String,
+    /// Where the error was raised, if that's known.
+    pub range: Option<Range>,
+}
+
+impl ErrorVal {
+    pub fn new(message: String, range: Option<Range>) -> Self {
+        ErrorVal { message, range }
+    }
+}
+
+impl PartialEq for ErrorVal {
+    fn eq(&self, other: &Self) -> bool {
+        let same_range = match (&self.range, &other.range) {
+            (&Some(ref r), &Some(ref o)) => r.start == o.start && r.end == o.end,
+            (&None, &None) => true,
+            _ => false,
+        };
+        self.message == other.message && same_range
+    }
+}
+
+/// The names that `match` arms use for all types other than records.
+pub const TYPE_NAMES: &[&str] = &[
+    "int", "float", "char", "string", "bool", "stack", "map", "ref", "error", "coroutine",
+    "handle", "pointer", "buffer", "nil",
+];
+
+/// A bytecode value.
+///
+/// Strings, local stacks, maps and records are reference counted, so copying them around the stack
+/// is cheap. Local stacks, maps and records are copied on write with `Rc::make_mut`, so a change to one copy is never visible
+/// through another.
 #[derive(EnumAsGetters, EnumIsA, PartialEq, Clone, Debug)]
 pub enum BCVal {
     Int(i64),
+    Float(f64),
     Ident(String),
     Char(char),
-    String(String),
+    String(Rc<str>),
     Bool(bool),
-    Stack(Vec<BCVal>),
+    Stack(Rc<Vec<BCVal>>),
+    Map(Rc<BCMap>),
+    Record(Rc<Record>),
+    Error(Rc<ErrorVal>),
+    Coroutine(Coroutine),
+    Handle(Handle),
+    Pointer(usize),
+    Buffer(Buffer),
     PushAll(Vec<BCVal>),
     Address(usize),
+    Ref(usize),
     Nil,
 }
 
@@ -19,29 +98,52 @@ impl BCVal {
     pub fn matches(&self, other: &Self) -> bool {
         match self {
             &BCVal::Int(_) => other.is_int(),
+            &BCVal::Float(_) => other.is_float(),
             &BCVal::Ident(_) => other.is_ident(),
             &BCVal::Char(_) => other.is_char(),
             &BCVal::String(_) => other.is_string(),
             &BCVal::Bool(_) => other.is_bool(),
             &BCVal::Stack(_) => other.is_stack(),
+            &BCVal::Map(_) => other.is_map(),
+            &BCVal::Record(ref r) => match other {
+                &BCVal::Record(ref o) => r.record_type == o.record_type,
+                _ => false,
+            },
+            &BCVal::Error(_) => other.is_error(),
+            &BCVal::Coroutine(_) => other.is_coroutine(),
+            &BCVal::Handle(_) => other.is_handle(),
+            &BCVal::Pointer(_) => other.is_pointer(),
+            &BCVal::Buffer(_) => other.is_buffer(),
             &BCVal::PushAll(_) => other.is_push_all(),
             &BCVal::Address(_) => other.is_address(),
+            &BCVal::Ref(_) => other.is_ref(),
             &BCVal::Nil => other.is_nil(),
         }
     }
 
-    pub fn type_string(&self) -> &'static str {
-        match self {
+    /// Gets the name of this value's type. Records use the name of their record type.
+    pub fn type_string(&self) -> Cow<'static, str> {
+        let s = match self {
             &BCVal::Int(_) => "int",
+            &BCVal::Float(_) => "float",
             &BCVal::Ident(_) => "identifier",
             &BCVal::Char(_) => "char",
             &BCVal::String(_) => "string",
             &BCVal::Bool(_) => "bool",
             &BCVal::Stack(_) => "local stack",
+            &BCVal::Map(_) => "map",
+            &BCVal::Record(ref r) => return Cow::Owned(r.type_name().to_string()),
+            &BCVal::Error(_) => "error",
+            &BCVal::Coroutine(_) => "coroutine",
+            &BCVal::Handle(_) => "handle",
+            &BCVal::Pointer(_) => "pointer",
+            &BCVal::Buffer(_) => "buffer",
             &BCVal::PushAll(_) => "push collection",
             &BCVal::Address(_) => "address",
+            &BCVal::Ref(_) => "ref",
             &BCVal::Nil => "nil",
-        }
+        };
+        Cow::Borrowed(s)
     }
 
     pub fn compare(&self, other: &BCVal) -> Result<Ordering> {
@@ -57,6 +159,9 @@ impl BCVal {
 
         match self {
             &BCVal::Int(i) => Ok(other.as_int().cmp(&i)), 
+            &BCVal::Float(f) => other.as_float().partial_cmp(&f).ok_or_else(|| {
+                "NaN may not be compared with ordinal operators".into()
+            }),
             &BCVal::Char(c) => Ok(other.as_char().cmp(&c)),
             &BCVal::Address(a) => Ok(other.as_address().cmp(&a)),
             _ => Err(
@@ -68,6 +173,81 @@ impl BCVal {
         }
     }
 
+    /// Whether this value has the given type, using the names from `match` arms. Records have the
+    /// type of their record's name.
+    pub fn has_type(&self, name: &str) -> bool {
+        match (self, name) {
+            (&BCVal::Int(_), "int") |
+            (&BCVal::Float(_), "float") |
+            (&BCVal::Char(_), "char") |
+            (&BCVal::String(_), "string") |
+            (&BCVal::Bool(_), "bool") |
+            (&BCVal::Stack(_), "stack") |
+            (&BCVal::Map(_), "map") |
+            (&BCVal::Ref(_), "ref") |
+            (&BCVal::Error(_), "error") |
+            (&BCVal::Coroutine(_), "coroutine") |
+            (&BCVal::Handle(_), "handle") |
+            (&BCVal::Pointer(_), "pointer") |
+            (&BCVal::Buffer(_), "buffer") |
+            (&BCVal::Nil, "nil") => true,
+            (&BCVal::Record(ref r), name) => r.type_name() == name,
+            _ => false,
+        }
+    }
+
+    /// Converts this value to a map key, if it's a type that can be used as one.
+    pub fn to_map_key(&self) -> Result<MapKey> {
+        match self {
+            &BCVal::Bool(b) => Ok(MapKey::Bool(b)),
+            &BCVal::Int(i) => Ok(MapKey::Int(i)),
+            &BCVal::Char(c) => Ok(MapKey::Char(c)),
+            &BCVal::String(ref s) => Ok(MapKey::String(s.clone())),
+            _ => Err(
+                format!(
+                    "map keys must be ints, strings, chars, or bools; got {} instead",
+                    self.type_string()
+                ).into(),
+            ),
+        }
+    }
+
+    /// Whether this value is a ref, or is a local stack, map, or record that contains one.
+    pub fn contains_ref(&self) -> bool {
+        self.contains(&BCVal::is_ref)
+    }
+
+    /// Whether this value is a coroutine, or is a local stack, map, or record that contains one.
+    pub fn contains_coroutine(&self) -> bool {
+        self.contains(&BCVal::is_coroutine)
+    }
+
+    /// Whether this value is a file handle, or is a local stack, map, or record that contains one.
+    pub fn contains_handle(&self) -> bool {
+        self.contains(&BCVal::is_handle)
+    }
+
+    /// Whether this value is a foreign pointer, or is a local stack, map, or record that contains
+    /// one.
+    pub fn contains_pointer(&self) -> bool {
+        self.contains(&BCVal::is_pointer)
+    }
+
+    /// Whether this value is a buffer, or is a local stack, map, or record that contains one.
+    pub fn contains_buffer(&self) -> bool {
+        self.contains(&BCVal::is_buffer)
+    }
+
+    fn contains<F: Fn(&BCVal) -> bool>(&self, pred: &F) -> bool {
+        pred(self) || match self {
+            &BCVal::Stack(ref vals) => vals.iter().any(|v| v.contains(pred)),
+            &BCVal::PushAll(ref vals) => vals.iter().any(|v| v.contains(pred)),
+            &BCVal::Map(ref map) => map.values().any(|v| v.contains(pred)),
+            &BCVal::Record(ref record) => record.fields.iter().any(|v| v.contains(pred)),
+            _ => false,
+        }
+    }
+
     /// Appends the contents of one stack value to another.
     pub fn append(&mut self, other: &mut BCVal) {
         assert!(self.is_push_all() && other.is_push_all());
@@ -85,18 +265,39 @@ impl Display for BCVal {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
             &BCVal::Int(i) => write!(f, "{}", i),
+            // debug formatting keeps the decimal point on whole numbers
+            &BCVal::Float(x) => write!(f, "{:?}", x),
             &BCVal::Ident(ref s) => write!(f, "{}", s),
             &BCVal::Char(c) => write!(f, "{}", c),
             &BCVal::String(ref s) => write!(f, "{}", s),
             &BCVal::Bool(b) => write!(f, "{}", b),
-            &BCVal::Stack(ref v) | &BCVal::PushAll(ref v) => {
+            &BCVal::Stack(_) | &BCVal::PushAll(_) => {
+                let v: &[BCVal] = match self {
+                    &BCVal::Stack(ref v) => v,
+                    &BCVal::PushAll(ref v) => v,
+                    _ => unreachable!(),
+                };
                 write!(
                     f,
                     "[{}]",
                     v.iter().map(BCVal::to_string).collect::<Vec<_>>().join(",")
                 )
             }
+            &BCVal::Map(ref m) => {
+                write!(
+                    f,
+                    "%[{}]",
+                    m.iter().map(|(k, v)| format!("{}:{}", k, v)).collect::<Vec<_>>().join(",")
+                )
+            }
+            &BCVal::Record(ref r) => write!(f, "{}", r),
+            &BCVal::Error(ref e) => write!(f, "{}", e.message),
+            &BCVal::Coroutine(ref c) => write!(f, "coroutine(0x{:X})", c.addr()),
+            &BCVal::Handle(ref h) => write!(f, "{}", h),
+            &BCVal::Pointer(p) => write!(f, "pointer(0x{:X})", p),
+            &BCVal::Buffer(ref b) => write!(f, "{}", b),
             &BCVal::Address(a) => write!(f, "0x{:X}", a),
+            &BCVal::Ref(a) => write!(f, "ref(0x{:X})", a),
             &BCVal::Nil => write!(f, "nil"),
         }
     }
@@ -106,11 +307,24 @@ impl From<IRVal> for BCVal {
     fn from(other: IRVal) -> Self {
         match other {
             IRVal::Int(i) => BCVal::Int(i),
+            IRVal::Float(x) => BCVal::Float(x),
             IRVal::Ident(i) => BCVal::Ident(i),
             IRVal::Char(c) => BCVal::Char(c),
-            IRVal::String(s) => BCVal::String(s),
+            IRVal::String(s) => BCVal::String(s.into()),
             IRVal::Bool(b) => BCVal::Bool(b),
-            IRVal::Stack(s) => BCVal::Stack(s.into_iter().map(IRVal::into).collect()),
+            IRVal::Stack(s) => BCVal::Stack(Rc::new(s.into_iter().map(IRVal::into).collect())),
+            IRVal::Map(m) => BCVal::Map(Rc::new(
+                m.into_iter()
+                    .map(|(k, v)| {
+                        let k = BCVal::from(k)
+                            .to_map_key()
+                            .expect("map literal keys should have been checked by the parser");
+                        (k, v.into())
+                    })
+                    .collect(),
+            )),
+            IRVal::Record(r) => BCVal::Record(r),
+            IRVal::Error(e) => BCVal::Error(e),
             IRVal::Address(a) => BCVal::Address(a),
             IRVal::Nil => BCVal::Nil,
             IRVal::BakeBlock(_) => panic!("IRVal::BakeBlock variants may not be converted to BCVals"),
@@ -12,13 +12,23 @@ fn run_program<P: AsRef<Path>, Q: AsRef<Path>>(
     dump: bool,
     optimize: bool,
     compile_only: bool,
+    check_foreign: bool,
     search_dirs: &[Q],
 ) -> Result<()> {
     let filled_ast = process_source_path(path, search_dirs).chain_err(
         || "Parse error",
     )?;
-    let ir_compiler = CompileIR::new(&filled_ast).builtins(&*BUILTINS);
-    let compiler = CompileBytes::new(ir_compiler.compile()?);
+    let ir_compiler = CompileIR::new(&filled_ast)
+        .builtins(&*BUILTINS)
+        .check_foreign(check_foreign);
+    for warning in ir_compiler.warnings() {
+        warning.print();
+    }
+    let dead_funs = EliminateDeadFuns::new(ir_compiler.compile()?);
+    for warning in dead_funs.warnings(&filled_ast.path) {
+        warning.print();
+    }
+    let compiler = CompileBytes::new(dead_funs.compile()?);
     let fun_table = {
         let fun_table = compiler.compile().chain_err(|| "Compile error")?;
         // run optimizations
@@ -66,6 +76,8 @@ fn main() {
         (about: crate_description!())
         (@arg DUMP: -d --dump "Dumps the bytecode of all user-defined functions")
         (@arg COMPILE_ONLY: -c --compile "Compiles only; does not run")
+        (@arg NO_CHECK_FOREIGN: --("no-check-foreign")
+            "Skips looking up foreign libraries and functions while compiling")
         (@arg OPTIMIZE: -O --optimize +takes_value
             default_value[true]
             possible_values(&["true", "false", "0", "1", "yes", "no"])
@@ -79,12 +91,13 @@ fn main() {
     let dump = matches.is_present("DUMP");
     let optimize = (&["true", "yes", "1"]).contains(&matches.value_of("OPTIMIZE").unwrap());
     let compile_only = matches.is_present("COMPILE_ONLY");
+    let check_foreign = !matches.is_present("NO_CHECK_FOREIGN");
     let search_dirs = match env::var("SBL_PATH") {
         Ok(p) => env::split_paths(&format!(".:{}", p)).collect::<Vec<_>>(),
         _ => vec![],
     };
 
-    if let Err(e) = run_program(path, dump, optimize, compile_only, &search_dirs) {
+    if let Err(e) = run_program(path, dump, optimize, compile_only, check_foreign, &search_dirs) {
         print_error_chain(e);
         process::exit(1);
     }
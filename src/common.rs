@@ -86,6 +86,32 @@ pub fn print_error_chain<T: ChainedError>(err_chain: T) {
     }
 }
 
+/// A non-fatal diagnostic raised while compiling a program.
+#[derive(Clone, Debug)]
+pub struct Warning {
+    pub range: Range,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn new(range: Range, message: String) -> Self {
+        Warning { range, message }
+    }
+
+    /// Prints this warning to stderr, along with the source that it refers to.
+    pub fn print(&self) {
+        eprintln!("warning: {}", self.message);
+        eprintln!();
+        print_range_underline(self.range.clone());
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} (in {})", self.message, self.range)
+    }
+}
+
 /// Prints an underlined range.
 pub fn print_range_underline(range: Range) {
     const MAX_LINES: usize = 4;
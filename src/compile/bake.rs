@@ -39,6 +39,19 @@ impl BakeIRFunTable {
                 vm.inject_user_fun(BCUserFun::new(format!("<bake block at {}>", tokens.range()), compiled, tokens.clone(), locals))?;
                 let state: State = vm.clone()
                     .into();
+                // these values only mean something to the VM that made them
+                let unbakeable: &[(fn(&BCVal) -> bool, &str)] = &[
+                    (BCVal::contains_ref, "ref"),
+                    (BCVal::contains_coroutine, "coroutine"),
+                    (BCVal::contains_handle, "handle"),
+                    (BCVal::contains_pointer, "pointer"),
+                    (BCVal::contains_buffer, "buffer"),
+                ];
+                for &(contains, what) in unbakeable {
+                    if state.stack.iter().any(contains) {
+                        return Err(format!("bake block at {} produced a {}, which may not be baked", tokens.range(), what).into());
+                    }
+                }
                 Ok(state.stack
                     .into_iter()
                     .map(|v| BC::push(tokens.clone(), BCVal::PushAll(vec![v])))
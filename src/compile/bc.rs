@@ -2,11 +2,19 @@ use prelude::*;
 
 pub struct CompileBytes {
     fun_table: IRFunTable,
+    defined: BCFunTable,
 }
 
 impl CompileBytes {
     pub fn new(fun_table: IRFunTable) -> Self {
-        CompileBytes { fun_table }
+        CompileBytes { fun_table, defined: BCFunTable::new() }
+    }
+
+    /// Adds functions that have already been compiled, so that bake blocks can call them. They're
+    /// left out of the compiled function table.
+    pub fn defined(mut self, fun_table: &BCFunTable) -> Self {
+        self.defined = fun_table.clone();
+        self
     }
 }
 
@@ -25,8 +33,21 @@ impl Compile for CompileBytes {
         let bc_funs = bc_funs.into_iter()
             .map(|(k, v)| (k, v.into()))
             .collect::<BCFunTable>();
-        let bake_compile = BakeIRFunTable::new(bake_graph, bake_funs, bc_funs);
-        bake_compile.compile()
+        if self.defined.is_empty() {
+            let bake_compile = BakeIRFunTable::new(bake_graph, bake_funs, bc_funs);
+            return bake_compile.compile();
+        }
+        // the bake VM gets everything, but only the functions that were compiled here come out
+        let mut defined = self.defined;
+        for name in bc_funs.keys().chain(bake_funs.keys()) {
+            defined.remove(name);
+        }
+        let mut all_funs = bc_funs;
+        all_funs.extend(defined.iter().map(|(k, v)| (k.clone(), v.clone())));
+        let bake_compile = BakeIRFunTable::new(bake_graph, bake_funs, all_funs);
+        Ok(bake_compile.compile()?
+            .into_iter()
+            .filter(|&(ref k, _)| !defined.contains_key(k))
+            .collect())
     }
 }
-
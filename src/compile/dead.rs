@@ -0,0 +1,137 @@
+use prelude::*;
+use petgraph::visit::Dfs;
+use std::collections::HashSet;
+
+/// Removes all functions that can't be reached from a set of root functions.
+///
+/// This runs on the IR function table, before any bytecode is generated, so that unused functions
+/// are never compiled, optimized, or loaded by the VM. Foreign functions that are dropped here are
+/// never opened at startup either.
+pub struct EliminateDeadFuns {
+    fun_table: IRFunTable,
+    roots: Vec<String>,
+}
+
+impl EliminateDeadFuns {
+    /// Creates a new dead function eliminator, using `main` as the only root.
+    pub fn new(fun_table: IRFunTable) -> Self {
+        EliminateDeadFuns {
+            fun_table,
+            roots: vec!["main".to_string()],
+        }
+    }
+
+    /// Sets the functions that are considered to be reachable, replacing the default `main`.
+    pub fn roots<S: ToString>(mut self, roots: &[S]) -> Self {
+        self.roots = roots.iter().map(S::to_string).collect();
+        self
+    }
+
+    /// Gets the names of all functions that are reachable from the roots.
+    fn reachable(&self) -> HashSet<String> {
+        let call_graph = build_call_graph(&self.fun_table);
+        let mut reachable = HashSet::new();
+        for root in call_graph.node_indices().filter(|n| self.roots.contains(&call_graph[*n])) {
+            let mut dfs = Dfs::new(&call_graph, root);
+            while let Some(node) = dfs.next(&call_graph) {
+                reachable.insert(call_graph[node].clone());
+            }
+        }
+        reachable
+    }
+
+    /// Gets a warning for each user function defined in the given source file that is never used.
+    pub fn warnings(&self, source_path: &str) -> Vec<Warning> {
+        let reachable = self.reachable();
+        let mut warnings = self.fun_table
+            .values()
+            .filter_map(|f| if let &Fun::UserFun(ref f) = f { Some(f) } else { None })
+            .filter(|f| {
+                !reachable.contains(&f.name) && f.tokens.range().source_path().as_str() == source_path
+            })
+            .map(|f| {
+                Warning::new(f.tokens[0].range(), format!("function `{}` is never used", f.name))
+            })
+            .collect::<Vec<_>>();
+        warnings.sort_by(|a, b| a.range.start.cmp(&b.range.start));
+        warnings
+    }
+}
+
+impl Compile for EliminateDeadFuns {
+    type Out = IRFunTable;
+
+    fn compile(self) -> Result<Self::Out> {
+        let reachable = self.reachable();
+        Ok(self.fun_table
+            .into_iter()
+            .filter(|&(ref name, _)| reachable.contains(name))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+
+    fn compile_ir(text: &str) -> IRFunTable {
+        let tokenizer = Tokenizer::new("test", text);
+        let mut parser = Parser::new(tokenizer);
+        let ast = AST {
+            ast: parser.parse().unwrap(),
+            path: "test".into(),
+        };
+        CompileIR::new(&ast).builtins(&*BUILTINS).compile().unwrap()
+    }
+
+    #[test]
+    fn test_dead_funs() {
+        let fun_table = compile_ir(r#"
+            foreign "libc.so.6" {
+                int puts [ string ]
+                int unlink [ string ]
+            }
+            used { "hello" puts }
+            baked { 2 }
+            unused { unused-too }
+            unused-too { "file" unlink }
+            main { used bake { baked } }
+        "#);
+        let elim = EliminateDeadFuns::new(fun_table);
+        let warnings = elim.warnings("test")
+            .into_iter()
+            .map(|w| w.message)
+            .collect::<Vec<_>>();
+        assert_eq!(warnings, vec![
+            "function `unused` is never used".to_string(),
+            "function `unused-too` is never used".to_string(),
+        ]);
+
+        let fun_table = elim.compile().unwrap();
+        for name in &["main", "used", "baked", "puts"] {
+            assert!(fun_table.contains_key(*name), "expected `{}` to be kept", name);
+        }
+        for name in &["unused", "unused-too", "unlink", "+"] {
+            assert!(!fun_table.contains_key(*name), "expected `{}` to be removed", name);
+        }
+    }
+
+    #[test]
+    fn test_dead_funs_imported() {
+        // functions that come from other files don't get warnings
+        let fun_table = compile_ir("unused { } main { }");
+        assert!(EliminateDeadFuns::new(fun_table).warnings("other.sbl").is_empty());
+    }
+
+    #[test]
+    fn test_dead_funs_roots() {
+        let fun_table = compile_ir("lib-entry { helper } helper { 1 } main { }");
+        let fun_table = EliminateDeadFuns::new(fun_table)
+            .roots(&["lib-entry"])
+            .compile()
+            .unwrap();
+        assert!(fun_table.contains_key("lib-entry"));
+        assert!(fun_table.contains_key("helper"));
+        assert!(!fun_table.contains_key("main"));
+    }
+}
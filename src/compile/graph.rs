@@ -5,6 +5,18 @@ use std::collections::{HashMap, HashSet};
 pub type CallGraph = Graph<String, ()>;
 
 pub fn build_call_graph(fun_table: &IRFunTable) -> CallGraph {
+    /// Utility function that gathers the names of all functions called or spawned in a body,
+    /// including the ones called from inside of bake blocks.
+    fn get_all_calls<'a>(body: &'a [IR], calls: &mut Vec<&'a str>) {
+        for ir in body {
+            match ir.ir_type {
+                IRType::Call | IRType::Spawn => calls.push(ir.val.as_ref().unwrap().as_ident()),
+                IRType::Bake => get_all_calls(ir.val.as_ref().unwrap().as_bake_block(), calls),
+                _ => { }
+            }
+        }
+    }
+
     // build all of the funtable nodes
     let mut fun_graph = Graph::new();
     let mut node_table = HashMap::new();
@@ -17,17 +29,19 @@ pub fn build_call_graph(fun_table: &IRFunTable) -> CallGraph {
     for (fname, node) in &node_table {
         let fun = fun_table.get(fname).unwrap();
         if let &Fun::UserFun(ref fun) = fun {
-            for ir in &fun.body {
-                if ir.ir_type == IRType::Call {
-                    // find the node and hook it up
-                    let callee_name = ir.val.as_ref()
-                        .unwrap()
-                        .as_string();
-                    let callee = node_table.get(callee_name)
-                        .unwrap();
+            let mut calls = vec![];
+            get_all_calls(&fun.body, &mut calls);
+            for callee_name in calls {
+                // find the node and hook it up; builtins that were never added to the table don't
+                // have a node
+                if let Some(callee) = node_table.get(callee_name) {
                     fun_graph.add_edge(*node, *callee, ());
                 }
             }
+        } else if let &Fun::CallbackFun(ref callback) = fun {
+            // taking a pointer to a callback means that C may call its function at any time
+            let callee = node_table[&callback.name];
+            fun_graph.add_edge(*node, callee, ());
         }
     }
     fun_graph
@@ -39,7 +53,7 @@ pub fn build_bake_call_graph(fun_table: &IRFunTable) -> Result<CallGraph> {
         for ref ir in body {
 
             match &ir.ir_type {
-                &IRType::Call => {
+                &IRType::Call | &IRType::Spawn => {
                     let name = ir.val
                         .as_ref()
                         .unwrap()
@@ -1,5 +1,6 @@
 use prelude::*;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
 
 /*
  * IR compiler
@@ -9,9 +10,68 @@ use std::collections::BTreeMap;
 /// gathered, and then filled in.
 type BoringTable = BTreeMap<String, Option<IRFun>>;
 
+/// Identifiers that are compiled into coroutine instructions rather than function calls.
+const COROUTINE_OPS: &[&str] = &["resume", "yield"];
+
+/// Makes sure that a popped item doesn't name a local after a coroutine instruction, which would
+/// otherwise never be loaded.
+fn check_local_name(item: &Item) -> Result<()> {
+    match item.item_type {
+        ItemType::Ident(ref name) if COROUTINE_OPS.contains(&name.as_str()) => Err(
+            format!(
+                "`{}` is reserved for coroutines, and may not be used as a local name",
+                name
+            ).into(),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Calls the given function with every statement in a block, including the statements in nested
+/// blocks.
+fn walk_stmts<'a, F: FnMut(&'a Stmt)>(block: &'a Block, f: &mut F) {
+    for stmt in &block.block {
+        f(stmt);
+        match *stmt {
+            Stmt::Stack(_) => {}
+            Stmt::Br(ref br) => {
+                walk_stmts(&br.block, f);
+                for elbr in &br.elbr_stmts {
+                    walk_stmts(&elbr.block, f);
+                }
+                if let Some(ref el) = br.el_stmt {
+                    walk_stmts(&el.block, f);
+                }
+            }
+            Stmt::Loop(ref lp) => walk_stmts(&lp.block, f),
+            Stmt::Bake(ref bake) => walk_stmts(&bake.block, f),
+            Stmt::Try(ref t) => {
+                walk_stmts(&t.block, f);
+                walk_stmts(&t.catch_stmt.block, f);
+            }
+            Stmt::Match(ref m) => {
+                for arm in &m.arms {
+                    walk_stmts(&arm.block, f);
+                }
+                if let Some(ref el) = m.el_stmt {
+                    walk_stmts(&el.block, f);
+                }
+            }
+            Stmt::Spawn(ref spawn) => {
+                if let SpawnTarget::Block(ref block) = spawn.target {
+                    walk_stmts(block, f);
+                }
+            }
+        }
+    }
+}
+
 pub struct CompileIR<'ast> {
     ast: &'ast AST,
     fun_table: BoringTable,
+    /// Functions that were compiled before, and aren't compiled again.
+    defined: BTreeSet<String>,
+    check_foreign: bool,
 }
 
 impl<'ast> Compile for CompileIR<'ast> {
@@ -21,39 +81,27 @@ impl<'ast> Compile for CompileIR<'ast> {
     fn compile(mut self) -> Result<Self::Out> {
         // set up the function table
         self.fill_boring_table()?;
+        if self.check_foreign {
+            check_foreign_funs(self.foreign_funs())?;
+        }
 
         // fill the entries for the function table
-        for top in &self.ast.ast {
+        let ast = self.ast;
+        for top in &ast.ast {
             if let &TopLevel::BCFunDef(ref fun) = top {
-                let fun_name = fun.name.clone();
-                {
-                    let fun_entry = self.fun_table.get(&fun_name).expect(
-                        "got function with name that was not filled out",
-                    );
-                    assert!(
-                        fun_entry.is_none(),
-                        "found duplicate function that was not caught in fill_boring_table(): `{}`",
-                        fun_name
-                    );
-                }
-                let mut block = {
-                    let mut label_offset = 0;
-                    let block_compiler = CompileIRBlock::new(&self.fun_table, &fun.block, &mut label_offset);
-                    let res = block_compiler.compile()?;
-                    res
-                };
-                block.push(IR::ret(fun.tokens().into()));
-                let built_fun = IRUserFun::new(fun_name, block, fun.tokens().into());
-
-                self.fun_table.insert(
-                    built_fun.name.clone(),
-                    Some(Fun::UserFun(built_fun)),
-                );
+                self.compile_fun(fun.name.clone(), &fun.block, fun.tokens())?;
+            }
+        }
+        for spawn in self.spawn_blocks() {
+            if let SpawnTarget::Block(ref block) = spawn.target {
+                self.compile_fun(spawn.fun_name(), block, spawn.tokens())?;
             }
         }
 
+        let defined = self.defined;
         Ok(self.fun_table
             .into_iter()
+            .filter(|&(ref k, _)| !defined.contains(k))
             .map(|(k, v)| (k, v.unwrap()))
             .collect())
     }
@@ -64,31 +112,171 @@ impl<'ast> CompileIR<'ast> {
         CompileIR {
             ast,
             fun_table: BoringTable::new(),
+            defined: BTreeSet::new(),
+            check_foreign: false,
         }
     }
 
+    /// Sets whether the libraries and symbols of foreign functions are looked up while compiling.
+    /// Otherwise, a missing one isn't found until the program is run. Foreign structs and
+    /// callbacks aren't looked up in their libraries at all, so only functions are checked.
+    pub fn check_foreign(mut self, check_foreign: bool) -> Self {
+        self.check_foreign = check_foreign;
+        self
+    }
+
     /// Appends a set of builtin functions to the funtable. Overwrites any
     /// functions that have been defined already.
-    pub fn builtins(mut self, builtins: &'static BTreeMap<&'static str, BuiltinFun>) -> Self {
-        for (k, v) in builtins.into_iter().map(|(k, v)| {
-            (k.to_string(), Some(Fun::BuiltinFun(v)))
-        })
-        {
-            self.fun_table.insert(k, v);
+    ///
+    /// This takes either the standard `BUILTINS`, or a `Builtins` set that may also have closures
+    /// from the host program.
+    pub fn builtins<B: Into<Builtins>>(mut self, builtins: B) -> Self {
+        for (k, v) in builtins.into().funs() {
+            self.fun_table.insert(k, Some(v));
+        }
+        self
+    }
+
+    /// Adds functions that have already been compiled, so that the code being compiled can use
+    /// them. They're left out of the compiled function table, and may not be defined again.
+    pub fn defined(mut self, fun_table: &IRFunTable) -> Self {
+        for (k, v) in fun_table {
+            self.fun_table.insert(k.clone(), Some(v.clone()));
+            self.defined.insert(k.clone());
         }
         self
     }
 
+    /// Gets a warning for each `match` statement in the main source file that has no `el` arm and
+    /// doesn't cover every type.
+    pub fn warnings(&self) -> Vec<Warning> {
+        let mut all_types = TYPE_NAMES.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let mut stmts = vec![];
+        for top in &self.ast.ast {
+            match *top {
+                TopLevel::RecordDef(ref record) => all_types.push(record.name.clone()),
+                TopLevel::BCFunDef(ref fun) => {
+                    if fun.range().source_path().as_str() == self.ast.path {
+                        walk_stmts(&fun.block, &mut |stmt| if let Stmt::Match(ref m) = *stmt {
+                            stmts.push(m);
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        stmts
+            .into_iter()
+            .filter(|m| m.el_stmt.is_none())
+            .filter_map(|m| {
+                let uncovered = all_types
+                    .iter()
+                    .filter(|t| !m.arms.iter().any(|arm| arm.types.contains(t)))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if uncovered.is_empty() {
+                    None
+                } else {
+                    Some(Warning::new(
+                        m.tokens[0].range(),
+                        format!(
+                            "match has no `el` arm, and does not cover these types: {}",
+                            uncovered.join(", ")
+                        ),
+                    ))
+                }
+            })
+            .collect()
+    }
+
+    /// Compiles a block into a user function, and puts it in the function table.
+    fn compile_fun(&mut self, name: String, block: &Block, tokens: &[RcToken]) -> Result<()> {
+        {
+            let fun_entry = self.fun_table.get(&name).expect(
+                "got function with name that was not filled out",
+            );
+            assert!(
+                fun_entry.is_none(),
+                "found duplicate function that was not caught in fill_boring_table(): `{}`",
+                name
+            );
+        }
+        let mut body = {
+            let mut label_offset = 0;
+            let block_compiler = CompileIRBlock::new(&self.fun_table, block, &mut label_offset);
+            block_compiler.compile()?
+        };
+        body.push(IR::ret(tokens.into()));
+        let built_fun = IRUserFun::new(name, body, tokens.into());
+
+        self.fun_table.insert(
+            built_fun.name.clone(),
+            Some(Fun::UserFun(built_fun)),
+        );
+        Ok(())
+    }
+
+    /// Gets all of the foreign functions that have been declared.
+    fn foreign_funs(&self) -> Vec<&'ast ForeignFun> {
+        self.ast
+            .ast
+            .iter()
+            .filter_map(|top| if let TopLevel::Foreign(ref foreign) = *top {
+                Some(foreign.functions.iter())
+            } else {
+                None
+            })
+            .flat_map(|funs| funs)
+            .collect()
+    }
+
+    /// Gets all of the `spawn` statements that run a block, which are compiled into functions of
+    /// their own.
+    fn spawn_blocks(&self) -> Vec<&'ast SpawnStmt> {
+        let mut spawns = vec![];
+        for top in &self.ast.ast {
+            if let TopLevel::BCFunDef(ref fun) = *top {
+                walk_stmts(&fun.block, &mut |stmt| if let Stmt::Spawn(ref spawn) = *stmt {
+                    if spawn.target.is_block() {
+                        spawns.push(spawn);
+                    }
+                });
+            }
+        }
+        spawns
+    }
+
+    /// Puts a function in the function table while it's being filled. Anything that was compiled
+    /// before under the same name is replaced, and compiled again.
+    fn fill(&mut self, name: String, fun: Option<IRFun>) {
+        self.defined.remove(&name);
+        self.fun_table.insert(name, fun);
+    }
+
     /// Fills the function table with null values of functions that have yet to be compiled.
     fn fill_boring_table(&mut self) -> Result<()> {
         /// Utility function that checks if a function has already been defined in the
         /// table.
         fn check_defined(name: &str, fun_table: &BoringTable) -> Result<()> {
+            if COROUTINE_OPS.contains(&name) {
+                return Err(
+                    format!(
+                        "`{}` is reserved for coroutines, and may not be used as a function name",
+                        name
+                    ).into(),
+                );
+            }
             if let Some(other) = fun_table.get(name) {
                 match *other {
                     Some(Fun::ForeignFun(_)) |
+                    Some(Fun::RecordFun(_)) |
+                    Some(Fun::StructFun(_)) |
+                    Some(Fun::CallbackFun(_)) |
+                    Some(Fun::UserFun(_)) |
                     None => {
-                        // None means it's a function we inserted earlier
+                        // None means it's a function we inserted earlier, and user functions were
+                        // defined by code that was compiled before
                         return Err(
                             format!("function `{}` has already been defined", name).into(),
                         ) as Result<_>;
@@ -105,7 +293,7 @@ impl<'ast> CompileIR<'ast> {
                     check_defined(&fun.name, &self.fun_table).chain_err(
                         || fun.range(),
                     )?;
-                    self.fun_table.insert(fun.name.clone(), None);
+                    self.fill(fun.name.clone(), None);
                 }
                 &TopLevel::Foreign(ref foreign) => {
                     for frn_fun in &foreign.functions {
@@ -114,16 +302,70 @@ impl<'ast> CompileIR<'ast> {
                                 frn_fun.range()
                             },
                         )?;
-                        self.fun_table.insert(
+                        self.fill(
                             frn_fun.name.clone(),
                             Some(Fun::ForeignFun(frn_fun.clone())),
                         );
                     }
+                    for frn_struct in &foreign.structs {
+                        let struct_type = Rc::new(StructType::from(frn_struct));
+                        for (name, struct_fun) in StructFun::all(&struct_type) {
+                            check_defined(&name, &self.fun_table).chain_err(
+                                || frn_struct.range(),
+                            )?;
+                            self.fill(name, Some(Fun::StructFun(struct_fun)));
+                        }
+                    }
+                    for callback in &foreign.callbacks {
+                        check_defined(&callback.ptr_name(), &self.fun_table).chain_err(
+                            || callback.range(),
+                        )?;
+                        self.fill(
+                            callback.ptr_name(),
+                            Some(Fun::CallbackFun(callback.clone())),
+                        );
+                    }
+                }
+                &TopLevel::RecordDef(ref record) => {
+                    if TYPE_NAMES.contains(&record.name.as_str()) {
+                        return Err(Error::from(
+                            format!("record `{}` has the same name as a builtin type", record.name),
+                        )).chain_err(|| record.range());
+                    }
+                    let record_type = Rc::new(RecordType::from(record));
+                    for (name, record_fun) in RecordFun::all(&record_type) {
+                        check_defined(&name, &self.fun_table).chain_err(
+                            || record.range(),
+                        )?;
+                        self.fill(name, Some(Fun::RecordFun(record_fun)));
+                    }
                 }
 
                 _ => panic!("got unprocessed top-level: {:#?}", top),
             }
         }
+        for spawn in self.spawn_blocks() {
+            self.fill(spawn.fun_name(), None);
+        }
+        // callbacks may name functions that are defined after them, so they're checked once
+        // everything else is in the table
+        for top in &self.ast.ast {
+            if let TopLevel::Foreign(ref foreign) = *top {
+                for callback in &foreign.callbacks {
+                    let result: Result<()> = match self.fun_table.get(&callback.name) {
+                        Some(&None) | Some(&Some(Fun::UserFun(_))) => Ok(()),
+                        Some(_) => Err(
+                            format!("callback `{}` is not a user function", callback.name).into(),
+                        ),
+                        None => Err(
+                            format!("callback refers to unknown function `{}`", callback.name)
+                                .into(),
+                        ),
+                    };
+                    result.chain_err(|| callback.range())?;
+                }
+            }
+        }
         Ok(())
     }
 }
@@ -148,7 +390,10 @@ impl<'ft, 'b, 'l> CompileIRBlock<'ft, 'b, 'l> {
         for action in actions {
             match *action {
                 StackAction::Push(ref i) => body.append(&mut self.compile_item_push(i)?),
-                StackAction::Pop(_, ref i) => body.push(IR::pop(action.tokens().into(), i.into())),
+                StackAction::Pop(_, ref i) => {
+                    check_local_name(i).chain_err(|| action.range())?;
+                    body.push(IR::pop(action.tokens().into(), i.into()))
+                }
             }
         }
         Ok(body)
@@ -157,6 +402,13 @@ impl<'ft, 'b, 'l> CompileIRBlock<'ft, 'b, 'l> {
     fn compile_item_push(&self, item: &Item) -> Result<IRBody> {
         match item.item_type {
             ItemType::Stack(_) => self.compile_local_stack(item),
+            ItemType::Map(_) => self.compile_map(item),
+            ItemType::Ident(ref ident) if ident == "resume" => {
+                Ok(vec![IR::resume(item.tokens().into())])
+            }
+            ItemType::Ident(ref ident) if ident == "yield" => {
+                Ok(vec![IR::yield_(item.tokens().into())])
+            }
             ItemType::Ident(ref ident) => {
                 if self.fun_table.contains_key(ident) || BUILTINS.contains_key(ident.as_str()) {
                     Ok(vec![IR::call(item.tokens().into(), item.into())])
@@ -187,6 +439,52 @@ impl<'ft, 'b, 'l> CompileIRBlock<'ft, 'b, 'l> {
             Ok(body)
         }
     }
+
+    /// Makes sure that every type in a match statement exists, and is only matched once.
+    fn check_match_types(&self, stmt: &MatchStmt) -> Result<()> {
+        let mut matched: Vec<&str> = vec![];
+        for arm in &stmt.arms {
+            for t in &arm.types {
+                let is_record = match self.fun_table.get(t) {
+                    Some(&Some(Fun::RecordFun(ref f))) => f.kind == RecordFunKind::Construct,
+                    _ => false,
+                };
+                if !is_record && !TYPE_NAMES.contains(&t.as_str()) {
+                    return Err(Error::from(format!("unknown type `{}` in match arm", t)))
+                        .chain_err(|| arm.range());
+                }
+                if matched.contains(&t.as_str()) {
+                    return Err(Error::from(
+                        format!("type `{}` is already matched by an earlier arm", t),
+                    )).chain_err(|| arm.range());
+                }
+                matched.push(t);
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_map(&self, item: &Item) -> Result<IRBody> {
+        let pairs = if let ItemType::Map(ref pairs) = item.item_type {
+            pairs
+        } else {
+            unreachable!()
+        };
+        // const maps can just be pushed themselves
+        if item.is_const() {
+            Ok(vec![IR::push(item.tokens().into(), item.into())])
+        } else {
+            let mut body = vec![IR::push(item.tokens().into(), IRVal::Map(vec![]))];
+            for &(ref key, ref val) in pairs {
+                body.append(&mut self.compile_item_push(key)?);
+                body.append(&mut self.compile_item_push(val)?);
+                let mut tokens: Tokens = key.tokens().into();
+                tokens.extend_from_slice(val.tokens());
+                body.push(IR::pushm(tokens));
+            }
+            Ok(body)
+        }
+    }
 }
 
 impl<'ft, 'b, 'l> Compile for CompileIRBlock<'ft, 'b, 'l> {
@@ -366,6 +664,96 @@ impl<'ft, 'b, 'l> Compile for CompileIRBlock<'ft, 'b, 'l> {
                     debug_assert!(!body.iter().skip(start).any(|i| i.ir_type == IRType::Nop),
                         "Found NOPs after building LOOP statement! This is a compiler bug!");
                 }
+                Stmt::Match(ref m) => {
+                    //
+                    // Match statements are structured as such:
+                    //      pop v
+                    //      load v
+                    //      is_type [ arm1 types ]
+                    //      jmpz a
+                    //      ; arm1 body
+                    //      jmp c
+                    // a:
+                    //      load v
+                    //      is_type [ arm2 types ]
+                    //      jmpz b
+                    //      ; arm2 body
+                    //      jmp c
+                    // b:
+                    //      ; el body
+                    // c:
+                    //
+                    self.check_match_types(m)?;
+                    let binding = IRVal::Ident(m.binding.clone());
+                    body.push(IR::pop(m.tokens().into(), binding.clone()));
+                    let exit_label = IRVal::Int(*self.label_offset as i64);
+                    *self.label_offset += 1;
+                    for arm in &m.arms {
+                        let next_label = IRVal::Int(*self.label_offset as i64);
+                        *self.label_offset += 1;
+                        let types = arm.types.iter().cloned().map(IRVal::String).collect();
+                        body.push(IR::load(arm.tokens().into(), binding.clone()));
+                        body.push(IR::is_type(arm.tokens().into(), IRVal::Stack(types)));
+                        body.push(IR::jmpz(arm.tokens().into(), next_label.clone()));
+                        {
+                            let block_compiler = CompileIRBlock::new(self.fun_table, &arm.block, self.label_offset);
+                            body.append(&mut block_compiler.compile()?);
+                        }
+                        body.push(IR::jmp(arm.tokens().into(), exit_label.clone()));
+                        body.push(IR::label(arm.tokens().into(), next_label));
+                    }
+                    if let Some(ref el) = m.el_stmt {
+                        let block_compiler = CompileIRBlock::new(self.fun_table, &el.block, self.label_offset);
+                        body.append(&mut block_compiler.compile()?);
+                    }
+                    body.push(IR::label(m.tokens().into(), exit_label));
+                }
+                Stmt::Try(ref t) => {
+                    //
+                    // Try statements are structured as such:
+                    //      try a
+                    //      ; try body
+                    //      end_try
+                    //      jmp b
+                    // a:
+                    //      ; catch body
+                    // b:
+                    //
+                    let catch_label = IRVal::Int(*self.label_offset as i64);
+                    *self.label_offset += 1;
+                    let exit_label = IRVal::Int(*self.label_offset as i64);
+                    *self.label_offset += 1;
+                    body.push(IR::enter_try(t.tokens().into(), catch_label.clone()));
+                    {
+                        let block_compiler = CompileIRBlock::new(self.fun_table, &t.block, self.label_offset);
+                        body.append(&mut block_compiler.compile()?);
+                    }
+                    body.push(IR::end_try(t.tokens().into()));
+                    body.push(IR::jmp(t.tokens().into(), exit_label.clone()));
+                    body.push(IR::label(t.catch_stmt.tokens().into(), catch_label));
+                    {
+                        let block_compiler = CompileIRBlock::new(self.fun_table, &t.catch_stmt.block, self.label_offset);
+                        body.append(&mut block_compiler.compile()?);
+                    }
+                    body.push(IR::label(t.tokens().into(), exit_label));
+                }
+                Stmt::Spawn(ref spawn) => {
+                    let name = spawn.fun_name();
+                    match self.fun_table.get(&name) {
+                        // user functions that haven't been compiled yet don't have an entry
+                        Some(&Some(Fun::UserFun(_))) | Some(&None) => {}
+                        Some(_) => {
+                            return Err(Error::from(
+                                format!("attempted to spawn `{}`, which is not a user function", name),
+                            )).chain_err(|| spawn.range())
+                        }
+                        None => {
+                            return Err(Error::from(format!("attempted to spawn unknown function `{}`", name)))
+                                .chain_err(|| spawn.range())
+                        }
+                    }
+                    body.push(IR::spawn(spawn.tokens().into(), IRVal::Ident(name)));
+                }
                 Stmt::Bake(ref block) => {
                     //
                     // Bake blocks are special. They get executed at the time that bytecode is
@@ -0,0 +1,191 @@
+use prelude::*;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A function that has been linked into a `LinkedFunTable`.
+pub type LinkedFun = Fun<Rc<BCUserFun>>;
+
+/// A function table where every function has been given an index, and calls in user function
+/// bodies have been rewritten to use those indices instead of names.
+///
+/// Calls to functions that aren't in the table at link time (e.g. functions that get added while
+/// baking) are left as names, and have to be looked up when they're called.
+#[derive(Clone, Debug)]
+pub struct LinkedFunTable {
+    funs: Vec<LinkedFun>,
+    names: Vec<String>,
+    indices: HashMap<String, usize>,
+}
+
+impl LinkedFunTable {
+    /// Gets the function at the given index.
+    pub fn get(&self, index: usize) -> &LinkedFun {
+        &self.funs[index]
+    }
+
+    /// Gets the name of the function at the given index.
+    pub fn name(&self, index: usize) -> &str {
+        &self.names[index]
+    }
+
+    /// Gets the index of the function with the given name.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.indices.get(name).cloned()
+    }
+
+    /// Gets all functions in the table, in index order.
+    pub fn funs(&self) -> &[LinkedFun] {
+        &self.funs
+    }
+
+    /// Links a function and adds it to this table, returning its index. If a function with the
+    /// same name is already in the table, it's replaced and keeps its index.
+    pub fn insert(&mut self, name: String, fun: BCFun) -> usize {
+        let fun = self.link_fun(fun);
+        if let Some(index) = self.index_of(&name) {
+            self.funs[index] = fun;
+            index
+        } else {
+            let index = self.funs.len();
+            self.funs.push(fun);
+            self.indices.insert(name.clone(), index);
+            self.names.push(name);
+            index
+        }
+    }
+
+    /// Links all of the functions in a function table and adds them to this table. Every function
+    /// gets an index before any of them are linked, so calls between them are all linked. Functions
+    /// that are already in this table are replaced and keep their indices.
+    pub fn extend(&mut self, fun_table: BCFunTable) {
+        for name in fun_table.keys() {
+            if self.index_of(name).is_none() {
+                let index = self.names.len();
+                self.indices.insert(name.clone(), index);
+                self.names.push(name.clone());
+            }
+        }
+        // new functions get their indices in the same order that they're pushed here
+        for (name, fun) in fun_table {
+            let index = self.indices[&name];
+            let fun = self.link_fun(fun);
+            if index < self.funs.len() {
+                self.funs[index] = fun;
+            } else {
+                self.funs.push(fun);
+            }
+        }
+    }
+
+    /// Rewrites all calls and spawns in the given body to use function indices.
+    pub fn link_body(&self, body: &mut BCBody) {
+        for bc in body.iter_mut().filter(|bc| {
+            bc.bc_type == BCType::Call || bc.bc_type == BCType::TailCall ||
+                bc.bc_type == BCType::Spawn
+        })
+        {
+            let index = match bc.val {
+                Some(BCVal::Ident(ref name)) => self.index_of(name),
+                _ => None,
+            };
+            if let Some(index) = index {
+                bc.val = Some(BCVal::Address(index));
+            }
+        }
+    }
+
+    fn link_fun(&self, fun: BCFun) -> LinkedFun {
+        match fun {
+            Fun::UserFun(mut fun) => {
+                self.link_body(&mut fun.body);
+                Fun::UserFun(Rc::new(fun))
+            }
+            Fun::ForeignFun(fun) => Fun::ForeignFun(fun),
+            Fun::RecordFun(fun) => Fun::RecordFun(fun),
+            Fun::StructFun(fun) => Fun::StructFun(fun),
+            Fun::CallbackFun(fun) => Fun::CallbackFun(fun),
+            Fun::BuiltinFun(fun) => Fun::BuiltinFun(fun),
+            Fun::HostFun(fun) => Fun::HostFun(fun),
+        }
+    }
+}
+
+impl From<BCFunTable> for LinkedFunTable {
+    fn from(other: BCFunTable) -> Self {
+        let mut linked = LinkedFunTable {
+            funs: Vec::with_capacity(other.len()),
+            names: Vec::with_capacity(other.len()),
+            indices: HashMap::new(),
+        };
+        linked.extend(other);
+        linked
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+
+    fn compile_bc(text: &str) -> BCFunTable {
+        let tokenizer = Tokenizer::new("test", text);
+        let mut parser = Parser::new(tokenizer);
+        let ast = AST {
+            ast: parser.parse().unwrap(),
+            path: "test".into(),
+        };
+        let ir = CompileIR::new(&ast).builtins(&*BUILTINS).compile().unwrap();
+        CompileBytes::new(ir).compile().unwrap()
+    }
+
+    #[test]
+    fn test_link() {
+        let mut fun_table = compile_bc("foo { 1 } bar { 2 } main { foo bar }");
+        fun_table.remove("bar");
+        let mut linked = LinkedFunTable::from(fun_table);
+        let foo = linked.index_of("foo").unwrap();
+        assert_eq!(linked.name(foo), "foo");
+        let main = linked.index_of("main").unwrap();
+        {
+            let calls = linked.get(main)
+                .as_user_fun()
+                .body
+                .iter()
+                .filter(|bc| bc.bc_type == BCType::Call)
+                .map(|bc| bc.val.clone().unwrap())
+                .collect::<Vec<_>>();
+            // `bar` isn't defined yet, so it stays a name
+            assert_eq!(calls, vec![BCVal::Address(foo), BCVal::Ident("bar".to_string())]);
+        }
+
+        // replacing a function keeps its index
+        let foo_fun = compile_bc("foo { 2 }").remove("foo").unwrap();
+        assert_eq!(linked.insert("foo".to_string(), foo_fun), foo);
+        let bar_fun = compile_bc("bar { 3 }").remove("bar").unwrap();
+        let bar = linked.insert("bar".to_string(), bar_fun);
+        assert_eq!(linked.name(bar), "bar");
+        assert_eq!(linked.index_of("bar"), Some(bar));
+    }
+
+    #[test]
+    fn test_link_extend() {
+        let mut linked = LinkedFunTable::from(compile_bc("foo { 1 } main { foo }"));
+        let foo = linked.index_of("foo").unwrap();
+        let len = linked.funs().len();
+        // `abc` sorts before `zed`, but the call to it is still linked
+        linked.extend(compile_bc("abc { foo zed } foo { 2 } zed { 3 }"));
+        assert_eq!(linked.index_of("foo"), Some(foo));
+        let zed = linked.index_of("zed").unwrap();
+        let abc = linked.index_of("abc").unwrap();
+        assert_eq!(linked.funs().len(), len + 2);
+        assert_eq!(linked.name(zed), "zed");
+        assert_eq!(linked.name(abc), "abc");
+        let calls = linked.get(abc)
+            .as_user_fun()
+            .body
+            .iter()
+            .filter(|bc| bc.bc_type == BCType::Call || bc.bc_type == BCType::TailCall)
+            .map(|bc| bc.val.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(calls, vec![BCVal::Address(foo), BCVal::Address(zed)]);
+    }
+}
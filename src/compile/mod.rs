@@ -2,6 +2,8 @@ pub mod bc;
 pub mod ir;
 pub mod bake;
 pub mod graph;
+pub mod dead;
+pub mod link;
 pub mod optimize;
 
 use errors::*;
@@ -17,4 +19,6 @@ pub use self::bc::*;
 pub use self::ir::*;
 pub use self::bake::*;
 pub use self::graph::*;
+pub use self::dead::*;
+pub use self::link::*;
 pub use self::optimize::*;
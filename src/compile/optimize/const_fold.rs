@@ -0,0 +1,123 @@
+use prelude::*;
+use std::collections::BTreeMap;
+use std::mem;
+
+/// Builtin functions that have no side effects, along with how many items they take off of the
+/// stack.
+const PURE_BUILTINS: &[(&str, usize)] = &[
+    ("+", 2),
+    ("-", 2),
+    ("*", 2),
+    ("/", 2),
+    ("|", 2),
+    ("==", 2),
+    ("!=", 2),
+    ("<", 2),
+    (">", 2),
+    ("<=", 2),
+    (">=", 2),
+];
+
+/// A table of pure builtins, mapped to their arity and the function to evaluate them with.
+pub type PureBuiltins = BTreeMap<String, (usize, &'static BuiltinFun)>;
+
+/// Gets all of the pure builtins that are defined in the given function table. Names that have
+/// been overridden by a user or foreign function are left out.
+pub fn pure_builtins(fun_table: &BCFunTable) -> PureBuiltins {
+    PURE_BUILTINS
+        .iter()
+        .filter_map(|&(name, arity)| match fun_table.get(name) {
+            Some(&Fun::BuiltinFun(fun)) => Some((name.to_string(), (arity, fun))),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Gets the value of an instruction if it pushes exactly one value.
+fn const_push(bc: &BC) -> Option<&BCVal> {
+    match bc.val {
+        Some(BCVal::PushAll(ref vals)) if bc.bc_type == BCType::Push && vals.len() == 1 => {
+            Some(&vals[0])
+        }
+        _ => None,
+    }
+}
+
+/// Evaluates a pure builtin, using the constant pushes at the end of the given body as its
+/// arguments. If the arguments aren't all constant, or the builtin fails, `None` is returned.
+fn fold_call(body: &[BC], arity: usize, fun: &BuiltinFun) -> Option<Vec<BCVal>> {
+    if body.len() < arity {
+        return None;
+    }
+    let args = body[body.len() - arity..]
+        .iter()
+        .map(const_push)
+        .collect::<Option<Vec<_>>>()?;
+    let mut state = State::new();
+    for arg in args {
+        state.push(arg.clone());
+    }
+    match fun(&mut state) {
+        Ok(_) => Some(state.stack),
+        Err(_) => None,
+    }
+}
+
+impl BCUserFun {
+    /// Evaluates calls to pure builtins whose arguments are all constant pushes, replacing them
+    /// with a push of the result. Conditional jumps on a constant are replaced with either an
+    /// unconditional jump, or nothing at all.
+    ///
+    /// Calls that fail (e.g. dividing by zero) are left alone so that the error still happens at
+    /// runtime.
+    ///
+    /// This is used exclusively by the optimizer.
+    pub(in compile::optimize) fn optimize_const_fold(&mut self, pure_builtins: &PureBuiltins) {
+        self.body = mem::replace(&mut self.body, vec![])
+            .into_iter()
+            .fold(vec![], |mut body: BCBody, instr| {
+                match instr.bc_type {
+                    BCType::Call => {
+                        let folded = pure_builtins
+                            .get(instr.val.as_ref().unwrap().as_ident())
+                            .and_then(|&(arity, fun)| {
+                                fold_call(&body, arity, fun).map(|vals| (arity, vals))
+                            });
+                        if let Some((arity, vals)) = folded {
+                            let start = body.len() - arity;
+                            let mut tokens = body.drain(start..)
+                                .flat_map(|bc| bc.tokens)
+                                .collect::<Tokens>();
+                            tokens.extend(instr.tokens);
+                            body.extend(
+                                vals.into_iter()
+                                    .map(|v| BC::push(tokens.clone(), BCVal::PushAll(vec![v]))),
+                            );
+                        } else {
+                            body.push(instr);
+                        }
+                    }
+                    BCType::SymJmpZ if body.last().and_then(const_push).is_some() => {
+                        let cond = body.pop().unwrap();
+                        let jump_taken = match const_push(&cond).unwrap() {
+                            &BCVal::Bool(false) |
+                            &BCVal::Nil => true,
+                            _ => false,
+                        };
+                        if jump_taken {
+                            let mut tokens = cond.tokens;
+                            tokens.extend(instr.tokens);
+                            body.push(BC {
+                                bc_type: BCType::SymJmp,
+                                tokens,
+                                target: None,
+                                val: instr.val,
+                            });
+                        }
+                    }
+                    _ => body.push(instr),
+                }
+                body
+            });
+    }
+}
@@ -31,7 +31,9 @@ impl Inline {
         const SKIP: &[&'static str] = &["main"]; // function names to skip and not inline
         if let &Fun::UserFun(ref fun) = fun as &BCFun {
             !SKIP.contains(&fun.name.as_str()) &&
-                !fun.body.iter().any(|bc| bc.bc_type == BCType::Call)
+                !fun.body.iter().any(|bc| {
+                    bc.bc_type == BCType::Call || bc.bc_type == BCType::TailCall
+                })
         } else {
             false
         }
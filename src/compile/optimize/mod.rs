@@ -1,9 +1,14 @@
 mod inline;
+mod const_fold;
+mod peephole;
+mod native;
 
 use prelude::*;
 use self::inline::*;
+use self::const_fold::*;
+use self::native::*;
 use std::mem;
-use std::collections::{HashMap, BTreeMap};
+use std::collections::{HashMap, BTreeMap, BTreeSet};
 
 /// A general optimization trait.
 pub trait Optimize {
@@ -49,6 +54,10 @@ bitflags! {
         const STORE                 = 1 << 1;
         const PUSH_COMPRESS         = 1 << 2;
         const ABSOLUTE_JUMPS        = 1 << 3;
+        const CONST_FOLD            = 1 << 4;
+        const PEEPHOLE              = 1 << 5;
+        const TAIL_CALL             = 1 << 6;
+        const NATIVE_OPS            = 1 << 7;
     }
 }
 
@@ -79,6 +88,7 @@ impl OptimizePipeline {
                 Optimizations::PUSH_COMPRESS => BCUserFun::optimize_push_compress as fn(&mut BCUserFun),
                 Optimizations::ABSOLUTE_JUMPS => BCUserFun::optimize_jumps,
                 Optimizations::STORE => BCUserFun::optimize_store,
+                Optimizations::TAIL_CALL => BCUserFun::optimize_tail_calls,
             };
         }
 
@@ -92,6 +102,33 @@ impl OptimizePipeline {
                 let opty = Inline::new(fun_table);
                 self.fun_table = opty.optimize();
             }
+            Optimizations::CONST_FOLD => {
+                // constant folding needs to know which functions are pure builtins
+                let pure_builtins = pure_builtins(&fun_table);
+                let opty = OptimizeMap::new(fun_table, |fun: &mut BCUserFun| {
+                    fun.optimize_const_fold(&pure_builtins)
+                });
+                self.fun_table = opty.optimize();
+            }
+            Optimizations::PEEPHOLE => {
+                // peephole rules only apply to builtins that haven't been overridden
+                let builtins = fun_table
+                    .iter()
+                    .filter(|&(_, fun)| matches!(fun, &Fun::BuiltinFun(_)))
+                    .map(|(name, _)| name.clone())
+                    .collect::<BTreeSet<_>>();
+                let opty = OptimizeMap::new(fun_table, |fun: &mut BCUserFun| {
+                    fun.optimize_peephole(&builtins)
+                });
+                self.fun_table = opty.optimize();
+            }
+            Optimizations::NATIVE_OPS => {
+                let native_ops = native_ops(&fun_table);
+                let opty = OptimizeMap::new(fun_table, |fun: &mut BCUserFun| {
+                    fun.optimize_native_ops(&native_ops)
+                });
+                self.fun_table = opty.optimize();
+            }
             _ => {
                 // map-based optimizations
                 let opty = OptimizeMap::new(fun_table, OPTIMAP[&opt]);
@@ -110,12 +147,24 @@ impl Optimize for OptimizePipeline {
             // INLINE can happen anywhere. Doing it before PUSH_COMPRESS is a good idea because
             // then any inlined PUSH instructions will get compressed later on.
             Optimizations::INLINE,
+            // CONST_FOLD happens after INLINE, so constants that were pushed by an inlined function
+            // can be folded too. It needs symbolic jumps, since it may remove instructions.
+            Optimizations::CONST_FOLD,
+            // PEEPHOLE cleans up after CONST_FOLD, e.g. jumps that now go to the next instruction.
+            // It runs before STORE, which doesn't expect pushes to go away.
+            Optimizations::PEEPHOLE,
+            // NATIVE_OPS happens after the optimizations that look for calls to specific builtins,
+            // and before TAIL_CALL so builtins don't get turned into tail calls.
+            Optimizations::NATIVE_OPS,
             // STORE converts a PUSH of a const immediately followed by a POP to a
             // STORE instruction
             Optimizations::STORE,
             // PUSH_COMPRESS should happen near the end, because some pushes may get removed or
             // added to fix this up.
             Optimizations::PUSH_COMPRESS,
+            // TAIL_CALL only changes instructions in place, but it follows symbolic jumps to find
+            // out whether a call is followed by a return.
+            Optimizations::TAIL_CALL,
             // ABSOLUTE_JUMPS happens last, because modifying the order of absolute jumps will mess
             // up the program.
             Optimizations::ABSOLUTE_JUMPS,
@@ -165,8 +214,8 @@ impl BCUserFun {
         });
     }
 
-    /// Converts all SYM_JUMP* instructions into JMP* instructions. This speeds up program
-    /// execution by not having to do a label lookup every time a jump is done.
+    /// Converts all SYM_JUMP* and SYM_TRY instructions into JMP* and TRY instructions. This speeds
+    /// up program execution by not having to do a label lookup every time a jump is done.
     ///
     /// This is used exclusively by the optimizer.
     pub(in compile::optimize) fn optimize_jumps(&mut self) {
@@ -187,6 +236,7 @@ impl BCUserFun {
             .filter(|instr| instr.bc_type != BCType::Label)
             .map(|instr| if instr.bc_type == BCType::SymJmp { BC::jmp(instr.tokens, labels[instr.val.unwrap().as_int()].into()) }
                  else if instr.bc_type == BCType::SymJmpZ { BC::jmpz(instr.tokens, labels[instr.val.unwrap().as_int()].into()) }
+                 else if instr.bc_type == BCType::SymTry { BC::enter_try(instr.tokens, labels[instr.val.unwrap().as_int()].into()) }
                  else { instr })
             .collect()
     }
@@ -222,4 +272,42 @@ impl BCUserFun {
                 body
             });
     }
+
+    /// Converts calls that are immediately followed by a return into tail calls, so the VM can
+    /// reuse the caller's frame for the callee. This lets tail recursion run in constant space.
+    ///
+    /// This is used exclusively by the optimizer.
+    pub(in compile::optimize) fn optimize_tail_calls(&mut self) {
+        let tail_calls = self.body
+            .iter()
+            .enumerate()
+            .filter(|&(addr, bc)| bc.bc_type == BCType::Call && self.returns_from(addr + 1))
+            .map(|(addr, _)| addr)
+            .collect::<Vec<_>>();
+        for addr in tail_calls {
+            self.body[addr].bc_type = BCType::TailCall;
+        }
+    }
+
+    /// Determines whether execution starting at the given address returns from the function
+    /// without doing anything else first.
+    fn returns_from(&self, mut addr: usize) -> bool {
+        let mut seen = vec![];
+        loop {
+            if seen.contains(&addr) {
+                return false;
+            }
+            seen.push(addr);
+            match self.body.get(addr).map(|bc| bc.bc_type) {
+                Some(BCType::Ret) => return true,
+                Some(BCType::Label) | Some(BCType::Nop) => addr += 1,
+                Some(BCType::SymJmp) => {
+                    let label = *self.body[addr].val.as_ref().unwrap().as_int();
+                    addr = self.get_label_address(label);
+                }
+                Some(BCType::Jmp) => addr = *self.body[addr].val.as_ref().unwrap().as_address(),
+                _ => return false,
+            }
+        }
+    }
 }
@@ -0,0 +1,49 @@
+use prelude::*;
+use std::collections::BTreeMap;
+
+/// Builtin functions that have a dedicated instruction in the VM.
+const NATIVE_OPS: &[(&str, BCType)] = &[
+    ("+", BCType::Add),
+    ("-", BCType::Sub),
+    ("*", BCType::Mul),
+    ("/", BCType::Div),
+    ("==", BCType::Eq),
+    ("!=", BCType::Ne),
+    ("<", BCType::Lt),
+    (">", BCType::Gt),
+    ("<=", BCType::Le),
+    (">=", BCType::Ge),
+    ("^", BCType::Dup),
+    ("#", BCType::Depth),
+];
+
+/// A table of builtin function names, mapped to the instruction that replaces calls to them.
+pub type NativeOps = BTreeMap<String, BCType>;
+
+/// Gets all of the native instructions for builtins that are defined in the given function table.
+/// Names that have been overridden by a user or foreign function are left out.
+pub fn native_ops(fun_table: &BCFunTable) -> NativeOps {
+    NATIVE_OPS
+        .iter()
+        .filter(|&&(name, _)| matches!(fun_table.get(name), Some(&Fun::BuiltinFun(_))))
+        .map(|&(name, bc_type)| (name.to_string(), bc_type))
+        .collect()
+}
+
+impl BCUserFun {
+    /// Replaces calls to builtins that have a native instruction with that instruction. This
+    /// skips looking up the function by name every time that it's called.
+    ///
+    /// This is used exclusively by the optimizer.
+    pub(in compile::optimize) fn optimize_native_ops(&mut self, native_ops: &NativeOps) {
+        for bc in self.body.iter_mut().filter(|bc| {
+            bc.bc_type == BCType::Call || bc.bc_type == BCType::TailCall
+        })
+        {
+            if let Some(&bc_type) = native_ops.get(bc.val.as_ref().unwrap().as_ident()) {
+                bc.bc_type = bc_type;
+                bc.val = None;
+            }
+        }
+    }
+}
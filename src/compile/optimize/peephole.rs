@@ -0,0 +1,341 @@
+use prelude::*;
+use std::collections::BTreeSet;
+
+/// A match of a peephole rule's pattern against a function body.
+pub struct PeepholeMatch<'a> {
+    /// The entire body of the function that is being optimized.
+    pub body: &'a [BC],
+    /// The address where the pattern matched.
+    pub addr: usize,
+    /// The instructions that the pattern matched.
+    pub window: &'a [BC],
+    /// The names of all builtin functions.
+    pub builtins: &'a BTreeSet<String>,
+}
+
+/// A single peephole optimization.
+///
+/// Rules match on a run of instruction types, and then decide whether the matched instructions
+/// can be rewritten based on their values.
+pub struct PeepholeRule {
+    /// The name of this rule, which tests use to pick it out.
+    #[cfg(test)]
+    pub name: &'static str,
+    /// The types of instructions that this rule matches, in order.
+    pub pattern: &'static [BCType],
+    /// Gets the replacement for the matched instructions, or `None` if the rule doesn't apply.
+    pub rewrite: fn(&PeepholeMatch) -> Option<BCBody>,
+}
+
+impl PeepholeRule {
+    /// Attempts to apply this rule at the given address, returning the replacement for the matched
+    /// instructions.
+    pub fn apply(&self, body: &[BC], addr: usize, builtins: &BTreeSet<String>) -> Option<BCBody> {
+        let end = addr + self.pattern.len();
+        if end > body.len() ||
+            !body[addr..end]
+                .iter()
+                .zip(self.pattern)
+                .all(|(bc, bc_type)| bc.bc_type == *bc_type)
+        {
+            return None;
+        }
+        (self.rewrite)(&PeepholeMatch {
+            body,
+            addr,
+            window: &body[addr..end],
+            builtins,
+        })
+    }
+}
+
+/// All peephole rules, in the order that they are tried.
+pub const PEEPHOLE_RULES: &[PeepholeRule] = &[
+    PeepholeRule {
+        #[cfg(test)]
+        name: "push-discard",
+        pattern: &[BCType::Push, BCType::PopDiscard],
+        rewrite: push_discard,
+    },
+    PeepholeRule {
+        #[cfg(test)]
+        name: "tos-discard",
+        pattern: &[BCType::Call, BCType::PopDiscard],
+        rewrite: tos_discard,
+    },
+    PeepholeRule {
+        #[cfg(test)]
+        name: "load-pop",
+        pattern: &[BCType::Load, BCType::Pop],
+        rewrite: load_pop,
+    },
+    PeepholeRule {
+        #[cfg(test)]
+        name: "jump-next",
+        pattern: &[BCType::SymJmp],
+        rewrite: jump_next,
+    },
+    PeepholeRule {
+        #[cfg(test)]
+        name: "jumpz-next",
+        pattern: &[BCType::SymJmpZ],
+        rewrite: jumpz_next,
+    },
+    PeepholeRule {
+        #[cfg(test)]
+        name: "thread-jump",
+        pattern: &[BCType::SymJmp],
+        rewrite: thread_jump,
+    },
+    PeepholeRule {
+        #[cfg(test)]
+        name: "thread-jumpz",
+        pattern: &[BCType::SymJmpZ],
+        rewrite: thread_jump,
+    },
+];
+
+/// Gets the address of the first non-label instruction at or after the given address.
+fn skip_labels(body: &[BC], mut addr: usize) -> usize {
+    while addr < body.len() && body[addr].bc_type == BCType::Label {
+        addr += 1;
+    }
+    addr
+}
+
+/// Gets the address of the first instruction that would be run after jumping to a label.
+fn label_target(body: &[BC], label: &BCVal) -> Option<usize> {
+    body.iter()
+        .position(|bc| bc.bc_type == BCType::Label && bc.val.as_ref() == Some(label))
+        .map(|addr| skip_labels(body, addr))
+}
+
+/// `PUSH x; POP_DISCARD` doesn't need to push the last value at all.
+fn push_discard(m: &PeepholeMatch) -> Option<BCBody> {
+    let push = &m.window[0];
+    let mut vals = push.val.as_ref().unwrap().as_push_all().clone();
+    vals.pop();
+    if vals.is_empty() {
+        Some(vec![])
+    } else {
+        Some(vec![BC::push(push.tokens.clone(), BCVal::PushAll(vals))])
+    }
+}
+
+/// `CALL ^; POP_DISCARD` copies the top of the stack, only to throw it away.
+fn tos_discard(m: &PeepholeMatch) -> Option<BCBody> {
+    let name = m.window[0].val.as_ref().unwrap().as_ident();
+    if name == "^" && m.builtins.contains(name) {
+        Some(vec![])
+    } else {
+        None
+    }
+}
+
+/// `LOAD a; POP a` stores a variable back into itself, which does nothing.
+fn load_pop(m: &PeepholeMatch) -> Option<BCBody> {
+    if m.window[0].val == m.window[1].val {
+        Some(vec![])
+    } else {
+        None
+    }
+}
+
+/// Jumping to the instruction that comes next anyway does nothing.
+fn jump_next(m: &PeepholeMatch) -> Option<BCBody> {
+    let target = label_target(m.body, m.window[0].val.as_ref().unwrap())?;
+    if target == skip_labels(m.body, m.addr + 1) {
+        Some(vec![])
+    } else {
+        None
+    }
+}
+
+/// A conditional jump to the next instruction still has to take its condition off of the stack.
+fn jumpz_next(m: &PeepholeMatch) -> Option<BCBody> {
+    jump_next(m).map(|_| vec![BC::pop_discard(m.window[0].tokens.clone())])
+}
+
+/// A jump to an unconditional jump can go straight to that jump's destination.
+fn thread_jump(m: &PeepholeMatch) -> Option<BCBody> {
+    let jump = &m.window[0];
+    let mut label = jump.val.as_ref().unwrap();
+    let mut seen = vec![label];
+    loop {
+        let target = label_target(m.body, label)?;
+        match m.body.get(target) {
+            Some(bc) if bc.bc_type == BCType::SymJmp => {
+                label = bc.val.as_ref().unwrap();
+                // jumps that go in a circle can't be threaded
+                if seen.contains(&label) {
+                    return None;
+                }
+                seen.push(label);
+            }
+            _ => break,
+        }
+    }
+    if label == jump.val.as_ref().unwrap() {
+        None
+    } else {
+        Some(vec![BC {
+            bc_type: jump.bc_type,
+            tokens: jump.tokens.clone(),
+            target: None,
+            val: Some(label.clone()),
+        }])
+    }
+}
+
+/// Makes a single pass of the given rules over a body, returning whether anything was changed.
+pub fn apply_peephole_rules(
+    body: &mut BCBody,
+    rules: &[PeepholeRule],
+    builtins: &BTreeSet<String>,
+) -> bool {
+    let mut changed = false;
+    let mut addr = 0;
+    while addr < body.len() {
+        let rewrite = rules
+            .iter()
+            .filter_map(|rule| {
+                rule.apply(body, addr, builtins)
+                    .map(|replacement| (rule.pattern.len(), replacement))
+            })
+            .next();
+        if let Some((len, replacement)) = rewrite {
+            // stay at the same address, since the replacement may match something else
+            body.splice(addr..addr + len, replacement);
+            changed = true;
+        } else {
+            addr += 1;
+        }
+    }
+    changed
+}
+
+impl BCUserFun {
+    /// Applies all peephole rules to this function until none of them apply anymore.
+    ///
+    /// This is used exclusively by the optimizer.
+    pub(in compile::optimize) fn optimize_peephole(&mut self, builtins: &BTreeSet<String>) {
+        while apply_peephole_rules(&mut self.body, PEEPHOLE_RULES, builtins) {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn instr(bc_type: BCType, val: Option<BCVal>) -> BC {
+        BC {
+            bc_type,
+            tokens: vec![],
+            target: None,
+            val,
+        }
+    }
+
+    fn push(vals: &[i64]) -> BC {
+        BC::push(vec![], BCVal::PushAll(vals.iter().map(|i| BCVal::Int(*i)).collect()))
+    }
+
+    fn label(which: i64) -> BC {
+        instr(BCType::Label, Some(BCVal::Int(which)))
+    }
+
+    fn sym_jmp(which: i64) -> BC {
+        instr(BCType::SymJmp, Some(BCVal::Int(which)))
+    }
+
+    fn sym_jmpz(which: i64) -> BC {
+        instr(BCType::SymJmpZ, Some(BCVal::Int(which)))
+    }
+
+    fn call(name: &str) -> BC {
+        instr(BCType::Call, Some(BCVal::Ident(name.to_string())))
+    }
+
+    /// Applies a single rule to a body until it no longer applies.
+    fn apply_rule(name: &str, mut body: BCBody) -> BCBody {
+        let rule = PEEPHOLE_RULES.iter().find(|r| r.name == name).unwrap();
+        let builtins = BUILTINS.keys().map(|k| k.to_string()).collect();
+        while apply_peephole_rules(&mut body, ::std::slice::from_ref(rule), &builtins) {}
+        body
+    }
+
+    #[test]
+    fn test_peephole_push_discard() {
+        let body = vec![push(&[1]), BC::pop_discard(vec![]), push(&[2])];
+        assert_eq!(apply_rule("push-discard", body), vec![push(&[2])]);
+        let body = vec![push(&[1, 2, 3]), BC::pop_discard(vec![]), BC::pop_discard(vec![])];
+        assert_eq!(apply_rule("push-discard", body), vec![push(&[1])]);
+    }
+
+    #[test]
+    fn test_peephole_tos_discard() {
+        let body = vec![call("^"), BC::pop_discard(vec![]), call("foo")];
+        assert_eq!(apply_rule("tos-discard", body), vec![call("foo")]);
+        let body = vec![call("foo"), BC::pop_discard(vec![])];
+        assert_eq!(apply_rule("tos-discard", body.clone()), body);
+    }
+
+    #[test]
+    fn test_peephole_load_pop() {
+        let body = vec![
+            instr(BCType::Load, Some(BCVal::Address(0))),
+            instr(BCType::Pop, Some(BCVal::Address(0))),
+        ];
+        assert_eq!(apply_rule("load-pop", body), vec![]);
+        let body = vec![
+            instr(BCType::Load, Some(BCVal::Address(0))),
+            instr(BCType::Pop, Some(BCVal::Address(1))),
+        ];
+        assert_eq!(apply_rule("load-pop", body.clone()), body);
+    }
+
+    #[test]
+    fn test_peephole_jump_next() {
+        let body = vec![sym_jmp(1), label(0), label(1), push(&[1])];
+        assert_eq!(apply_rule("jump-next", body), vec![label(0), label(1), push(&[1])]);
+        let body = vec![sym_jmp(1), push(&[1]), label(1)];
+        assert_eq!(apply_rule("jump-next", body.clone()), body);
+    }
+
+    #[test]
+    fn test_peephole_jumpz_next() {
+        let body = vec![sym_jmpz(0), label(0), push(&[1])];
+        assert_eq!(
+            apply_rule("jumpz-next", body),
+            vec![BC::pop_discard(vec![]), label(0), push(&[1])]
+        );
+    }
+
+    #[test]
+    fn test_peephole_thread_jump() {
+        let body = vec![
+            sym_jmp(0),
+            push(&[1]),
+            label(0),
+            sym_jmp(1),
+            push(&[2]),
+            label(1),
+            sym_jmp(2),
+            push(&[3]),
+            label(2),
+        ];
+        let threaded = apply_rule("thread-jump", body);
+        assert_eq!(threaded[0], sym_jmp(2));
+        assert_eq!(threaded[3], sym_jmp(2));
+
+        // jumps that go in a circle are left alone
+        let body = vec![label(0), sym_jmp(1), label(1), sym_jmp(0)];
+        assert_eq!(apply_rule("thread-jump", body.clone()), body);
+    }
+
+    #[test]
+    fn test_peephole_thread_jumpz() {
+        let body = vec![sym_jmpz(0), push(&[1]), label(0), sym_jmp(1), push(&[2]), label(1)];
+        assert_eq!(apply_rule("thread-jumpz", body)[0], sym_jmpz(1));
+    }
+}
@@ -10,13 +10,25 @@ pub trait UserFun {
     fn tokens(&self) -> &[Rc<Token>];
 }
 
+impl<U: UserFun> UserFun for Rc<U> {
+    type InstructionT = U::InstructionT;
+
+    fn name(&self) -> &str { (**self).name() }
+    fn body(&self) -> &[Self::InstructionT] { (**self).body() }
+    fn tokens(&self) -> &[Rc<Token>] { (**self).tokens() }
+}
+
 /// A marker trait that defines an instruction type.
 pub trait Instruction {}
 
 pub enum Fun<U: UserFun> {
     UserFun(U),
     ForeignFun(ForeignFun),
+    RecordFun(RecordFun),
+    StructFun(StructFun),
+    CallbackFun(ForeignCallback),
     BuiltinFun(&'static BuiltinFun),
+    HostFun(HostFun),
 }
 
 
@@ -39,7 +51,11 @@ impl<U: UserFun + Clone> Clone for Fun<U> {
         match self {
             &Fun::UserFun(ref fun) => Fun::UserFun(fun.clone()),
             &Fun::ForeignFun(ref fun) => Fun::ForeignFun(fun.clone()),
+            &Fun::RecordFun(ref fun) => Fun::RecordFun(fun.clone()),
+            &Fun::StructFun(ref fun) => Fun::StructFun(fun.clone()),
+            &Fun::CallbackFun(ref fun) => Fun::CallbackFun(fun.clone()),
             &Fun::BuiltinFun(fun) => Fun::BuiltinFun(fun),
+            &Fun::HostFun(ref fun) => Fun::HostFun(fun.clone()),
         }
     }
 }
@@ -49,7 +65,11 @@ impl<U: UserFun + Debug> Debug for Fun<U> {
         write!(fmt, "{}", match self {
             &Fun::UserFun(ref fun) => format!("{:?}", fun),
             &Fun::ForeignFun(ref fun) => format!("{:?}", fun),
+            &Fun::RecordFun(ref fun) => format!("{:?}", fun),
+            &Fun::StructFun(ref fun) => format!("{:?}", fun),
+            &Fun::CallbackFun(ref fun) => format!("{:?}", fun),
             &Fun::BuiltinFun(fun) => format!("{:?}", fun as *const _),
+            &Fun::HostFun(ref fun) => format!("{:?}", fun),
         })
     }
 }
@@ -11,6 +11,7 @@ use std::fmt::{self, Formatter, Display};
 pub enum IRType {
     Push,
     PushL,
+    PushM,
     Pop,
     Load,
     JmpZ,
@@ -19,6 +20,12 @@ pub enum IRType {
     Ret,
     Bake,
     Label,
+    IsType,
+    Try,
+    EndTry,
+    Spawn,
+    Resume,
+    Yield,
     Nop,
 }
 
@@ -30,6 +37,7 @@ impl Display for IRType {
             match self {
                 &IRType::Push => "PUSH",
                 &IRType::PushL => "PUSHL",
+                &IRType::PushM => "PUSHM",
                 &IRType::Pop => "POP",
                 &IRType::Load => "LOAD",
                 &IRType::JmpZ => "JMPZ",
@@ -38,6 +46,12 @@ impl Display for IRType {
                 &IRType::Ret => "RET",
                 &IRType::Bake => "BAKE",
                 &IRType::Label => "LABEL",
+                &IRType::IsType => "IS_TYPE",
+                &IRType::Try => "TRY",
+                &IRType::EndTry => "END_TRY",
+                &IRType::Spawn => "SPAWN",
+                &IRType::Resume => "RESUME",
+                &IRType::Yield => "YIELD",
                 &IRType::Nop => "NOP",
             }
         )
@@ -72,6 +86,70 @@ impl IR {
         }
     }
 
+    pub fn pushm(tokens: Tokens) -> IR {
+        IR {
+            ir_type: IRType::PushM,
+            tokens,
+            val: None,
+        }
+    }
+
+    /// Pops a value, pushing whether it has one of the listed types.
+    pub fn is_type(tokens: Tokens, val: IRVal) -> IR {
+        assert_matches!(val, IRVal::Stack(_));
+        IR {
+            ir_type: IRType::IsType,
+            tokens,
+            val: Some(val),
+        }
+    }
+
+    /// Starts a try block, whose errors are handled by the code at the given label.
+    pub fn enter_try(tokens: Tokens, val: IRVal) -> IR {
+        assert_matches!(val, IRVal::Int(_));
+        IR {
+            ir_type: IRType::Try,
+            tokens,
+            val: Some(val),
+        }
+    }
+
+    pub fn end_try(tokens: Tokens) -> IR {
+        IR {
+            ir_type: IRType::EndTry,
+            tokens,
+            val: None,
+        }
+    }
+
+    /// Pushes a new coroutine that runs the given function.
+    pub fn spawn(tokens: Tokens, val: IRVal) -> IR {
+        assert_matches!(val, IRVal::Ident(_));
+        IR {
+            ir_type: IRType::Spawn,
+            tokens,
+            val: Some(val),
+        }
+    }
+
+    /// Pops a coroutine and runs it until it yields or returns.
+    pub fn resume(tokens: Tokens) -> IR {
+        IR {
+            ir_type: IRType::Resume,
+            tokens,
+            val: None,
+        }
+    }
+
+    /// Pops a value, handing it back to whatever resumed the current coroutine.
+    pub fn yield_(tokens: Tokens) -> IR {
+        IR {
+            ir_type: IRType::Yield,
+            tokens,
+            val: None,
+        }
+    }
+
     pub fn pop(tokens: Tokens, val: IRVal) -> IR {
         IR {
             ir_type: IRType::Pop,
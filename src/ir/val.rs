@@ -1,15 +1,21 @@
 use prelude::*;
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::fmt::{self, Formatter, Display};
+use std::rc::Rc;
 
 #[derive(EnumIntoGetters, EnumAsGetters, EnumIsA, PartialEq, Clone, Debug)]
 pub enum IRVal {
     Int(i64),
+    Float(f64),
     Ident(String),
     Char(char),
     String(String),
     Bool(bool),
     Stack(Vec<IRVal>),
+    Map(Vec<(IRVal, IRVal)>),
+    Record(Rc<Record>),
+    Error(Rc<ErrorVal>),
     Address(usize),
     Nil,
     BakeBlock(IRBody),
@@ -19,29 +25,41 @@ impl IRVal {
     pub fn matches(&self, other: &Self) -> bool {
         match self {
             &IRVal::Int(_) => other.is_int(),
+            &IRVal::Float(_) => other.is_float(),
             &IRVal::Ident(_) => other.is_ident(),
             &IRVal::Char(_) => other.is_char(),
             &IRVal::String(_) => other.is_string(),
             &IRVal::Bool(_) => other.is_bool(),
             &IRVal::Stack(_) => other.is_stack(),
+            &IRVal::Map(_) => other.is_map(),
+            &IRVal::Record(ref r) => match other {
+                &IRVal::Record(ref o) => r.record_type == o.record_type,
+                _ => false,
+            },
+            &IRVal::Error(_) => other.is_error(),
             &IRVal::Address(_) => other.is_address(),
             &IRVal::Nil => other.is_nil(),
             &IRVal::BakeBlock(_) => other.is_bake_block(),
         }
     }
 
-    pub fn type_string(&self) -> &'static str {
-        match self {
+    pub fn type_string(&self) -> Cow<'static, str> {
+        let s = match self {
             &IRVal::Int(_) => "int",
+            &IRVal::Float(_) => "float",
             &IRVal::Ident(_) => "identifier",
             &IRVal::Char(_) => "char",
             &IRVal::String(_) => "string",
             &IRVal::Bool(_) => "bool",
             &IRVal::Stack(_) => "local stack",
+            &IRVal::Map(_) => "map",
             &IRVal::Address(_) => "address",
             &IRVal::Nil => "nil",
+            &IRVal::Record(ref r) => return Cow::Owned(r.type_name().to_string()),
+            &IRVal::Error(_) => "error",
             &IRVal::BakeBlock(_) => "bake block",
-        }
+        };
+        Cow::Borrowed(s)
     }
 
     pub fn compare(&self, other: &IRVal) -> Result<Ordering> {
@@ -57,8 +75,11 @@ impl IRVal {
 
         match self {
             &IRVal::Int(i) => Ok(other.as_int().cmp(&i)),
+            &IRVal::Float(x) => other.as_float().partial_cmp(&x).ok_or_else(|| {
+                "NaN may not be compared with ordinal operators".into()
+            }),
             &IRVal::Address(a) => Ok(other.as_address().cmp(&a)),
-            &IRVal::Ident(_) | &IRVal::String(_) | &IRVal::Bool(_) | &IRVal::Stack(_) | &IRVal::Nil => Err(
+            &IRVal::Ident(_) | &IRVal::String(_) | &IRVal::Bool(_) | &IRVal::Stack(_) | &IRVal::Map(_) | &IRVal::Record(_) | &IRVal::Error(_) | &IRVal::Nil => Err(
                 format!(
                     "{} types may not be compared with ordinal operators",
                     self.type_string()
@@ -76,6 +97,7 @@ impl Display for IRVal {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
             &IRVal::Int(i) => write!(f, "{}", i),
+            &IRVal::Float(x) => write!(f, "{:?}", x),
             &IRVal::Ident(ref s) => write!(f, "{}", s),
             &IRVal::Char(c) => write!(f, "{}", c),
             &IRVal::String(ref s) => write!(f, "{}", s),
@@ -87,6 +109,15 @@ impl Display for IRVal {
                     v.iter().map(IRVal::to_string).collect::<Vec<_>>().join(",")
                 )
             }
+            &IRVal::Map(ref m) => {
+                write!(
+                    f,
+                    "%[{}]",
+                    m.iter().map(|&(ref k, ref v)| format!("{}:{}", k, v)).collect::<Vec<_>>().join(",")
+                )
+            }
+            &IRVal::Record(ref r) => write!(f, "{}", r),
+            &IRVal::Error(ref e) => write!(f, "{}", e.message),
             &IRVal::Address(a) => write!(f, "0x{:X}", a),
             &IRVal::Nil => write!(f, "nil"),
             &IRVal::BakeBlock(ref b) => write!(f, "bake block {{ {:#?} }}", b),
@@ -98,13 +129,24 @@ impl From<BCVal> for IRVal {
     fn from(other: BCVal) -> Self {
         match other {
             BCVal::Int(i) => IRVal::Int(i),
+            BCVal::Float(x) => IRVal::Float(x),
             BCVal::Ident(i) => IRVal::Ident(i),
             BCVal::Char(c) => IRVal::Char(c),
-            BCVal::String(s) => IRVal::String(s),
+            BCVal::String(s) => IRVal::String(s.to_string()),
             BCVal::Bool(b) => IRVal::Bool(b),
-            BCVal::Stack(s) => IRVal::Stack(s.into_iter().map(BCVal::into).collect()),
+            BCVal::Stack(s) => IRVal::Stack(s.iter().cloned().map(BCVal::into).collect()),
+            BCVal::Map(m) => IRVal::Map(
+                m.iter().map(|(k, v)| (BCVal::from(k.clone()).into(), v.clone().into())).collect(),
+            ),
+            BCVal::Record(r) => IRVal::Record(r),
+            BCVal::Error(e) => IRVal::Error(e),
             BCVal::Address(a) => IRVal::Address(a),
             BCVal::PushAll(_) => panic!("BCVal::PushAll values cannot be converted to an IRVal"),
+            BCVal::Ref(_) => panic!("BCVal::Ref values cannot be converted to an IRVal"),
+            BCVal::Coroutine(_) => panic!("BCVal::Coroutine values cannot be converted to an IRVal"),
+            BCVal::Handle(_) => panic!("BCVal::Handle values cannot be converted to an IRVal"),
+            BCVal::Pointer(_) => panic!("BCVal::Pointer values cannot be converted to an IRVal"),
+            BCVal::Buffer(_) => panic!("BCVal::Buffer values cannot be converted to an IRVal"),
             BCVal::Nil => IRVal::Nil,
         }
     }
@@ -114,11 +156,13 @@ impl From<Item> for IRVal {
     fn from(other: Item) -> Self {
         match other.into() {
             ItemType::Int(i) => IRVal::Int(i),
+            ItemType::Float(x) => IRVal::Float(x),
             ItemType::Ident(i) => IRVal::Ident(i),
             ItemType::Char(c) => IRVal::Char(c),
             ItemType::String(s) => IRVal::String(s),
             ItemType::Bool(b) => IRVal::Bool(b),
             ItemType::Stack(s) => IRVal::Stack(s.into_iter().map(Item::into).collect()),
+            ItemType::Map(m) => IRVal::Map(m.into_iter().map(|(k, v)| (k.into(), v.into())).collect()),
             ItemType::Nil => IRVal::Nil,
         }
     }
@@ -32,6 +32,13 @@ pub mod errors {
                 description("Ranged error")
                 display("in {}", range)
             }
+
+            /// A value that was thrown with `throw`. The value itself is kept in the VM state
+            /// until it's caught.
+            Thrown(val: String) {
+                description("Uncaught thrown value")
+                display("uncaught value thrown: {}", val)
+            }
         }
     }
 
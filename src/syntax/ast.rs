@@ -77,11 +77,13 @@ macro_rules! lookaheads {
 #[derive(EnumIsA, PartialEq, Clone, Debug)]
 pub enum ItemType {
     Int(i64),
+    Float(f64),
     Ident(String),
     Char(char),
     String(String),
     Bool(bool),
     Stack(Vec<Item>),
+    Map(Vec<(Item, Item)>),
     Nil,
 }
 
@@ -89,11 +91,13 @@ impl ItemType {
     pub fn type_string(&self) -> &'static str {
         match self {
             &ItemType::Int(_) => "int",
+            &ItemType::Float(_) => "float",
             &ItemType::Ident(_) => "identifier",
             &ItemType::Char(_) => "char",
             &ItemType::String(_) => "string",
             &ItemType::Bool(_) => "bool",
             &ItemType::Stack(_) => "local stack",
+            &ItemType::Map(_) => "map",
             &ItemType::Nil => "nil",
         }
     }
@@ -109,8 +113,8 @@ impl From<Item> for ItemType {
 /// This is an atomic type; no further constructs are parsed above the "item"
 /// level with this node.
 ///
-/// An item may be an int, identifier, character, string, boolean, stack
-/// literal, or nil.
+/// An item may be an int, float, identifier, character, string, boolean, stack
+/// literal, map literal, or nil.
 #[derive(Clone)]
 #[cfg_attr(not(test), derive(PartialEq, Debug))]
 pub struct Item {
@@ -127,6 +131,7 @@ impl Item {
         match self.item_type {
             ItemType::Ident(_) => false,
             ItemType::Stack(ref s) => s.iter().all(Item::is_const),
+            ItemType::Map(ref m) => m.iter().all(|&(ref k, ref v)| k.is_const() && v.is_const()),
             _ => true,
         }
     }
@@ -152,9 +157,9 @@ impl ASTNode for Item {
     }
 
     fn lookaheads() -> &'static [TokenType] {
-        lookaheads!(TokenType::Int TokenType::Ident TokenType::Char
+        lookaheads!(TokenType::Int TokenType::Float TokenType::Ident TokenType::Char
                     TokenType::String TokenType::KwT TokenType::KwF
-                    TokenType::KwNil TokenType::LBrack
+                    TokenType::KwNil TokenType::LBrack TokenType::MapLBrack
                     TokenType::BasedInt(2)
                     TokenType::BasedInt(8)
                     TokenType::BasedInt(16))
@@ -171,6 +176,12 @@ impl From<Token> for Item {
                     ItemType::Int(other_str.parse().unwrap()),
                 )
             }
+            TokenType::Float => {
+                Item::new(
+                    vec![other.into_rc()],
+                    ItemType::Float(other_str.parse().unwrap()),
+                )
+            }
             TokenType::BasedInt(base) => {
                 Item::new(
                     vec![other.into_rc()],
@@ -274,6 +285,9 @@ pub enum Stmt {
     Br(BrStmt),
     Loop(LoopStmt),
     Bake(BakeStmt),
+    Match(MatchStmt),
+    Try(TryStmt),
+    Spawn(SpawnStmt),
 }
 
 impl ASTNode for Stmt {
@@ -283,11 +297,14 @@ impl ASTNode for Stmt {
             Stmt::Br(ref s) => s.tokens(),
             Stmt::Loop(ref s) => s.tokens(),
             Stmt::Bake(ref s) => s.tokens(),
+            Stmt::Match(ref s) => s.tokens(),
+            Stmt::Try(ref s) => s.tokens(),
+            Stmt::Spawn(ref s) => s.tokens(),
         }
     }
 
     fn lookaheads() -> &'static [TokenType] {
-        lookaheads!(StackStmt BrStmt LoopStmt)
+        lookaheads!(StackStmt BrStmt LoopStmt MatchStmt TryStmt SpawnStmt)
     }
 }
 
@@ -324,6 +341,27 @@ impl PartialEq for Stmt {
                     false
                 }
             }
+            &Match(ref m) => {
+                if let &Match(ref o) = other {
+                    m == o
+                } else {
+                    false
+                }
+            }
+            &Try(ref t) => {
+                if let &Try(ref o) = other {
+                    t == o
+                } else {
+                    false
+                }
+            }
+            &Spawn(ref s) => {
+                if let &Spawn(ref o) = other {
+                    s == o
+                } else {
+                    false
+                }
+            }
         }
     }
 }
@@ -354,6 +392,9 @@ from_stmt!(Stack, StackStmt);
 from_stmt!(Br, BrStmt);
 from_stmt!(Loop, LoopStmt);
 from_stmt!(Bake, BakeStmt);
+from_stmt!(Match, MatchStmt);
+from_stmt!(Try, TryStmt);
+from_stmt!(Spawn, SpawnStmt);
 
 //
 // Stack statements
@@ -569,6 +610,87 @@ block_stmt!(BakeStmt
             new => (block: Block)
             lookaheads => (TokenType::KwBake));
 
+#[derive(Clone)]
+#[cfg_attr(not(test), derive(PartialEq, Debug))]
+pub struct TryStmt {
+    pub tokens: Tokens,
+    pub block: Block,
+    pub catch_stmt: CatchStmt,
+}
+
+block_stmt!(TryStmt
+            new => (block: Block, catch_stmt: CatchStmt)
+            lookaheads => (TokenType::KwTry));
+
+#[derive(Clone)]
+#[cfg_attr(not(test), derive(PartialEq, Debug))]
+pub struct CatchStmt {
+    pub tokens: Tokens,
+    pub block: Block,
+}
+
+block_stmt!(CatchStmt
+            new => (block: Block)
+            lookaheads => (TokenType::KwCatch));
+
+#[derive(Clone)]
+#[cfg_attr(not(test), derive(PartialEq, Debug))]
+pub struct MatchStmt {
+    pub tokens: Tokens,
+    /// The local that the matched value is stored in.
+    pub binding: String,
+    pub arms: Vec<MatchArm>,
+    pub el_stmt: Option<ElStmt>,
+}
+
+block_stmt!(MatchStmt
+            new => (binding: String, arms: Vec<MatchArm>, el_stmt: Option<ElStmt>)
+            lookaheads => (TokenType::KwMatch));
+
+#[derive(Clone)]
+#[cfg_attr(not(test), derive(PartialEq, Debug))]
+pub struct MatchArm {
+    pub tokens: Tokens,
+    /// The names of the types that this arm matches.
+    pub types: Vec<String>,
+    pub block: Block,
+}
+
+block_stmt!(MatchArm
+            new => (types: Vec<String>, block: Block)
+            lookaheads => (TokenType::Ident));
+
+/// What a `spawn` statement runs as a coroutine.
+#[derive(EnumIsA, Clone, PartialEq, Debug)]
+pub enum SpawnTarget {
+    /// A function, by name.
+    Fun(String),
+    /// A block, which is compiled into its own function.
+    Block(Block),
+}
+
+#[derive(Clone)]
+#[cfg_attr(not(test), derive(PartialEq, Debug))]
+pub struct SpawnStmt {
+    pub tokens: Tokens,
+    pub target: SpawnTarget,
+}
+
+block_stmt!(SpawnStmt
+            new => (target: SpawnTarget)
+            lookaheads => (TokenType::KwSpawn));
+
+impl SpawnStmt {
+    /// Gets the name of the function that this statement spawns. Blocks are given a name that
+    /// can't be used by any other function.
+    pub fn fun_name(&self) -> String {
+        match self.target {
+            SpawnTarget::Fun(ref name) => name.clone(),
+            SpawnTarget::Block(ref block) => format!("<spawn block at {}>", block.range()),
+        }
+    }
+}
+
 //
 // Top level statements
 //
@@ -578,6 +700,7 @@ pub enum TopLevel {
     BCFunDef(BCFunDef),
     Import(Import),
     Foreign(Foreign),
+    RecordDef(RecordDef),
 }
 
 #[derive(Clone)]
@@ -664,6 +787,84 @@ impl Debug for Import {
     }
 }
 
+/// The C types that foreign functions may take and return.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ForeignType {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    /// C's `int`.
+    Int,
+    /// C's `long`.
+    Long,
+    SizeT,
+    Float,
+    Double,
+    Char,
+    Bool,
+    /// A NUL-terminated `char*`.
+    String,
+    /// Any other pointer.
+    Ptr,
+    Void,
+}
+
+impl ForeignType {
+    /// Gets the foreign type with the given name, as it's written in a foreign block.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let ty = match name {
+            "i8" => ForeignType::I8,
+            "i16" => ForeignType::I16,
+            "i32" => ForeignType::I32,
+            "i64" => ForeignType::I64,
+            "u8" => ForeignType::U8,
+            "u16" => ForeignType::U16,
+            "u32" => ForeignType::U32,
+            "u64" => ForeignType::U64,
+            "int" => ForeignType::Int,
+            "long" => ForeignType::Long,
+            "size_t" => ForeignType::SizeT,
+            "float" => ForeignType::Float,
+            "double" => ForeignType::Double,
+            "char" => ForeignType::Char,
+            "bool" => ForeignType::Bool,
+            "string" => ForeignType::String,
+            "ptr" => ForeignType::Ptr,
+            "void" => ForeignType::Void,
+            _ => return None,
+        };
+        Some(ty)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ForeignType::I8 => "i8",
+            ForeignType::I16 => "i16",
+            ForeignType::I32 => "i32",
+            ForeignType::I64 => "i64",
+            ForeignType::U8 => "u8",
+            ForeignType::U16 => "u16",
+            ForeignType::U32 => "u32",
+            ForeignType::U64 => "u64",
+            ForeignType::Int => "int",
+            ForeignType::Long => "long",
+            ForeignType::SizeT => "size_t",
+            ForeignType::Float => "float",
+            ForeignType::Double => "double",
+            ForeignType::Char => "char",
+            ForeignType::Bool => "bool",
+            ForeignType::String => "string",
+            ForeignType::Ptr => "ptr",
+            ForeignType::Void => "void",
+        }
+    }
+}
+
 #[derive(Clone)]
 #[cfg_attr(not(test), derive(PartialEq, Debug))]
 pub struct ForeignFun {
@@ -673,9 +874,11 @@ pub struct ForeignFun {
     /// Name of the library where the foreign function exists.
     pub lib: String,
     /// List of the parameters that this call takes.
-    pub params: Vec<ItemType>,
+    pub params: Vec<ForeignType>,
+    /// Whether the function takes extra arguments after its parameters, like `printf`.
+    pub variadic: bool,
     /// The return type of the function.
-    pub return_type: ItemType,
+    pub return_type: ForeignType,
 }
 
 impl ForeignFun {
@@ -683,14 +886,16 @@ impl ForeignFun {
         tokens: Tokens,
         name: String,
         lib: String,
-        params: Vec<ItemType>,
-        return_type: ItemType,
+        params: Vec<ForeignType>,
+        variadic: bool,
+        return_type: ForeignType,
     ) -> Self {
         ForeignFun {
             tokens,
             name,
             lib,
             params,
+            variadic,
             return_type,
         }
     }
@@ -711,10 +916,11 @@ impl Debug for ForeignFun {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(
             f,
-            "ForeignFun {{ name: {} lib: {} params: {:?} return_type: {:?} }}",
+            "ForeignFun {{ name: {} lib: {} params: {:?} variadic: {} return_type: {:?} }}",
             self.name,
             self.lib,
             self.params,
+            self.variadic,
             self.return_type
         )
     }
@@ -724,7 +930,7 @@ impl Debug for ForeignFun {
 impl PartialEq for ForeignFun {
     fn eq(&self, other: &Self) -> bool {
         self.name == other.name && self.lib == other.lib && self.params == other.params &&
-            self.return_type == other.return_type
+            self.variadic == other.variadic && self.return_type == other.return_type
     }
 }
 
@@ -733,11 +939,23 @@ impl PartialEq for ForeignFun {
 pub struct Foreign {
     pub tokens: Tokens,
     pub functions: Vec<ForeignFun>,
+    pub structs: Vec<ForeignStruct>,
+    pub callbacks: Vec<ForeignCallback>,
 }
 
 impl Foreign {
-    pub fn new(tokens: Tokens, functions: Vec<ForeignFun>) -> Self {
-        Foreign { tokens, functions }
+    pub fn new(
+        tokens: Tokens,
+        functions: Vec<ForeignFun>,
+        structs: Vec<ForeignStruct>,
+        callbacks: Vec<ForeignCallback>,
+    ) -> Self {
+        Foreign {
+            tokens,
+            functions,
+            structs,
+            callbacks,
+        }
     }
 }
 
@@ -754,14 +972,176 @@ impl ASTNode for Foreign {
 #[cfg(test)]
 impl Debug for Foreign {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "Foreign {{ functions: {:?} }}", self.functions)
+        write!(
+            f,
+            "Foreign {{ functions: {:?} structs: {:?} callbacks: {:?} }}",
+            self.functions,
+            self.structs,
+            self.callbacks
+        )
     }
 }
 
 #[cfg(test)]
 impl PartialEq for Foreign {
     fn eq(&self, other: &Self) -> bool {
-        self.functions == other.functions
+        self.functions == other.functions && self.structs == other.structs &&
+            self.callbacks == other.callbacks
+    }
+}
+
+/// A C struct that was declared in a foreign block.
+#[derive(Clone)]
+#[cfg_attr(not(test), derive(PartialEq, Debug))]
+pub struct ForeignStruct {
+    pub tokens: Tokens,
+    /// Name of the struct.
+    pub name: String,
+    /// Names and types of the struct's fields, in the order that C lays them out.
+    pub fields: Vec<(String, ForeignType)>,
+}
+
+impl ForeignStruct {
+    pub fn new(tokens: Tokens, name: String, fields: Vec<(String, ForeignType)>) -> Self {
+        ForeignStruct {
+            tokens,
+            name,
+            fields,
+        }
+    }
+}
+
+impl ASTNode for ForeignStruct {
+    fn tokens(&self) -> &[RcToken] {
+        &self.tokens
+    }
+
+    fn lookaheads() -> &'static [TokenType] {
+        lookaheads!(TokenType::KwStruct)
+    }
+}
+
+#[cfg(test)]
+impl Debug for ForeignStruct {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "ForeignStruct {{ name: {:?} fields: {:?} }}", self.name, self.fields)
+    }
+}
+
+#[cfg(test)]
+impl PartialEq for ForeignStruct {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.fields == other.fields
+    }
+}
+
+/// An SBL function that C may call back into, declared in a foreign block along with the C
+/// signature that it's called with.
+#[derive(Clone)]
+#[cfg_attr(not(test), derive(PartialEq, Debug))]
+pub struct ForeignCallback {
+    pub tokens: Tokens,
+    /// Name of the SBL function that gets called.
+    pub name: String,
+    /// List of the parameters that C passes to the callback.
+    pub params: Vec<ForeignType>,
+    /// The type that the callback returns to C.
+    pub return_type: ForeignType,
+}
+
+impl ForeignCallback {
+    pub fn new(
+        tokens: Tokens,
+        name: String,
+        params: Vec<ForeignType>,
+        return_type: ForeignType,
+    ) -> Self {
+        ForeignCallback {
+            tokens,
+            name,
+            params,
+            return_type,
+        }
+    }
+
+    /// Gets the name of the function that pushes a pointer to this callback.
+    pub fn ptr_name(&self) -> String {
+        format!("&{}", self.name)
+    }
+}
+
+impl ASTNode for ForeignCallback {
+    fn tokens(&self) -> &[RcToken] {
+        &self.tokens
+    }
+
+    fn lookaheads() -> &'static [TokenType] {
+        lookaheads!(TokenType::KwCallback)
+    }
+}
+
+#[cfg(test)]
+impl Debug for ForeignCallback {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ForeignCallback {{ name: {} params: {:?} return_type: {:?} }}",
+            self.name,
+            self.params,
+            self.return_type
+        )
+    }
+}
+
+#[cfg(test)]
+impl PartialEq for ForeignCallback {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.params == other.params &&
+            self.return_type == other.return_type
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(not(test), derive(PartialEq, Debug))]
+pub struct RecordDef {
+    pub tokens: Tokens,
+    /// Name of the record type.
+    pub name: String,
+    /// Names of the record's fields, in the order that the constructor pops them.
+    pub fields: Vec<String>,
+}
+
+impl RecordDef {
+    pub fn new(tokens: Tokens, name: String, fields: Vec<String>) -> Self {
+        RecordDef {
+            tokens,
+            name,
+            fields,
+        }
+    }
+}
+
+impl ASTNode for RecordDef {
+    fn tokens(&self) -> &[RcToken] {
+        &self.tokens
+    }
+
+    fn lookaheads() -> &'static [TokenType] {
+        lookaheads!(TokenType::KwRecord)
+    }
+}
+
+#[cfg(test)]
+impl Debug for RecordDef {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "RecordDef {{ name: {:?} fields: {:?} }}", self.name, self.fields)
+    }
+}
+
+#[cfg(test)]
+impl PartialEq for RecordDef {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.fields == other.fields
     }
 }
 
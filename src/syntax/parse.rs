@@ -1,5 +1,15 @@
 use prelude::*;
 
+/// Gives a clearer error than an unexpected token when a keyword is used where an identifier was
+/// expected.
+fn check_reserved(curr: &Token, expected: &[TokenType]) -> Result<()> {
+    if curr.token_type().is_keyword() && expected.contains(&TokenType::Ident) {
+        Err(format!("`{}` is a reserved word", curr.as_str()).into())
+    } else {
+        Ok(())
+    }
+}
+
 pub struct Parser<'c> {
     tokenizer: Tokenizer<'c>,
     curr: Option<Token>,
@@ -38,6 +48,25 @@ impl<'c> Parser<'c> {
         Ok(ast)
     }
 
+    /// Parses a list of statements, like the inside of a function body.
+    pub fn parse_stmts(&mut self) -> Result<Vec<Stmt>> {
+        let mut stmts = vec![];
+        while !self.is_end() {
+            let stmt = self.expect_stmt();
+            if stmt.is_err() {
+                let ref tokenizer = self.tokenizer;
+                let curr_range = self.curr.as_ref().map(Token::range).unwrap_or(Range::eof(
+                    tokenizer.source_path(),
+                    tokenizer.source_text(),
+                ));
+                stmt.chain_err(|| curr_range)?;
+            } else {
+                stmts.push(stmt.unwrap())
+            }
+        }
+        Ok(stmts)
+    }
+
     fn match_token(&mut self, token_type: TokenType) -> Result<Token> {
         let curr = self.expect_curr()
             .chain_err(|| format!("expected token type `{}`", token_type))?
@@ -46,6 +75,7 @@ impl<'c> Parser<'c> {
             self.next_token()?;
             Ok(curr)
         } else {
+            check_reserved(&curr, &[token_type])?;
             Err(
                 format!(
                     "expected token type `{}`; got `{}` instead",
@@ -72,6 +102,7 @@ impl<'c> Parser<'c> {
             self.next_token()?;
             Ok(curr)
         } else {
+            check_reserved(&curr, token_types)?;
             let mut expected_types = token_types
                 .iter()
                 .map(|t| format!("`{}`", t))
@@ -140,11 +171,14 @@ impl<'c> Parser<'c> {
             Ok(TopLevel::BCFunDef(self.expect_fun()?))
         } else if self.can_match_any(Foreign::lookaheads()) {
             Ok(TopLevel::Foreign(self.expect_foreign()?))
+        } else if self.can_match_any(RecordDef::lookaheads()) {
+            Ok(TopLevel::RecordDef(self.expect_record()?))
         } else {
             let mut all = vec![];
             all.extend_from_slice(BCFunDef::lookaheads());
             all.extend_from_slice(Import::lookaheads());
             all.extend_from_slice(Foreign::lookaheads());
+            all.extend_from_slice(RecordDef::lookaheads());
             self.match_any(all.as_slice())?;
             unreachable!()
         }
@@ -167,29 +201,100 @@ impl<'c> Parser<'c> {
         // require braces
         tokens.push(self.match_token(TokenType::LBrace)?.into_rc());
         let mut funs = vec![];
+        let mut structs = vec![];
+        let mut callbacks = vec![];
         while !self.can_match_token(TokenType::RBrace) && self.curr.is_some() {
-            let foreign_fn = self.expect_foreign_fun(&lib)?;
-            tokens.append_node(&foreign_fn);
-            funs.push(foreign_fn);
+            if self.can_match_any(ForeignStruct::lookaheads()) {
+                let foreign_struct = self.expect_foreign_struct()?;
+                tokens.append_node(&foreign_struct);
+                structs.push(foreign_struct);
+            } else if self.can_match_any(ForeignCallback::lookaheads()) {
+                let callback = self.expect_foreign_callback()?;
+                tokens.append_node(&callback);
+                callbacks.push(callback);
+            } else {
+                let foreign_fn = self.expect_foreign_fun(&lib)?;
+                tokens.append_node(&foreign_fn);
+                funs.push(foreign_fn);
+            }
         }
         tokens.push(self.match_token(TokenType::RBrace)?.into_rc());
-        Ok(Foreign::new(tokens, funs))
+        Ok(Foreign::new(tokens, funs, structs, callbacks))
     }
 
-    fn expect_foreign_fun(&mut self, lib: &str) -> Result<ForeignFun> {
-        fn type_map(name: &str) -> Result<ItemType> {
-            match name {
-                "int" => Ok(ItemType::Int(0)),
-                "char" => Ok(ItemType::Char('\0')),
-                "string" => Ok(ItemType::String(String::new())),
-                "bool" => Ok(ItemType::Bool(false)),
-                "void" => Ok(ItemType::Nil),
-                t => Err(format!("unknown foreign type `{}`", t).into()),
+    fn expect_foreign_struct(&mut self) -> Result<ForeignStruct> {
+        let mut tokens = vec![self.match_any(ForeignStruct::lookaheads())?.into_rc()];
+        let name_token = self.match_token(TokenType::Ident)?;
+        let name = String::from(name_token.as_str());
+        tokens.push(name_token.into_rc());
+        tokens.push(self.match_token(TokenType::LBrace)?.into_rc());
+        let mut fields: Vec<(String, ForeignType)> = vec![];
+        while !self.can_match_token(TokenType::RBrace) && self.curr.is_some() {
+            let type_token = self.match_token(TokenType::Ident)?;
+            let field_type = match ForeignType::from_name(type_token.as_str()) {
+                Some(ForeignType::String) | Some(ForeignType::Void) => {
+                    return Err(
+                        format!(
+                            "struct fields may not be of type `{}`",
+                            type_token.as_str()
+                        ).into(),
+                    )
+                }
+                Some(t) => t,
+                None => return Err(format!("unknown foreign type `{}`", type_token.as_str()).into()),
+            };
+            let field_token = self.match_token(TokenType::Ident)?;
+            if fields.iter().any(|&(ref f, _)| f == field_token.as_str()) {
+                return Err(
+                    format!(
+                        "field `{}` has already been defined for struct `{}`",
+                        field_token.as_str(),
+                        name
+                    ).into(),
+                );
             }
+            fields.push((field_token.as_str().to_string(), field_type));
+            tokens.push(type_token.into_rc());
+            tokens.push(field_token.into_rc());
+        }
+        tokens.push(self.match_token(TokenType::RBrace)?.into_rc());
+        if fields.is_empty() {
+            return Err(format!("struct `{}` has no fields", name).into());
         }
+        Ok(ForeignStruct::new(tokens, name, fields))
+    }
 
+    fn expect_foreign_callback(&mut self) -> Result<ForeignCallback> {
+        let mut tokens = vec![self.match_any(ForeignCallback::lookaheads())?.into_rc()];
         let return_type_token = self.match_token(TokenType::Ident)?;
-        let return_type = type_map(return_type_token.as_str())?;
+        let return_type = foreign_type(return_type_token.as_str())?;
+        if return_type == ForeignType::String {
+            // nothing would own the string once the callback returns
+            return Err("callbacks may not return `string`; use `ptr` instead".into());
+        }
+
+        let name_token = self.match_token(TokenType::Ident)?;
+        let name = String::from(name_token.as_str());
+        tokens.push(return_type_token.into_rc());
+        tokens.push(name_token.into_rc());
+        tokens.push(self.match_token(TokenType::LBrack)?.into_rc());
+        let mut params = vec![];
+        while !self.can_match_token(TokenType::RBrack) && self.curr.is_some() {
+            let param_token = self.match_token(TokenType::Ident)?;
+            let param = foreign_type(param_token.as_str())?;
+            if param == ForeignType::Void {
+                return Err("`void` may only be used as the return type of a callback".into());
+            }
+            params.push(param);
+            tokens.push(param_token.into_rc());
+        }
+        tokens.push(self.match_token(TokenType::RBrack)?.into_rc());
+        Ok(ForeignCallback::new(tokens, name, params, return_type))
+    }
+
+    fn expect_foreign_fun(&mut self, lib: &str) -> Result<ForeignFun> {
+        let return_type_token = self.match_token(TokenType::Ident)?;
+        let return_type = foreign_type(return_type_token.as_str())?;
 
         let name_token = self.match_token(TokenType::Ident)?;
         let name = String::from(name_token.as_str());
@@ -200,10 +305,21 @@ impl<'c> Parser<'c> {
             self.match_token(TokenType::LBrack)?.into_rc(),
         ];
         let mut params = vec![];
+        let mut variadic = false;
         // go through all of the types
         while !self.can_match_token(TokenType::RBrack) && self.curr.is_some() {
+            // extra arguments may only come after the rest of the parameters
+            if self.can_match_token(TokenType::Ellipsis) {
+                tokens.push(self.match_token(TokenType::Ellipsis)?.into_rc());
+                variadic = true;
+                break;
+            }
             let param_token = self.match_token(TokenType::Ident)?;
-            params.push(type_map(param_token.as_str())?);
+            let param = foreign_type(param_token.as_str())?;
+            if param == ForeignType::Void {
+                return Err("`void` may only be used as the return type of a foreign function".into());
+            }
+            params.push(param);
             tokens.push(param_token.into_rc());
         }
         tokens.push(self.match_token(TokenType::RBrack)?.into_rc());
@@ -212,10 +328,36 @@ impl<'c> Parser<'c> {
             name,
             lib.to_string(),
             params,
+            variadic,
             return_type,
         ))
     }
 
+    fn expect_record(&mut self) -> Result<RecordDef> {
+        let mut tokens = vec![self.match_any(RecordDef::lookaheads())?.into_rc()];
+        let name_token = self.match_token(TokenType::Ident)?;
+        let name = String::from(name_token.as_str());
+        tokens.push(name_token.into_rc());
+        tokens.push(self.match_token(TokenType::LBrace)?.into_rc());
+        let mut fields: Vec<String> = vec![];
+        while !self.can_match_token(TokenType::RBrace) && self.curr.is_some() {
+            let field_token = self.match_token(TokenType::Ident)?;
+            if fields.iter().any(|f| f == field_token.as_str()) {
+                return Err(
+                    format!(
+                        "field `{}` has already been defined for record `{}`",
+                        field_token.as_str(),
+                        name
+                    ).into(),
+                );
+            }
+            fields.push(field_token.as_str().to_string());
+            tokens.push(field_token.into_rc());
+        }
+        tokens.push(self.match_token(TokenType::RBrace)?.into_rc());
+        Ok(RecordDef::new(tokens, name, fields))
+    }
+
     fn expect_fun(&mut self) -> Result<BCFunDef> {
         let mut tokens = vec![self.match_any(BCFunDef::lookaheads())?.into_rc()];
         let name = tokens[0].as_str().to_string();
@@ -235,6 +377,12 @@ impl<'c> Parser<'c> {
             Ok(Stmt::Stack(self.expect_stack_stmt()?))
         } else if self.can_match_any(BakeStmt::lookaheads()) {
             Ok(Stmt::Bake(self.expect_bake_stmt()?))
+        } else if self.can_match_any(MatchStmt::lookaheads()) {
+            Ok(Stmt::Match(self.expect_match_stmt()?))
+        } else if self.can_match_any(TryStmt::lookaheads()) {
+            Ok(Stmt::Try(self.expect_try_stmt()?))
+        } else if self.can_match_any(SpawnStmt::lookaheads()) {
+            Ok(Stmt::Spawn(self.expect_spawn_stmt()?))
         } else {
             self.match_any(Stmt::lookaheads())?;
             unreachable!()
@@ -315,6 +463,79 @@ impl<'c> Parser<'c> {
         Ok(ElStmt::new(tokens, block))
     }
 
+    fn expect_try_stmt(&mut self) -> Result<TryStmt> {
+        let mut tokens = vec![self.match_any(TryStmt::lookaheads())?.into_rc()];
+        let block = self.expect_block()?;
+        tokens.append_node(&block);
+        let catch_stmt = self.expect_catch_stmt()?;
+        tokens.append_node(&catch_stmt);
+        Ok(TryStmt::new(tokens, block, catch_stmt))
+    }
+
+    fn expect_catch_stmt(&mut self) -> Result<CatchStmt> {
+        let mut tokens = vec![self.match_any(CatchStmt::lookaheads())?.into_rc()];
+        let block = self.expect_block()?;
+        tokens.append_node(&block);
+        Ok(CatchStmt::new(tokens, block))
+    }
+
+    fn expect_match_stmt(&mut self) -> Result<MatchStmt> {
+        let mut tokens = vec![self.match_any(MatchStmt::lookaheads())?.into_rc()];
+        let binding_token = self.match_token(TokenType::Dot)
+            .and_then(|dot| {
+                tokens.push(dot.into_rc());
+                self.match_token(TokenType::Ident)
+            })
+            .chain_err(|| "match statements must store the matched value in a local")?;
+        let binding = binding_token.as_str().to_string();
+        tokens.push(binding_token.into_rc());
+        tokens.push(self.match_token(TokenType::LBrace)?.into_rc());
+        let mut arms = vec![];
+        while self.can_match_any(MatchArm::lookaheads()) {
+            let arm = self.expect_match_arm()?;
+            tokens.append_node(&arm);
+            arms.push(arm);
+        }
+        let el_stmt = if self.can_match_any(ElStmt::lookaheads()) {
+            let el_stmt = self.expect_el_stmt()?;
+            tokens.append_node(&el_stmt);
+            Some(el_stmt)
+        } else {
+            None
+        };
+        tokens.push(self.match_token(TokenType::RBrace)?.into_rc());
+        Ok(MatchStmt::new(tokens, binding, arms, el_stmt))
+    }
+
+    fn expect_match_arm(&mut self) -> Result<MatchArm> {
+        let mut tokens = vec![];
+        let mut types = vec![];
+        while self.can_match_any(MatchArm::lookaheads()) {
+            let type_token = self.match_token(TokenType::Ident)?;
+            types.push(type_token.as_str().to_string());
+            tokens.push(type_token.into_rc());
+        }
+        let block = self.expect_block()?;
+        tokens.append_node(&block);
+        Ok(MatchArm::new(tokens, types, block))
+    }
+
+    fn expect_spawn_stmt(&mut self) -> Result<SpawnStmt> {
+        let mut tokens = vec![self.match_any(SpawnStmt::lookaheads())?.into_rc()];
+        let target = if self.can_match_any(Block::lookaheads()) {
+            let block = self.expect_block()?;
+            tokens.append_node(&block);
+            SpawnTarget::Block(block)
+        } else {
+            let name_token = self.match_token(TokenType::Ident)
+                .chain_err(|| "spawn statements must be followed by a function name or a block")?;
+            let name = name_token.as_str().to_string();
+            tokens.push(name_token.into_rc());
+            SpawnTarget::Fun(name)
+        };
+        Ok(SpawnStmt::new(tokens, target))
+    }
+
     fn expect_bake_stmt(&mut self) -> Result<BakeStmt> {
         let mut tokens = vec![self.match_any(BakeStmt::lookaheads())?.into_rc()];
         let block = self.expect_block()?;
@@ -331,6 +552,9 @@ impl<'c> Parser<'c> {
                 TokenType::KwBr,
                 TokenType::KwLoop,
                 TokenType::KwBake,
+                TokenType::KwMatch,
+                TokenType::KwTry,
+                TokenType::KwSpawn,
             ],
         ) && self.curr.is_some()
         {
@@ -349,6 +573,10 @@ impl<'c> Parser<'c> {
         if let Some(item) = self.try_item() {
             Ok(StackAction::Push(item))
         } else {
+            // a keyword that doesn't start a statement can only have been meant as an item
+            if let Some(ref curr) = self.curr {
+                check_reserved(curr, &Item::lookaheads())?;
+            }
             let mut tokens = vec![self.match_any(StackAction::lookaheads())?.into_rc()];
             let item = self.expect_item()?;
             tokens.append_node(&item);
@@ -371,6 +599,35 @@ impl<'c> Parser<'c> {
                 tokens.push(self.match_token(TokenType::RBrack)?.into_rc());
                 Ok(Item::new(tokens, ItemType::Stack(items)))
             }
+            TokenType::MapLBrack => {
+                let mut tokens = vec![token.into_rc()];
+                // match a map item, made of key-value pairs
+                let mut pairs = vec![];
+                while !self.can_match_token(TokenType::RBrack) {
+                    let key = self.expect_item()?;
+                    match key.item_type {
+                        ItemType::Int(_) | ItemType::String(_) | ItemType::Char(_) |
+                        ItemType::Bool(_) | ItemType::Ident(_) => {}
+                        ref t => {
+                            return Err(
+                                format!(
+                                    "map keys must be ints, strings, chars, or bools; got {} instead",
+                                    t.type_string()
+                                ).into(),
+                            )
+                        }
+                    }
+                    if self.can_match_token(TokenType::RBrack) {
+                        return Err("expected a value for the last key of this map".into());
+                    }
+                    let val = self.expect_item()?;
+                    tokens.append_node(&key);
+                    tokens.append_node(&val);
+                    pairs.push((key, val));
+                }
+                tokens.push(self.match_token(TokenType::RBrack)?.into_rc());
+                Ok(Item::new(tokens, ItemType::Map(pairs)))
+            }
             _ => Ok(token.into()),
         }
     }
@@ -385,6 +642,11 @@ impl<'c> Parser<'c> {
     }
 }
 
+/// Gets the foreign type with the given name, as it's written in a foreign block.
+fn foreign_type(name: &str) -> Result<ForeignType> {
+    ForeignType::from_name(name).ok_or_else(|| format!("unknown foreign type `{}`", name).into())
+}
+
 #[cfg(test)]
 mod test {
     use syntax::token::*;
@@ -414,6 +676,9 @@ mod test {
         (BCFunDef $($tail:tt)+) => { TopLevel::BCFunDef(fun!($($tail)+)) };
         (Import $($tail:tt)+) => { TopLevel::Import(import!($($tail)+)) };
         (Foreign $($tail:tt)+) => { TopLevel::Foreign(foreign!($($tail)+)) };
+        (RecordDef $name:ident { $($field:ident)* }) => {
+            TopLevel::RecordDef(RecordDef::new(vec![], stringify!($name).to_string(), vec![$(stringify!($field).to_string()),*]))
+        };
     }
 
     macro_rules! fun {
@@ -426,14 +691,14 @@ mod test {
 
     macro_rules! foreign {
         ($path:expr ; { $($tail:tt)* }) => {
-            Foreign::new(vec![], foreign_fn!($path ; $($tail)*) )
+            Foreign::new(vec![], foreign_fn!($path ; $($tail)*), vec![], vec![])
         };
     }
 
     macro_rules! foreign_fn {
         ($path:expr ; $type:ident $name:ident [ $($params:ident)* ] $($tail:tt)*) => {{
             let mut v = vec![
-                ForeignFun::new(vec![], stringify!($name).to_string(), $path.to_string(), vec![$(param!($params)),*], param!($type))
+                ForeignFun::new(vec![], stringify!($name).to_string(), $path.to_string(), vec![$(param!($params)),*], false, param!($type))
             ];
             v.append(&mut foreign_fn!($path ; $($tail)*));
             v
@@ -442,11 +707,7 @@ mod test {
     }
 
     macro_rules! param {
-        (int) => { ItemType::Int(0) };
-        (char) => { ItemType::Char('\0') };
-        (string) => { ItemType::String(String::new()) };
-        (bool) => { ItemType::Bool(false) };
-        (void) => { ItemType::Nil };
+        ($type:ident) => { ForeignType::from_name(stringify!($type)).unwrap() };
     }
 
     macro_rules! stmt {
@@ -543,6 +804,12 @@ mod test {
                     )
                 )
         };
+        (Map [$((($($key:tt)+) ($($val:tt)+)))*]) => {
+            Item::new(vec![], ItemType::Map(
+                        vec![ $((item!($($key)+), item!($($val)+))),* ]
+                    )
+                )
+        };
         ($type:ident $value:expr) => { Item::new(vec![], ItemType::$type($value)) };
     }
 
@@ -558,6 +825,9 @@ mod test {
                 int close [ int ]
             }
 
+            record point { x y }
+            record empty {}
+
             foo {
                 1 2 3 .a .b .c
                 # .@
@@ -595,6 +865,8 @@ mod test {
                 int open [ string string ]
                 int close [ int ]
             }))
+            (expect_top_level, top_level!(RecordDef point { x y }))
+            (expect_top_level, top_level!(RecordDef empty {}))
             (expect_top_level, top_level!(BCFunDef "foo" => {
                 (Stack Push Int 1 Push Int 2 Push Int 3 Pop Ident "a" Pop Ident "b" Pop Ident "c"
                        Push Ident "#" Pop Nil
@@ -744,6 +1016,8 @@ mod test {
             "this is a boring string without escapes"
             T F
             [ this is a stack ]
+            %[ 1 "one" 'b [ 2 ] key value ]
+            %[ ]
             @
             "#,
 
@@ -766,7 +1040,252 @@ mod test {
                               (Ident "stack")
                             ])
              )
+            (expect_item, item!(Map [
+                              ((Int 1) (String "one"))
+                              ((Char 'b') (Stack [(Int 2)]))
+                              ((Ident "key") (Ident "value"))
+                            ])
+             )
+            (expect_item, item!(Map []))
             (expect_item, item!(Nil))
         };
     }
+
+    #[test]
+    fn test_parser_map_errors() {
+        let mut p = Parser::new(Tokenizer::new("test", "%[ 1 2 3 ]"));
+        assert!(p.expect_item().is_err());
+        let mut p = Parser::new(Tokenizer::new("test", "%[ [ 1 ] 2 ]"));
+        assert!(p.expect_item().is_err());
+    }
+
+    #[test]
+    fn test_parser_record_errors() {
+        let mut p = Parser::new(Tokenizer::new("test", "record point { x y x }"));
+        assert!(p.expect_top_level().is_err());
+        let mut p = Parser::new(Tokenizer::new("test", "record point { x 1 }"));
+        assert!(p.expect_top_level().is_err());
+    }
+
+    #[test]
+    fn test_parser_reserved_words() {
+        for &(code, word) in &[
+            ("main { 1 record }", "record"),
+            ("record loop { }", "loop"),
+            ("record match { }", "match"),
+            ("main { 1 .match }", "match"),
+            ("try { }", "try"),
+            ("record point { x catch }", "catch"),
+            ("main { 1 .spawn }", "spawn"),
+            ("foreign \"libc.so.6\" { int abs [ int struct ] }", "struct"),
+            ("callback { }", "callback"),
+        ] {
+            let mut p = Parser::new(Tokenizer::new("test", code));
+            let err = p.parse().unwrap_err();
+            let message = err.iter().map(ToString::to_string).collect::<Vec<_>>().join(": ");
+            assert!(
+                message.contains(&format!("`{}` is a reserved word", word)),
+                "{:?} gave {:?}",
+                code,
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn test_parser_match() {
+        let mut p = Parser::new(Tokenizer::new("test", "match .v { int char { 1 } point { } el { 2 } }"));
+        let stmt = MatchStmt::from(p.expect_stmt().unwrap());
+        assert_eq!(stmt.binding, "v");
+        assert_eq!(stmt.arms, vec![
+            MatchArm::new(vec![], vec!["int".to_string(), "char".to_string()], block!((Stack Push Int 1))),
+            MatchArm::new(vec![], vec!["point".to_string()], block!()),
+        ]);
+        assert_eq!(stmt.el_stmt, Some(ElStmt::new(vec![], block!((Stack Push Int 2)))));
+        // the matched value has to be stored in a local
+        let mut p = Parser::new(Tokenizer::new("test", "match { int { } }"));
+        assert!(p.expect_stmt().is_err());
+        let mut p = Parser::new(Tokenizer::new("test", "match .v { el { } int { } }"));
+        assert!(p.expect_stmt().is_err());
+    }
+
+    #[test]
+    fn test_parser_try() {
+        let mut p = Parser::new(Tokenizer::new("test", "try { 1 throw } catch { .e }"));
+        let stmt = TryStmt::from(p.expect_stmt().unwrap());
+        assert_eq!(stmt.block, block!((Stack Push Int 1 Push Ident "throw")));
+        assert_eq!(stmt.catch_stmt, CatchStmt::new(vec![], block!((Stack Pop Ident "e"))));
+        // every try needs a catch
+        let mut p = Parser::new(Tokenizer::new("test", "try { 1 }"));
+        assert!(p.expect_stmt().is_err());
+    }
+
+    #[test]
+    fn test_parser_spawn() {
+        let mut p = Parser::new(Tokenizer::new("test", "spawn counter"));
+        let stmt = SpawnStmt::from(p.expect_stmt().unwrap());
+        assert_eq!(stmt.target, SpawnTarget::Fun("counter".to_string()));
+        assert_eq!(stmt.fun_name(), "counter");
+        let mut p = Parser::new(Tokenizer::new("test", "spawn { 1 yield }"));
+        let stmt = SpawnStmt::from(p.expect_stmt().unwrap());
+        assert_eq!(stmt.target, SpawnTarget::Block(block!((Stack Push Int 1 Push Ident "yield"))));
+        // spawns need something to run
+        let mut p = Parser::new(Tokenizer::new("test", "spawn 1"));
+        assert!(p.expect_stmt().is_err());
+    }
+
+    #[test]
+    fn test_parser_foreign_types() {
+        tests! {
+            r#"
+            foreign "libc.so.6" {
+                size_t strlen [ string ]
+                long labs [ long ]
+                double pow [ double double ]
+                float sqrtf [ float ]
+                void srand [ u32 ]
+                i64 sum [ i8 i16 i32 i64 u8 u16 u64 ]
+                string strchr [ string int ]
+                ptr memset [ ptr int size_t ]
+            }
+            "#,
+
+            (expect_top_level, top_level!(Foreign "libc.so.6" ; {
+                size_t strlen [ string ]
+                long labs [ long ]
+                double pow [ double double ]
+                float sqrtf [ float ]
+                void srand [ u32 ]
+                i64 sum [ i8 i16 i32 i64 u8 u16 u64 ]
+                string strchr [ string int ]
+                ptr memset [ ptr int size_t ]
+            }))
+        };
+        let mut p = Parser::new(Tokenizer::new("test", r#"foreign "libc.so.6" { int abs [ short ] }"#));
+        assert!(p.expect_top_level().is_err());
+        let mut p = Parser::new(Tokenizer::new("test", r#"foreign "libc.so.6" { int rand [ void ] }"#));
+        assert!(p.expect_top_level().is_err());
+    }
+
+    #[test]
+    fn test_parser_foreign_variadic() {
+        let code = r#"foreign "libc.so.6" { int printf [ string ... ] int abs [ int ] }"#;
+        let mut p = Parser::new(Tokenizer::new("test", code));
+        let foreign = match p.expect_top_level().unwrap() {
+            TopLevel::Foreign(foreign) => foreign,
+            top => panic!("expected foreign block, got {:?}", top),
+        };
+        assert_eq!(
+            foreign.functions,
+            vec![
+                ForeignFun::new(
+                    vec![],
+                    "printf".to_string(),
+                    "libc.so.6".to_string(),
+                    vec![ForeignType::String],
+                    true,
+                    ForeignType::Int,
+                ),
+                ForeignFun::new(
+                    vec![],
+                    "abs".to_string(),
+                    "libc.so.6".to_string(),
+                    vec![ForeignType::Int],
+                    false,
+                    ForeignType::Int,
+                ),
+            ]
+        );
+
+        // extra arguments have to come last, and callbacks can't take them
+        let errors = &[
+            "int printf [ ... string ]",
+            "int printf [ string ... ... ]",
+            "callback int f [ int ... ]",
+        ];
+        for code in errors {
+            let code = format!(r#"foreign "libc.so.6" {{ {} }}"#, code);
+            let mut p = Parser::new(Tokenizer::new("test", &code));
+            assert!(p.expect_top_level().is_err(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_parser_foreign_structs() {
+        let code = r#"
+            foreign "libc.so.6" {
+                struct timeval { long tv_sec long tv_usec }
+                int gettimeofday [ ptr ptr ]
+            }
+        "#;
+        let mut p = Parser::new(Tokenizer::new("test", code));
+        let foreign = match p.expect_top_level().unwrap() {
+            TopLevel::Foreign(foreign) => foreign,
+            top => panic!("expected foreign block, got {:?}", top),
+        };
+        assert_eq!(foreign.functions, foreign_fn!("libc.so.6" ; int gettimeofday [ ptr ptr ]));
+        assert_eq!(
+            foreign.structs,
+            vec![ForeignStruct::new(
+                vec![],
+                "timeval".to_string(),
+                vec![("tv_sec".to_string(), ForeignType::Long), ("tv_usec".to_string(), ForeignType::Long)],
+            )]
+        );
+
+        let errors = &[
+            "struct empty { }",
+            "struct s { int x int x }",
+            "struct s { string name }",
+            "struct s { void v }",
+            "struct s { short x }",
+        ];
+        for code in errors {
+            let code = format!(r#"foreign "libc.so.6" {{ {} }}"#, code);
+            let mut p = Parser::new(Tokenizer::new("test", &code));
+            assert!(p.expect_top_level().is_err(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_parser_foreign_callbacks() {
+        let code = r#"
+            foreign "libc.so.6" {
+                callback int compare [ ptr ptr ]
+                callback void done [ ]
+                void qsort [ ptr size_t size_t ptr ]
+            }
+        "#;
+        let mut p = Parser::new(Tokenizer::new("test", code));
+        let foreign = match p.expect_top_level().unwrap() {
+            TopLevel::Foreign(foreign) => foreign,
+            top => panic!("expected foreign block, got {:?}", top),
+        };
+        assert_eq!(foreign.functions, foreign_fn!("libc.so.6" ; void qsort [ ptr size_t size_t ptr ]));
+        assert_eq!(
+            foreign.callbacks,
+            vec![
+                ForeignCallback::new(
+                    vec![],
+                    "compare".to_string(),
+                    vec![ForeignType::Ptr, ForeignType::Ptr],
+                    ForeignType::Int,
+                ),
+                ForeignCallback::new(vec![], "done".to_string(), vec![], ForeignType::Void),
+            ]
+        );
+        assert_eq!(foreign.callbacks[0].ptr_name(), "&compare");
+
+        let errors = &[
+            "callback string name [ ]",
+            "callback int f [ void ]",
+            "callback int f [ short ]",
+            "callback int [ int ]",
+        ];
+        for code in errors {
+            let code = format!(r#"foreign "libc.so.6" {{ {} }}"#, code);
+            let mut p = Parser::new(Tokenizer::new("test", &code));
+            assert!(p.expect_top_level().is_err(), "{}", code);
+        }
+    }
 }
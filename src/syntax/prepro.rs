@@ -33,7 +33,8 @@ impl AST {
         let (ast, errors) = ast.into_iter()
             .map(|top| match top {
                 TopLevel::BCFunDef(_) |
-                TopLevel::Foreign(_) => Ok(vec![top]),
+                TopLevel::Foreign(_) |
+                TopLevel::RecordDef(_) => Ok(vec![top]),
                 TopLevel::Import(i) => i.import(search_dirs),
             })
             .fold((vec![], vec![]), |(mut ast, mut errors), item| {
@@ -52,10 +52,12 @@ pub enum TokenType {
 
     // Symbols
     Dot,
+    Ellipsis,
     LBrace,
     RBrace,
     LBrack,
     RBrack,
+    MapLBrack,
 
     // Keywords
     KwNil,
@@ -68,6 +70,25 @@ pub enum TokenType {
     KwLoop,
     KwForeign,
     KwBake,
+    KwRecord,
+    KwMatch,
+    KwTry,
+    KwCatch,
+    KwSpawn,
+    KwStruct,
+    KwCallback,
+}
+
+impl TokenType {
+    /// Gets whether this is the type of a word that can't be used as an identifier.
+    pub fn is_keyword(&self) -> bool {
+        use self::TokenType::*;
+        match *self {
+            KwImport | KwBr | KwElBr | KwEl | KwT | KwF | KwLoop | KwForeign | KwBake | KwRecord |
+            KwMatch | KwTry | KwCatch | KwSpawn | KwStruct | KwCallback => true,
+            _ => false,
+        }
+    }
 }
 
 impl Display for TokenType {
@@ -84,10 +105,12 @@ impl Display for TokenType {
 
             // Symbols
             Dot => "dot",
+            Ellipsis => "ellipsis",
             LBrace => "lbrace",
             RBrace => "rbrace",
             LBrack => "lbrack",
             RBrack => "rbrack",
+            MapLBrack => "map lbrack",
 
             // Keywords
             KwNil => "nil sigil",
@@ -100,6 +123,13 @@ impl Display for TokenType {
             KwLoop => "loop keyword",
             KwForeign => "foreign keyword",
             KwBake => "compile-time bake keyword",
+            KwRecord => "record keyword",
+            KwMatch => "match keyword",
+            KwTry => "try keyword",
+            KwCatch => "catch keyword",
+            KwSpawn => "spawn keyword",
+            KwStruct => "struct keyword",
+            KwCallback => "callback keyword",
         };
         write!(f, "{}", s)
     }
@@ -415,7 +445,14 @@ impl<'c> Tokenizer<'c> {
         } else {
             self.match_any_char(DEC_DIGITS)?;
             while let Some(_) = self.try_match_any(DEC_DIGITS) {}
-            self.ok_token(TokenType::Int)
+            // a decimal point is only part of the number if a digit comes after it
+            if self.curr == Some('.') && self.next.map(|c| DEC_DIGITS.contains(c)).unwrap_or(false) {
+                self.next_char();
+                while let Some(_) = self.try_match_any(DEC_DIGITS) {}
+                self.ok_token(TokenType::Float)
+            } else {
+                self.ok_token(TokenType::Int)
+            }
         }
     }
 
@@ -465,11 +502,19 @@ impl<'c> Tokenizer<'c> {
                     "F" => TokenType::KwF,
                     "foreign" => TokenType::KwForeign,
                     "bake" => TokenType::KwBake,
+                    "record" => TokenType::KwRecord,
+                    "match" => TokenType::KwMatch,
+                    "try" => TokenType::KwTry,
+                    "catch" => TokenType::KwCatch,
+                    "spawn" => TokenType::KwSpawn,
+                    "struct" => TokenType::KwStruct,
+                    "callback" => TokenType::KwCallback,
                 }
             };
         };
         self.match_any_char(IDENT_CHARS)?;
-        while self.try_match_any(IDENT_CHARS).is_some() {}
+        // digits may show up anywhere but the start, so that an identifier isn't taken for an int
+        while self.try_match_any(IDENT_CHARS).or_else(|| self.try_match_any("0123456789")).is_some() {}
         if let Some(ty) = KEYWORDS.get(self.curr_range.as_str()) {
             self.ok_token(*ty)
         } else {
@@ -477,6 +522,19 @@ impl<'c> Tokenizer<'c> {
         }
     }
 
+    fn next_map_lbrack(&mut self) -> Result<Token> {
+        self.match_char('%')?;
+        self.match_char('[')?;
+        self.ok_token(TokenType::MapLBrack)
+    }
+
+    fn next_ellipsis(&mut self) -> Result<Token> {
+        self.match_char('.')?;
+        self.match_char('.')?;
+        self.match_char('.')?;
+        self.ok_token(TokenType::Ellipsis)
+    }
+
     fn match_single_token(&mut self, c: char, token_type: TokenType) -> Result<Token> {
         self.match_char(c)?;
         self.ok_token(token_type)
@@ -519,12 +577,16 @@ impl<'c> Iterator for Tokenizer<'c> {
             '"' => Some(self.next_string()),
             // char
             '\'' => Some(self.next_character()),
+            // ellipsis
+            '.' if self.next == Some('.') => Some(self.next_ellipsis()),
             // dot
             '.' => Some(self.match_single_token('.', TokenType::Dot)),
             // lbrace
             '{' => Some(self.match_single_token('{', TokenType::LBrace)),
             // rbrace
             '}' => Some(self.match_single_token('}', TokenType::RBrace)),
+            // map lbrack
+            '%' if self.next == Some('[') => Some(self.next_map_lbrack()),
             // lbrack
             '[' => Some(self.match_single_token('[', TokenType::LBrack)),
             // rbrack
@@ -581,6 +643,13 @@ mod test {
             @
             import
             foreign
+            record
+            match
+            try
+            catch
+            spawn
+            struct
+            callback
             "#,
             (TokenType::KwBr)
             (TokenType::KwElBr)
@@ -591,6 +660,13 @@ mod test {
             (TokenType::KwNil)
             (TokenType::KwImport)
             (TokenType::KwForeign)
+            (TokenType::KwRecord)
+            (TokenType::KwMatch)
+            (TokenType::KwTry)
+            (TokenType::KwCatch)
+            (TokenType::KwSpawn)
+            (TokenType::KwStruct)
+            (TokenType::KwCallback)
         };
     }
 
@@ -609,6 +685,7 @@ mod test {
             <=
             >=
             ********
+            u8 size_t
             "#,
 
             (TokenType::Ident, "foo")
@@ -622,6 +699,31 @@ mod test {
             (TokenType::Ident, "<=")
             (TokenType::Ident, ">=")
             (TokenType::Ident, "********")
+            (TokenType::Ident, "u8")
+            (TokenType::Ident, "size_t")
+        };
+    }
+
+    #[test]
+    fn test_lexer_idents_with_digits() {
+        // digits may come after the first character of an identifier, so `x1` and `a-1` are one
+        // identifier each rather than an identifier followed by an int
+        tests! {
+            r#"
+            x1 a-1 v2-b3
+            x 1
+            1x -1a
+            "#,
+
+            (TokenType::Ident, "x1")
+            (TokenType::Ident, "a-1")
+            (TokenType::Ident, "v2-b3")
+            (TokenType::Ident, "x")
+            (TokenType::Int, "1")
+            (TokenType::Int, "1")
+            (TokenType::Ident, "x")
+            (TokenType::Int, "-1")
+            (TokenType::Ident, "a")
         };
     }
 
@@ -629,13 +731,16 @@ mod test {
     fn test_lexer_syms() {
         tests! {
             r#"
-            . [ ] { }
+            . [ ] { } %[ % ...
             "#,
             (TokenType::Dot)
             (TokenType::LBrack)
             (TokenType::RBrack)
             (TokenType::LBrace)
             (TokenType::RBrace)
+            (TokenType::MapLBrack)
+            (TokenType::Ident, "%")
+            (TokenType::Ellipsis)
         };
     }
 
@@ -724,6 +829,25 @@ mod test {
         };
     }
 
+    #[test]
+    fn test_lexer_floats() {
+        tests! {
+            r#"
+            0.5
+            12.25
+            -3.0
+            1 .x
+            "#,
+
+            (TokenType::Float, "0.5")
+            (TokenType::Float, "12.25")
+            (TokenType::Float, "-3.0")
+            (TokenType::Int, "1")
+            (TokenType::Dot, ".")
+            (TokenType::Ident, "x")
+        };
+    }
+
     #[test]
     fn test_lexer_comments() {
         tests! {
@@ -0,0 +1,87 @@
+use prelude::*;
+use std::cell::RefCell;
+use std::fmt::{self, Formatter, Debug, Display};
+use std::rc::Rc;
+
+/// A block of bytes that foreign functions can read from and write into.
+///
+/// Buffers are shared rather than copied, since foreign functions write to them through a pointer;
+/// writing to any copy of a buffer changes all of them.
+#[derive(Clone)]
+pub struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+impl Buffer {
+    /// Creates a buffer of the given size, with every byte set to zero.
+    pub fn new(size: usize) -> Self {
+        Buffer(Rc::new(RefCell::new(vec![0; size])))
+    }
+
+    /// Creates a buffer holding a string's bytes, followed by a NUL byte.
+    pub fn from_str(s: &str) -> Self {
+        let mut bytes = Vec::with_capacity(s.len() + 1);
+        bytes.extend_from_slice(s.as_bytes());
+        bytes.push(0);
+        Buffer(Rc::new(RefCell::new(bytes)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    fn check_offset(&self, offset: usize) -> Result<()> {
+        if offset < self.len() {
+            Ok(())
+        } else {
+            Err(
+                format!(
+                    "offset {} is out of bounds for a buffer of {} bytes",
+                    offset,
+                    self.len()
+                ).into(),
+            )
+        }
+    }
+
+    pub fn get(&self, offset: usize) -> Result<u8> {
+        self.check_offset(offset)?;
+        Ok(self.0.borrow()[offset])
+    }
+
+    pub fn set(&self, offset: usize, byte: u8) -> Result<()> {
+        self.check_offset(offset)?;
+        self.0.borrow_mut()[offset] = byte;
+        Ok(())
+    }
+
+    /// Reads the bytes up to the first NUL byte (or the end of the buffer) as a string.
+    pub fn read_string(&self) -> Result<String> {
+        let bytes = self.0.borrow();
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8(bytes[..end].to_vec())
+            .map_err(|_| "buffer does not hold a valid UTF-8 string".into())
+    }
+
+    /// Gets a pointer to the start of this buffer's bytes. Buffers never change size, so the
+    /// pointer stays good for as long as the buffer is around.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.0.borrow_mut().as_mut_ptr()
+    }
+}
+
+impl PartialEq for Buffer {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Display for Buffer {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "buffer({} bytes)", self.len())
+    }
+}
+
+impl Debug for Buffer {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Buffer({:?})", self.0.borrow())
+    }
+}
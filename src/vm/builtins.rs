@@ -1,6 +1,11 @@
 use prelude::*;
+use libc::{self, c_int};
 use std::collections::BTreeMap;
 use std::cmp::Ordering;
+use std::ffi::CStr;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
 
 pub type BuiltinFun = fn(&mut State) -> Result<()>;
 
@@ -30,6 +35,66 @@ lazy_static! {
             "^len" => len_o,
             "!len" => len_c,
 
+            // Map functions
+            "^get" => get_o,
+            "!get" => get_c,
+            "^set" => set,
+            "^remove" => remove,
+            "^has" => has_o,
+            "!has" => has_c,
+            "^keys" => keys_o,
+            "!keys" => keys_c,
+            "^values" => values_o,
+            "!values" => values_c,
+
+            // Ref functions
+            "ref" => make_ref,
+            "^deref" => deref_o,
+            "!deref" => deref_c,
+            "^assign" => assign_o,
+            "!assign" => assign_c,
+            "^xchg" => xchg_o,
+            "!xchg" => xchg_c,
+
+            // Error functions
+            "throw" => throw,
+            "error" => make_error,
+            "^error-message" => error_message_o,
+            "!error-message" => error_message_c,
+            "^error-range" => error_range_o,
+            "!error-range" => error_range_c,
+
+            // Coroutine functions
+            "^done" => done_o,
+            "!done" => done_c,
+
+            // File functions
+            "open" => open,
+            "stdin" => stdin,
+            "^read-line" => read_line_o,
+            "!read-line" => read_line_c,
+            "^read-all" => read_all_o,
+            "!read-all" => read_all_c,
+            "^write" => write_o,
+            "!write" => write_c,
+            "close" => close,
+            "exists" => exists,
+            "delete" => delete,
+
+            // Buffer functions
+            "buffer" => make_buffer,
+            "string-buffer" => string_buffer,
+            "^byte" => byte_o,
+            "!byte" => byte_c,
+            "^set-byte" => set_byte_o,
+            "!set-byte" => set_byte_c,
+            "^buffer-string" => buffer_string_o,
+            "!buffer-string" => buffer_string_c,
+
+            // errno functions
+            "errno" => errno,
+            "strerror" => strerror,
+
             // Quality of life functions
             "^print" => print_o,
             "!print" => print_c,
@@ -46,66 +111,78 @@ lazy_static! {
 /*
  * Operations
  */
-fn plus(state: &mut State) -> Result<()> {
+pub(in vm) fn plus(state: &mut State) -> Result<()> {
     let lhs = state.pop()?;
     let rhs = state.pop()?;
-    // TODO : addition between different types
-    match lhs {
-        BCVal::Int(i1) => {
-            if let BCVal::Int(i2) = rhs {
-                state.push(BCVal::Int(i1 + i2));
-            }
-        }
-        _ => return Err("Addition between non-integers is not allowed".into()),
-    }
+    let result = match (&lhs, &rhs) {
+        (&BCVal::Int(i1), &BCVal::Int(i2)) => match i1.checked_add(i2) {
+            Some(i) => BCVal::Int(i),
+            None => return Err("integer overflow in addition".into()),
+        },
+        (&BCVal::Float(f1), &BCVal::Float(f2)) => BCVal::Float(f1 + f2),
+        _ => return Err(arithmetic_error("Addition", &lhs, &rhs)),
+    };
+    state.push(result);
     Ok(())
 }
 
-fn minus(state: &mut State) -> Result<()> {
+pub(in vm) fn minus(state: &mut State) -> Result<()> {
     let rhs = state.pop()?;
     let lhs = state.pop()?;
-    // TODO : addition between different types
-    match lhs {
-        BCVal::Int(i1) => {
-            if let BCVal::Int(i2) = rhs {
-                state.push(BCVal::Int(i1 - i2));
-            }
-        }
-        _ => return Err("Subtraction between non-integers is not allowed".into()),
-    }
+    let result = match (&lhs, &rhs) {
+        (&BCVal::Int(i1), &BCVal::Int(i2)) => match i1.checked_sub(i2) {
+            Some(i) => BCVal::Int(i),
+            None => return Err("integer overflow in subtraction".into()),
+        },
+        (&BCVal::Float(f1), &BCVal::Float(f2)) => BCVal::Float(f1 - f2),
+        _ => return Err(arithmetic_error("Subtraction", &lhs, &rhs)),
+    };
+    state.push(result);
     Ok(())
 }
 
-fn times(state: &mut State) -> Result<()> {
+pub(in vm) fn times(state: &mut State) -> Result<()> {
     let lhs = state.pop()?;
     let rhs = state.pop()?;
-    // TODO : addition between different types
-    match lhs {
-        BCVal::Int(i1) => {
-            if let BCVal::Int(i2) = rhs {
-                state.push(BCVal::Int(i1 * i2));
-            }
-        }
-        _ => return Err("Multiplication between non-integers is not allowed".into()),
-    }
+    let result = match (&lhs, &rhs) {
+        (&BCVal::Int(i1), &BCVal::Int(i2)) => match i1.checked_mul(i2) {
+            Some(i) => BCVal::Int(i),
+            None => return Err("integer overflow in multiplication".into()),
+        },
+        (&BCVal::Float(f1), &BCVal::Float(f2)) => BCVal::Float(f1 * f2),
+        _ => return Err(arithmetic_error("Multiplication", &lhs, &rhs)),
+    };
+    state.push(result);
     Ok(())
 }
 
-fn divide(state: &mut State) -> Result<()> {
+pub(in vm) fn divide(state: &mut State) -> Result<()> {
     let rhs = state.pop()?;
     let lhs = state.pop()?;
-    // TODO : addition between different types
-    match lhs {
-        BCVal::Int(i1) => {
-            if let BCVal::Int(i2) = rhs {
-                state.push(BCVal::Int(i1 / i2));
-            }
-        }
-        _ => return Err("Division between non-integers is not allowed".into()),
-    }
+    let result = match (&lhs, &rhs) {
+        (&BCVal::Int(_), &BCVal::Int(0)) => return Err("attempted to divide by zero".into()),
+        (&BCVal::Int(i1), &BCVal::Int(i2)) => match i1.checked_div(i2) {
+            Some(i) => BCVal::Int(i),
+            None => return Err("integer overflow in division".into()),
+        },
+        (&BCVal::Float(f1), &BCVal::Float(f2)) => BCVal::Float(f1 / f2),
+        _ => return Err(arithmetic_error("Division", &lhs, &rhs)),
+    };
+    state.push(result);
     Ok(())
 }
 
+/// Makes the error for an arithmetic operation between values that it doesn't work on. Ints and
+/// floats may not be mixed.
+fn arithmetic_error(op: &str, lhs: &BCVal, rhs: &BCVal) -> Error {
+    format!(
+        "{} between non-integers is not allowed, unless both are floats; got {} and {}",
+        op,
+        lhs.type_string(),
+        rhs.type_string()
+    ).into()
+}
+
 fn bit_or(state: &mut State) -> Result<()> {
     let rhs = state.pop()?;
     let lhs = state.pop()?;
@@ -120,35 +197,35 @@ fn bit_or(state: &mut State) -> Result<()> {
     Ok(())
 }
 
-fn equals(state: &mut State) -> Result<()> {
+pub(in vm) fn equals(state: &mut State) -> Result<()> {
     let lhs = state.pop()?;
     let rhs = state.pop()?;
     state.push(BCVal::Bool(lhs == rhs));
     Ok(())
 }
 
-fn not_equals(state: &mut State) -> Result<()> {
+pub(in vm) fn not_equals(state: &mut State) -> Result<()> {
     let lhs = state.pop()?;
     let rhs = state.pop()?;
     state.push(BCVal::Bool(lhs != rhs));
     Ok(())
 }
 
-fn less_than(state: &mut State) -> Result<()> {
+pub(in vm) fn less_than(state: &mut State) -> Result<()> {
     let lhs = state.pop()?;
     let rhs = state.pop()?;
     state.push(BCVal::Bool(lhs.compare(&rhs)? == Ordering::Less));
     Ok(())
 }
 
-fn greater_than(state: &mut State) -> Result<()> {
+pub(in vm) fn greater_than(state: &mut State) -> Result<()> {
     let lhs = state.pop()?;
     let rhs = state.pop()?;
     state.push(BCVal::Bool(lhs.compare(&rhs)? == Ordering::Greater));
     Ok(())
 }
 
-fn lt_equals(state: &mut State) -> Result<()> {
+pub(in vm) fn lt_equals(state: &mut State) -> Result<()> {
     let lhs = state.pop()?;
     let rhs = state.pop()?;
     let cmp = lhs.compare(&rhs)?;
@@ -156,7 +233,7 @@ fn lt_equals(state: &mut State) -> Result<()> {
     Ok(())
 }
 
-fn gt_equals(state: &mut State) -> Result<()> {
+pub(in vm) fn gt_equals(state: &mut State) -> Result<()> {
     let lhs = state.pop()?;
     let rhs = state.pop()?;
     let cmp = lhs.compare(&rhs)?;
@@ -169,13 +246,13 @@ fn gt_equals(state: &mut State) -> Result<()> {
 /*
  * Stack access functions
  */
-fn tos(state: &mut State) -> Result<()> {
+pub(in vm) fn tos(state: &mut State) -> Result<()> {
     let tos = state.peek()?.clone();
     state.push(tos);
     Ok(())
 }
 
-fn stack_size(state: &mut State) -> Result<()> {
+pub(in vm) fn stack_size(state: &mut State) -> Result<()> {
     let size = BCVal::Int(state.stack_size() as i64);
     state.push(size);
     Ok(())
@@ -189,7 +266,8 @@ fn push(state: &mut State) -> Result<()> {
     let tos = state.pop()?;
     let mut stack = state.pop()?;
     if let BCVal::Stack(ref mut st) = stack {
-        st.push(tos);
+        // this only copies the stack if some other value is also using it
+        Rc::make_mut(st).push(tos);
     } else {
         return Err(
             format!(
@@ -207,7 +285,7 @@ fn pop(state: &mut State) -> Result<()> {
     let mut stack = state.pop()?;
     let popped: BCVal = if let BCVal::Stack(ref mut st) = stack {
         if st.len() > 0 {
-            Ok(st.pop().unwrap()) as Result<BCVal>
+            Ok(Rc::make_mut(st).pop().unwrap()) as Result<BCVal>
         } else {
             Err("attempted to pop empty TOS item".into()) as Result<BCVal>
         }
@@ -232,10 +310,14 @@ fn len_o(state: &mut State) -> Result<()> {
             p.as_stack().len()
         } else if p.is_string() {
             p.as_string().len()
+        } else if p.is_map() {
+            p.as_map().len()
+        } else if p.is_buffer() {
+            p.as_buffer().len()
         } else {
             return Err(
                 format!(
-                    "expected TOS item to be stack or string; instead got {}",
+                    "expected TOS item to be stack, string, map, or buffer; instead got {}",
                     p.type_string()
                 ).into(),
             );
@@ -252,10 +334,14 @@ fn len_c(state: &mut State) -> Result<()> {
             p.as_stack().len()
         } else if p.is_string() {
             p.as_string().len()
+        } else if p.is_map() {
+            p.as_map().len()
+        } else if p.is_buffer() {
+            p.as_buffer().len()
         } else {
             return Err(
                 format!(
-                    "expected TOS item to be stack or string; instead got {}",
+                    "expected TOS item to be stack, string, map, or buffer; instead got {}",
                     p.type_string()
                 ).into(),
             );
@@ -265,6 +351,475 @@ fn len_c(state: &mut State) -> Result<()> {
     Ok(())
 }
 
+/*
+ * Map functions
+ */
+
+/// Pops a map off of the stack.
+fn pop_map(state: &mut State) -> Result<Rc<BCMap>> {
+    match state.pop()? {
+        BCVal::Map(map) => Ok(map),
+        val => Err(format!("expected map; instead got {}", val.type_string()).into()),
+    }
+}
+
+/// Looks up a key in a map.
+fn map_get(map: &BCMap, key: &MapKey) -> Result<BCVal> {
+    map.get(key)
+        .cloned()
+        .ok_or_else(|| format!("map does not contain key `{}`", key).into())
+}
+
+fn get_o(state: &mut State) -> Result<()> {
+    let key = state.pop()?.to_map_key()?;
+    let map = pop_map(state)?;
+    let val = map_get(&map, &key)?;
+    state.push(BCVal::Map(map));
+    state.push(val);
+    Ok(())
+}
+
+fn get_c(state: &mut State) -> Result<()> {
+    let key = state.pop()?.to_map_key()?;
+    let map = pop_map(state)?;
+    let val = map_get(&map, &key)?;
+    state.push(val);
+    Ok(())
+}
+
+fn set(state: &mut State) -> Result<()> {
+    let val = state.pop()?;
+    let key = state.pop()?.to_map_key()?;
+    let mut map = pop_map(state)?;
+    // this only copies the map if some other value is also using it
+    Rc::make_mut(&mut map).insert(key, val);
+    state.push(BCVal::Map(map));
+    Ok(())
+}
+
+fn remove(state: &mut State) -> Result<()> {
+    let key = state.pop()?.to_map_key()?;
+    let mut map = pop_map(state)?;
+    if map.contains_key(&key) {
+        Rc::make_mut(&mut map).remove(&key);
+    }
+    state.push(BCVal::Map(map));
+    Ok(())
+}
+
+fn has_o(state: &mut State) -> Result<()> {
+    let key = state.pop()?.to_map_key()?;
+    let map = pop_map(state)?;
+    let has = map.contains_key(&key);
+    state.push(BCVal::Map(map));
+    state.push(BCVal::Bool(has));
+    Ok(())
+}
+
+fn has_c(state: &mut State) -> Result<()> {
+    let key = state.pop()?.to_map_key()?;
+    let map = pop_map(state)?;
+    state.push(BCVal::Bool(map.contains_key(&key)));
+    Ok(())
+}
+
+fn keys_o(state: &mut State) -> Result<()> {
+    let map = pop_map(state)?;
+    let keys = map.keys().cloned().map(BCVal::from).collect();
+    state.push(BCVal::Map(map));
+    state.push(BCVal::Stack(Rc::new(keys)));
+    Ok(())
+}
+
+fn keys_c(state: &mut State) -> Result<()> {
+    let map = pop_map(state)?;
+    let keys = map.keys().cloned().map(BCVal::from).collect();
+    state.push(BCVal::Stack(Rc::new(keys)));
+    Ok(())
+}
+
+fn values_o(state: &mut State) -> Result<()> {
+    let map = pop_map(state)?;
+    let values = map.values().cloned().collect();
+    state.push(BCVal::Map(map));
+    state.push(BCVal::Stack(Rc::new(values)));
+    Ok(())
+}
+
+fn values_c(state: &mut State) -> Result<()> {
+    let map = pop_map(state)?;
+    let values = map.values().cloned().collect();
+    state.push(BCVal::Stack(Rc::new(values)));
+    Ok(())
+}
+
+/*
+ * Ref functions
+ */
+
+/// Gets the heap address of a ref value.
+fn expect_ref(val: &BCVal) -> Result<usize> {
+    if let &BCVal::Ref(addr) = val {
+        Ok(addr)
+    } else {
+        Err(format!("expected ref; instead got {}", val.type_string()).into())
+    }
+}
+
+fn make_ref(state: &mut State) -> Result<()> {
+    let val = state.pop()?;
+    let r = state.alloc(val);
+    state.push(r);
+    Ok(())
+}
+
+fn deref_o(state: &mut State) -> Result<()> {
+    let addr = expect_ref(state.peek()?)?;
+    let val = state.heap.get(addr)?.clone();
+    state.push(val);
+    Ok(())
+}
+
+fn deref_c(state: &mut State) -> Result<()> {
+    let addr = expect_ref(&state.pop()?)?;
+    let val = state.heap.get(addr)?.clone();
+    state.push(val);
+    Ok(())
+}
+
+fn assign_o(state: &mut State) -> Result<()> {
+    let val = state.pop()?;
+    let addr = expect_ref(state.peek()?)?;
+    state.heap.set(addr, val)?;
+    Ok(())
+}
+
+fn assign_c(state: &mut State) -> Result<()> {
+    let val = state.pop()?;
+    let addr = expect_ref(&state.pop()?)?;
+    state.heap.set(addr, val)?;
+    Ok(())
+}
+
+fn xchg_o(state: &mut State) -> Result<()> {
+    let val = state.pop()?;
+    let addr = expect_ref(state.peek()?)?;
+    let old = state.heap.set(addr, val)?;
+    state.push(old);
+    Ok(())
+}
+
+fn xchg_c(state: &mut State) -> Result<()> {
+    let val = state.pop()?;
+    let addr = expect_ref(&state.pop()?)?;
+    let old = state.heap.set(addr, val)?;
+    state.push(old);
+    Ok(())
+}
+
+/*
+ * Error functions
+ */
+
+/// Gets the error from an error value.
+fn expect_error(val: &BCVal) -> Result<&ErrorVal> {
+    if let &BCVal::Error(ref err) = val {
+        Ok(err)
+    } else {
+        Err(format!("expected error; instead got {}", val.type_string()).into())
+    }
+}
+
+/// Gets where an error was raised as a string, or nil if that isn't known.
+fn error_range(err: &ErrorVal) -> BCVal {
+    err.range
+        .as_ref()
+        .map(|r| BCVal::String(r.to_string().into()))
+        .unwrap_or(BCVal::Nil)
+}
+
+fn throw(state: &mut State) -> Result<()> {
+    let val = state.pop()?;
+    let desc = val.to_string();
+    state.thrown = Some(val);
+    Err(ErrorKind::Thrown(desc).into())
+}
+
+fn make_error(state: &mut State) -> Result<()> {
+    let message = match state.pop()? {
+        BCVal::String(s) => s.to_string(),
+        val => return Err(format!("expected string; instead got {}", val.type_string()).into()),
+    };
+    state.push(BCVal::Error(Rc::new(ErrorVal::new(message, None))));
+    Ok(())
+}
+
+fn error_message_o(state: &mut State) -> Result<()> {
+    let message = expect_error(state.peek()?)?.message.clone();
+    state.push(BCVal::String(message.into()));
+    Ok(())
+}
+
+fn error_message_c(state: &mut State) -> Result<()> {
+    let message = expect_error(&state.pop()?)?.message.clone();
+    state.push(BCVal::String(message.into()));
+    Ok(())
+}
+
+fn error_range_o(state: &mut State) -> Result<()> {
+    let range = error_range(expect_error(state.peek()?)?);
+    state.push(range);
+    Ok(())
+}
+
+fn error_range_c(state: &mut State) -> Result<()> {
+    let range = error_range(expect_error(&state.pop()?)?);
+    state.push(range);
+    Ok(())
+}
+
+/*
+ * Coroutine functions
+ */
+
+/// Gets whether a coroutine value has finished running.
+fn is_done(val: &BCVal) -> Result<bool> {
+    if let &BCVal::Coroutine(ref coroutine) = val {
+        Ok(coroutine.is_done())
+    } else {
+        Err(format!("expected coroutine; instead got {}", val.type_string()).into())
+    }
+}
+
+fn done_o(state: &mut State) -> Result<()> {
+    let done = is_done(state.peek()?)?;
+    state.push(BCVal::Bool(done));
+    Ok(())
+}
+
+fn done_c(state: &mut State) -> Result<()> {
+    let done = is_done(&state.pop()?)?;
+    state.push(BCVal::Bool(done));
+    Ok(())
+}
+
+/*
+ * File functions
+ */
+
+/// Pops a file handle off of the stack.
+fn pop_handle(state: &mut State) -> Result<Handle> {
+    match state.pop()? {
+        BCVal::Handle(handle) => Ok(handle),
+        val => Err(format!("expected handle; instead got {}", val.type_string()).into()),
+    }
+}
+
+/// Pops a string off of the stack.
+fn pop_string(state: &mut State) -> Result<Rc<str>> {
+    match state.pop()? {
+        BCVal::String(s) => Ok(s),
+        val => Err(format!("expected string; instead got {}", val.type_string()).into()),
+    }
+}
+
+fn open(state: &mut State) -> Result<()> {
+    let mode = pop_string(state)?;
+    let path = pop_string(state)?;
+    let handle = Handle::open(&path, &mode)?;
+    state.push(BCVal::Handle(handle));
+    Ok(())
+}
+
+fn stdin(state: &mut State) -> Result<()> {
+    state.push(BCVal::Handle(Handle::stdin()));
+    Ok(())
+}
+
+/// Reads a line from a handle, pushing nil at the end of the file.
+fn read_line(state: &mut State, keep: bool) -> Result<()> {
+    let handle = pop_handle(state)?;
+    let line = handle.read_line()?;
+    if keep {
+        state.push(BCVal::Handle(handle));
+    }
+    state.push(line.map(|l| BCVal::String(l.into())).unwrap_or(BCVal::Nil));
+    Ok(())
+}
+
+fn read_line_o(state: &mut State) -> Result<()> {
+    read_line(state, true)
+}
+
+fn read_line_c(state: &mut State) -> Result<()> {
+    read_line(state, false)
+}
+
+fn read_all(state: &mut State, keep: bool) -> Result<()> {
+    let handle = pop_handle(state)?;
+    let contents = handle.read_all()?;
+    if keep {
+        state.push(BCVal::Handle(handle));
+    }
+    state.push(BCVal::String(contents.into()));
+    Ok(())
+}
+
+fn read_all_o(state: &mut State) -> Result<()> {
+    read_all(state, true)
+}
+
+fn read_all_c(state: &mut State) -> Result<()> {
+    read_all(state, false)
+}
+
+fn write(state: &mut State, keep: bool) -> Result<()> {
+    let val = state.pop()?;
+    let handle = pop_handle(state)?;
+    handle.write(&val.to_string())?;
+    if keep {
+        state.push(BCVal::Handle(handle));
+    }
+    Ok(())
+}
+
+fn write_o(state: &mut State) -> Result<()> {
+    write(state, true)
+}
+
+fn write_c(state: &mut State) -> Result<()> {
+    write(state, false)
+}
+
+fn close(state: &mut State) -> Result<()> {
+    pop_handle(state)?.close()
+}
+
+fn exists(state: &mut State) -> Result<()> {
+    let path = pop_string(state)?;
+    state.push(BCVal::Bool(Path::new(&*path).exists()));
+    Ok(())
+}
+
+fn delete(state: &mut State) -> Result<()> {
+    let path = pop_string(state)?;
+    fs::remove_file(&*path).map_err(|e| format!("could not delete `{}`: {}", path, e).into())
+}
+
+/*
+ * Buffer functions
+ */
+
+/// Pops a buffer off of the stack.
+fn pop_buffer(state: &mut State) -> Result<Buffer> {
+    match state.pop()? {
+        BCVal::Buffer(buffer) => Ok(buffer),
+        val => Err(format!("expected buffer; instead got {}", val.type_string()).into()),
+    }
+}
+
+/// Pops a size or offset off of the stack.
+fn pop_offset(state: &mut State) -> Result<usize> {
+    match state.pop()? {
+        BCVal::Int(i) if i >= 0 => Ok(i as usize),
+        BCVal::Int(i) => Err(format!("expected a size or offset; instead got {}", i).into()),
+        val => Err(format!("expected int; instead got {}", val.type_string()).into()),
+    }
+}
+
+fn make_buffer(state: &mut State) -> Result<()> {
+    let size = pop_offset(state)?;
+    state.push(BCVal::Buffer(Buffer::new(size)));
+    Ok(())
+}
+
+fn string_buffer(state: &mut State) -> Result<()> {
+    let s = pop_string(state)?;
+    state.push(BCVal::Buffer(Buffer::from_str(&s)));
+    Ok(())
+}
+
+fn byte_o(state: &mut State) -> Result<()> {
+    let offset = pop_offset(state)?;
+    let buffer = pop_buffer(state)?;
+    let byte = buffer.get(offset)?;
+    state.push(BCVal::Buffer(buffer));
+    state.push(BCVal::Int(byte as i64));
+    Ok(())
+}
+
+fn byte_c(state: &mut State) -> Result<()> {
+    let offset = pop_offset(state)?;
+    let buffer = pop_buffer(state)?;
+    state.push(BCVal::Int(buffer.get(offset)? as i64));
+    Ok(())
+}
+
+/// Pops a byte, an offset, and a buffer off of the stack, and sets the byte at that offset. The
+/// buffer is returned so that the keeping variant can put it back.
+fn pop_set_byte(state: &mut State) -> Result<Buffer> {
+    let byte = match state.pop()? {
+        BCVal::Int(i) if i >= 0 && i <= 255 => i as u8,
+        BCVal::Int(i) => return Err(format!("{} does not fit in a byte", i).into()),
+        val => return Err(format!("expected int; instead got {}", val.type_string()).into()),
+    };
+    let offset = pop_offset(state)?;
+    let buffer = pop_buffer(state)?;
+    buffer.set(offset, byte)?;
+    Ok(buffer)
+}
+
+fn set_byte_o(state: &mut State) -> Result<()> {
+    let buffer = pop_set_byte(state)?;
+    state.push(BCVal::Buffer(buffer));
+    Ok(())
+}
+
+fn set_byte_c(state: &mut State) -> Result<()> {
+    pop_set_byte(state)?;
+    Ok(())
+}
+
+fn buffer_string_o(state: &mut State) -> Result<()> {
+    let buffer = pop_buffer(state)?;
+    let s = buffer.read_string()?;
+    state.push(BCVal::Buffer(buffer));
+    state.push(BCVal::String(s.into()));
+    Ok(())
+}
+
+fn buffer_string_c(state: &mut State) -> Result<()> {
+    let buffer = pop_buffer(state)?;
+    state.push(BCVal::String(buffer.read_string()?.into()));
+    Ok(())
+}
+
+/*
+ * errno functions
+ */
+
+fn errno(state: &mut State) -> Result<()> {
+    let errno = state.errno;
+    state.push(BCVal::Int(errno as i64));
+    Ok(())
+}
+
+fn strerror(state: &mut State) -> Result<()> {
+    let errnum = match state.pop()? {
+        BCVal::Int(i) if i >= c_int::min_value() as i64 && i <= c_int::max_value() as i64 => {
+            i as c_int
+        }
+        BCVal::Int(i) => return Err(format!("errno value is out of range: {}", i).into()),
+        val => return Err(format!("expected int; instead got {}", val.type_string()).into()),
+    };
+    // the message belongs to libc, and may be changed by the next call, so it gets copied
+    let message = unsafe { CStr::from_ptr(libc::strerror(errnum)) }
+        .to_string_lossy()
+        .into_owned();
+    state.push(BCVal::String(message.into()));
+    Ok(())
+}
+
 /*
  * QOL functions
  */
@@ -0,0 +1,169 @@
+use prelude::*;
+use libc::c_void;
+use libffi::low::ffi_cif;
+use libffi::middle::{Cif, Closure};
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::ptr;
+use std::rc::Rc;
+use super::foreign::{marshal, read_value};
+
+thread_local! {
+    /// The VM that a foreign function is being called from, which is what callbacks are run on.
+    ///
+    /// This is only set while C is running. It's made from the VM's `&mut` right before the call
+    /// into C, and the VM is only reached through this pointer until C returns: the function's
+    /// arguments and code pointer are copied out of the VM beforehand, and nothing that's
+    /// borrowed from the VM or its state is held across the call. So the `&mut VM` that a
+    /// callback makes from it is the only live reference to the VM while the callback runs.
+    static CURRENT_VM: Cell<*mut VM> = Cell::new(ptr::null_mut());
+    /// The first error that a callback ran into while C was running it. Errors can't be passed
+    /// back up through C, so they're held here until the foreign function that called the
+    /// callback returns.
+    static CALLBACK_ERROR: RefCell<Option<Error>> = RefCell::new(None);
+}
+
+/// Marks a VM as the one that callbacks run on, until this is dropped.
+pub(in vm) struct CurrentVM(*mut VM);
+
+impl CurrentVM {
+    pub fn enter(vm: *mut VM) -> Self {
+        CurrentVM(CURRENT_VM.with(|current| current.replace(vm)))
+    }
+}
+
+impl Drop for CurrentVM {
+    fn drop(&mut self) {
+        CURRENT_VM.with(|current| current.set(self.0));
+    }
+}
+
+/// Takes the error that a callback ran into, if there was one.
+pub(in vm) fn take_callback_error() -> Option<Error> {
+    CALLBACK_ERROR.with(|err| err.borrow_mut().take())
+}
+
+fn callback_failed() -> bool {
+    CALLBACK_ERROR.with(|err| err.borrow().is_some())
+}
+
+#[derive(Debug)]
+struct CallbackData {
+    callback: ForeignCallback,
+    fun: Rc<BCUserFun>,
+}
+
+/// A C function pointer that calls an SBL function.
+#[derive(Debug)]
+pub struct CallbackPtr {
+    // the closure points at the data, so it has to be dropped first
+    closure: Closure<'static>,
+    /// The closure's userdata. This is never read from here; it's only kept so that it lives as
+    /// long as the closure does.
+    _data: Box<CallbackData>,
+}
+
+impl CallbackPtr {
+    fn new(callback: ForeignCallback, fun: Rc<BCUserFun>) -> Self {
+        let cif = Cif::new(
+            callback.params.iter().map(ForeignType::ffi_type),
+            callback.return_type.ffi_type(),
+        );
+        let data = Box::new(CallbackData { callback, fun });
+        // the data is boxed, so it doesn't move for as long as the closure is around
+        let userdata: &'static CallbackData = unsafe { &*(&*data as *const CallbackData) };
+        let closure = Closure::new(cif, trampoline, userdata);
+        CallbackPtr {
+            closure,
+            _data: data,
+        }
+    }
+
+    pub fn as_ptr(&self) -> *const c_void {
+        *self.closure.code_ptr() as *const c_void
+    }
+}
+
+/// Where C ends up when it calls a callback. This converts the arguments, runs the SBL function on
+/// the current VM, and converts its return value.
+unsafe extern "C" fn trampoline(
+    _cif: &ffi_cif,
+    result: &mut u64,
+    args: *const *const c_void,
+    data: &CallbackData,
+) {
+    let callback = &data.callback;
+    let vm = CURRENT_VM.with(Cell::get);
+    if vm.is_null() {
+        // there's no VM to report this to, and returning garbage to C isn't any better
+        eprintln!("callback `{}` was called while no VM was running", callback.name);
+        process::abort();
+    }
+    if callback.return_type != ForeignType::Void {
+        *result = 0;
+    }
+    // once a callback fails, the rest are skipped until the foreign function returns
+    if callback_failed() {
+        return;
+    }
+    // nothing else borrows the VM until C returns; see `CURRENT_VM`
+    let run = AssertUnwindSafe(|| run_callback(&mut *vm, data, args, result));
+    let err = match panic::catch_unwind(run) {
+        Ok(Ok(())) => return,
+        Ok(Err(err)) => err,
+        Err(_) => format!("callback `{}` panicked", callback.name).into(),
+    };
+    CALLBACK_ERROR.with(|slot| *slot.borrow_mut() = Some(err));
+}
+
+unsafe fn run_callback(
+    vm: &mut VM,
+    data: &CallbackData,
+    args: *const *const c_void,
+    result: *mut u64,
+) -> Result<()> {
+    let callback = &data.callback;
+    let vals = callback
+        .params
+        .iter()
+        .enumerate()
+        .map(|(index, param)| {
+            let what = format!("argument {} of callback `{}`", index + 1, callback.name);
+            read_value(&what, *param, *args.offset(index as isize) as *const u8)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let returns = callback.return_type != ForeignType::Void;
+    if let Some(val) = vm.call_back(data.fun.clone(), vals, returns)? {
+        let what = format!("the return value of callback `{}`", callback.name);
+        // strings can't be returned from callbacks, so nothing ends up in the pool
+        let ffi_val = marshal(&what, callback.return_type, &val, &mut vec![])?;
+        ffi_val.write_return(result);
+    }
+    Ok(())
+}
+
+impl ForeignCallback {
+    /// Pushes a pointer to this callback, making it the first time that it's asked for. The
+    /// pointer stays good for as long as the VM state is around.
+    pub(in vm) fn call(&self, fun_table: &LinkedFunTable, state: &mut State) -> Result<()> {
+        if !state.callbacks.contains_key(&self.name) {
+            let fun = fun_table
+                .index_of(&self.name)
+                .map(|index| fun_table.get(index))
+                .and_then(|fun| if let &Fun::UserFun(ref fun) = fun {
+                    Some(fun.clone())
+                } else {
+                    None
+                })
+                .expect("callback does not refer to a user function; compiler should have caught this");
+            state.callbacks.insert(
+                self.name.clone(),
+                Rc::new(CallbackPtr::new(self.clone(), fun)),
+            );
+        }
+        let ptr = state.callbacks[&self.name].as_ptr();
+        state.push(BCVal::Pointer(ptr as usize));
+        Ok(())
+    }
+}
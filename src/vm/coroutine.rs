@@ -0,0 +1,112 @@
+use prelude::*;
+use std::cell::RefCell;
+use std::fmt::{self, Formatter, Debug};
+use std::mem;
+use std::rc::Rc;
+
+/// A stack, call stack, and set of running `try` blocks for code to run in. The main program has
+/// one of these, and so does every coroutine.
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+    pub stack: Vec<BCVal>,
+    pub call_stack: Vec<BCFunState>,
+    pub handlers: Vec<Handler>,
+}
+
+impl Context {
+    /// Gets all of the values that this context can reach directly; that is, its stack, the
+    /// locals of every function in its call stack, and the values that its try blocks will put
+    /// back.
+    pub fn vals(&self) -> Vec<&BCVal> {
+        let locals = self.call_stack
+            .iter()
+            .flat_map(|f| f.locals.iter().filter_map(Option::as_ref));
+        let handlers = self.handlers.iter().flat_map(|h| h.popped.iter());
+        self.stack.iter().chain(locals).chain(handlers).collect()
+    }
+}
+
+#[derive(Debug)]
+enum CoroutineState {
+    /// The coroutine is waiting to be resumed, either because it hasn't started yet or because it
+    /// yielded.
+    Suspended(Context),
+    /// The coroutine's context is the one that's currently in use, or it has resumed another
+    /// coroutine.
+    Running,
+    /// The coroutine's function has returned, or it raised an error that it didn't catch.
+    Done,
+}
+
+/// A coroutine that was created with a `spawn` statement.
+///
+/// Coroutines are shared rather than copied, so resuming any copy of one resumes all of them.
+#[derive(Clone)]
+pub struct Coroutine(Rc<RefCell<CoroutineState>>);
+
+impl Coroutine {
+    /// Creates a coroutine that will run the given function the first time that it's resumed.
+    pub fn new(fun: Rc<BCUserFun>) -> Self {
+        let context = Context {
+            call_stack: vec![fun.into()],
+            ..Default::default()
+        };
+        Coroutine(Rc::new(RefCell::new(CoroutineState::Suspended(context))))
+    }
+
+    /// Whether this coroutine has finished running.
+    pub fn is_done(&self) -> bool {
+        match *self.0.borrow() {
+            CoroutineState::Done => true,
+            _ => false,
+        }
+    }
+
+    /// Marks this coroutine as running, taking its context so it can be switched to.
+    pub fn start(&self) -> Result<Context> {
+        let mut state = self.0.borrow_mut();
+        match mem::replace(&mut *state, CoroutineState::Running) {
+            CoroutineState::Suspended(context) => Ok(context),
+            CoroutineState::Running => Err("attempted to resume a coroutine that is already running".into()),
+            CoroutineState::Done => {
+                *state = CoroutineState::Done;
+                Err("attempted to resume a coroutine that has finished".into())
+            }
+        }
+    }
+
+    /// Stores the context of a coroutine that has yielded, so it can pick up where it left off.
+    pub fn suspend(&self, context: Context) {
+        *self.0.borrow_mut() = CoroutineState::Suspended(context);
+    }
+
+    /// Marks this coroutine as finished.
+    pub fn finish(&self) {
+        *self.0.borrow_mut() = CoroutineState::Done;
+    }
+
+    /// Gets the values in this coroutine's context, if it's suspended.
+    pub fn vals(&self) -> Vec<BCVal> {
+        match *self.0.borrow() {
+            CoroutineState::Suspended(ref context) => context.vals().into_iter().cloned().collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Gets an address that's unique to this coroutine, which is shared by all of its copies.
+    pub fn addr(&self) -> usize {
+        &*self.0 as *const _ as usize
+    }
+}
+
+impl PartialEq for Coroutine {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Debug for Coroutine {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Coroutine(0x{:X})", self.addr())
+    }
+}
@@ -0,0 +1,160 @@
+use prelude::*;
+use std::cell::RefCell;
+use std::fmt::{self, Formatter, Debug, Display};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::rc::Rc;
+
+enum Stream {
+    Read(BufReader<File>),
+    Write(BufWriter<File>),
+    Stdin,
+}
+
+/// A file (or stdin) that was opened by a program.
+///
+/// Handles are shared rather than copied, so closing any copy of one closes all of them. Files
+/// are closed once the last copy of their handle goes away, if the program doesn't close them
+/// itself.
+#[derive(Clone)]
+pub struct Handle {
+    path: Rc<str>,
+    stream: Rc<RefCell<Option<Stream>>>,
+}
+
+impl Handle {
+    fn new(path: &str, stream: Stream) -> Self {
+        Handle {
+            path: path.into(),
+            stream: Rc::new(RefCell::new(Some(stream))),
+        }
+    }
+
+    /// Opens a file for reading ("r"), writing ("w"), or appending ("a"). Files that are opened
+    /// for writing or appending are created if they don't exist.
+    pub fn open(path: &str, mode: &str) -> Result<Self> {
+        let mut options = OpenOptions::new();
+        match mode {
+            "r" => options.read(true),
+            "w" => options.write(true).create(true).truncate(true),
+            "a" => options.append(true).create(true),
+            _ => {
+                return Err(
+                    format!("unknown file mode `{}`; expected \"r\", \"w\", or \"a\"", mode).into(),
+                )
+            }
+        };
+        let file = options
+            .open(path)
+            .map_err(|e| Error::from(format!("could not open `{}`: {}", path, e)))?;
+        let stream = if mode == "r" {
+            Stream::Read(BufReader::new(file))
+        } else {
+            Stream::Write(BufWriter::new(file))
+        };
+        Ok(Handle::new(path, stream))
+    }
+
+    /// Gets a handle that reads from stdin.
+    pub fn stdin() -> Self {
+        Handle::new("<stdin>", Stream::Stdin)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Turns an I/O error into an error that says which file it happened to.
+    fn io_error(&self, err: io::Error) -> Error {
+        format!("I/O error on `{}`: {}", self.path, err).into()
+    }
+
+    /// Runs a function with this handle's stream, if it hasn't been closed.
+    fn with_stream<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Stream) -> Result<T>,
+    {
+        match *self.stream.borrow_mut() {
+            Some(ref mut stream) => f(stream),
+            None => Err(format!("attempted to use `{}` after it was closed", self.path).into()),
+        }
+    }
+
+    /// Reads the next line, without its line ending. Nothing is read at the end of the file.
+    pub fn read_line(&self) -> Result<Option<String>> {
+        let mut line = String::new();
+        let count = self.with_stream(|stream| {
+            let result = match *stream {
+                Stream::Read(ref mut reader) => reader.read_line(&mut line),
+                Stream::Stdin => io::stdin().read_line(&mut line),
+                Stream::Write(_) => return Err(self.not_readable()),
+            };
+            result.map_err(|e| self.io_error(e))
+        })?;
+        if count == 0 {
+            return Ok(None);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+
+    /// Reads everything up to the end of the file.
+    pub fn read_all(&self) -> Result<String> {
+        let mut contents = String::new();
+        self.with_stream(|stream| {
+            let result = match *stream {
+                Stream::Read(ref mut reader) => reader.read_to_string(&mut contents),
+                Stream::Stdin => io::stdin().read_to_string(&mut contents),
+                Stream::Write(_) => return Err(self.not_readable()),
+            };
+            result.map_err(|e| self.io_error(e))
+        })?;
+        Ok(contents)
+    }
+
+    pub fn write(&self, contents: &str) -> Result<()> {
+        self.with_stream(|stream| match *stream {
+            Stream::Write(ref mut writer) => writer
+                .write_all(contents.as_bytes())
+                .map_err(|e| self.io_error(e)),
+            _ => Err(format!("`{}` was not opened for writing", self.path).into()),
+        })
+    }
+
+    /// Closes this handle, writing out anything that hasn't been written yet.
+    pub fn close(&self) -> Result<()> {
+        let stream = self.stream.borrow_mut().take();
+        match stream {
+            Some(Stream::Write(mut writer)) => writer.flush().map_err(|e| self.io_error(e)),
+            Some(_) => Ok(()),
+            None => Err(format!("attempted to close `{}` twice", self.path).into()),
+        }
+    }
+
+    fn not_readable(&self) -> Error {
+        format!("`{}` was not opened for reading", self.path).into()
+    }
+}
+
+impl PartialEq for Handle {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.stream, &other.stream)
+    }
+}
+
+impl Display for Handle {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "handle({})", self.path)
+    }
+}
+
+impl Debug for Handle {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Handle({:?})", self.path)
+    }
+}
@@ -1,166 +1,575 @@
 use prelude::*;
-use libc::{self, RTLD_NOW, c_char};
-use libffi::low::CodePtr;
-use libffi::high::call::{call, Arg};
-use std::ffi::CString;
+use super::callback::{take_callback_error, CurrentVM};
+use libc::{c_char, c_int, c_long, c_void};
+use libffi::low;
+use libffi::middle::{arg, ffi_abi_FFI_DEFAULT_ABI, Arg, Cif, CodePtr, Type};
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::ptr;
+use std::sync::Arc;
 
+/// An argument for a foreign function, stored at the width of the C type that it's passed as.
 #[derive(PartialEq, Clone, Debug)]
-enum FfiVal {
-    Int(i64),
-    Char(u8),
+pub(in vm) enum FfiVal {
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    Int(c_int),
+    Long(c_long),
+    SizeT(usize),
+    Float(f32),
+    Double(f64),
+    Char(c_char),
     String(*const c_char),
-    Bool(i32),
-    //Stack(&'a *const c_void),
-    Nil(()),
+    Ptr(*mut c_void),
 }
 
-impl<'a> FfiVal {
-    pub fn to_arg(&'a self) -> Arg<'a> {
+impl FfiVal {
+    pub fn to_arg(&self) -> Arg {
         match self {
-            &FfiVal::Int(ref i) => Arg::new(i),
-            &FfiVal::Char(ref c) => Arg::new(c),
-            &FfiVal::String(ref s) => Arg::new(s),
-            &FfiVal::Bool(ref i) => Arg::new(i),
-            &FfiVal::Nil(ref t) => Arg::new(t),
+            &FfiVal::I8(ref i) => arg(i),
+            &FfiVal::I16(ref i) => arg(i),
+            &FfiVal::I32(ref i) => arg(i),
+            &FfiVal::I64(ref i) => arg(i),
+            &FfiVal::U8(ref i) => arg(i),
+            &FfiVal::U16(ref i) => arg(i),
+            &FfiVal::U32(ref i) => arg(i),
+            &FfiVal::U64(ref i) => arg(i),
+            &FfiVal::Int(ref i) => arg(i),
+            &FfiVal::Long(ref i) => arg(i),
+            &FfiVal::SizeT(ref i) => arg(i),
+            &FfiVal::Float(ref f) => arg(f),
+            &FfiVal::Double(ref f) => arg(f),
+            &FfiVal::Char(ref c) => arg(c),
+            &FfiVal::String(ref s) => arg(s),
+            &FfiVal::Ptr(ref p) => arg(p),
         }
     }
+
+    /// Writes this value to memory that C owns, like a struct field. The memory doesn't need to
+    /// be aligned.
+    unsafe fn write_to(&self, dest: *mut u8) {
+        match *self {
+            FfiVal::I8(i) => ptr::write_unaligned(dest as *mut i8, i),
+            FfiVal::I16(i) => ptr::write_unaligned(dest as *mut i16, i),
+            FfiVal::I32(i) => ptr::write_unaligned(dest as *mut i32, i),
+            FfiVal::I64(i) => ptr::write_unaligned(dest as *mut i64, i),
+            FfiVal::U8(i) => ptr::write_unaligned(dest as *mut u8, i),
+            FfiVal::U16(i) => ptr::write_unaligned(dest as *mut u16, i),
+            FfiVal::U32(i) => ptr::write_unaligned(dest as *mut u32, i),
+            FfiVal::U64(i) => ptr::write_unaligned(dest as *mut u64, i),
+            FfiVal::Int(i) => ptr::write_unaligned(dest as *mut c_int, i),
+            FfiVal::Long(i) => ptr::write_unaligned(dest as *mut c_long, i),
+            FfiVal::SizeT(i) => ptr::write_unaligned(dest as *mut usize, i),
+            FfiVal::Float(f) => ptr::write_unaligned(dest as *mut f32, f),
+            FfiVal::Double(f) => ptr::write_unaligned(dest as *mut f64, f),
+            FfiVal::Char(c) => ptr::write_unaligned(dest as *mut c_char, c),
+            FfiVal::String(p) => ptr::write_unaligned(dest as *mut *const c_char, p),
+            FfiVal::Ptr(p) => ptr::write_unaligned(dest as *mut *mut c_void, p),
+        }
+    }
+
+    /// Writes this value to where libffi expects a closure's return value to go. Integers that
+    /// are smaller than a register have to be widened to fill it.
+    pub(in vm) unsafe fn write_return(&self, result: *mut u64) {
+        match *self {
+            FfiVal::I8(i) => *result = i as i64 as u64,
+            FfiVal::I16(i) => *result = i as i64 as u64,
+            FfiVal::I32(i) => *result = i as i64 as u64,
+            FfiVal::I64(i) => *result = i as u64,
+            FfiVal::U8(i) => *result = i as u64,
+            FfiVal::U16(i) => *result = i as u64,
+            FfiVal::U32(i) => *result = i as u64,
+            FfiVal::U64(i) => *result = i,
+            FfiVal::Int(i) => *result = i as i64 as u64,
+            FfiVal::Long(i) => *result = i as i64 as u64,
+            FfiVal::SizeT(i) => *result = i as u64,
+            FfiVal::Float(f) => ptr::write(result as *mut f32, f),
+            FfiVal::Double(f) => ptr::write(result as *mut f64, f),
+            FfiVal::Char(c) => *result = c as i64 as u64,
+            FfiVal::String(p) => ptr::write(result as *mut *const c_char, p),
+            FfiVal::Ptr(p) => ptr::write(result as *mut *mut c_void, p),
+        }
+    }
+}
+
+/// Reads a value of the given C type out of memory that C owns, like a struct field or a callback's
+/// argument. The memory doesn't need to be aligned.
+pub(in vm) unsafe fn read_value(what: &str, ty: ForeignType, src: *const u8) -> Result<BCVal> {
+    macro_rules! read {
+        ($t:ty) => { ptr::read_unaligned(src as *const $t) };
+    }
+    let val = match ty {
+        ForeignType::I8 => BCVal::Int(read!(i8) as i64),
+        ForeignType::I16 => BCVal::Int(read!(i16) as i64),
+        ForeignType::I32 => BCVal::Int(read!(i32) as i64),
+        ForeignType::I64 => BCVal::Int(read!(i64)),
+        ForeignType::U8 => BCVal::Int(read!(u8) as i64),
+        ForeignType::U16 => BCVal::Int(read!(u16) as i64),
+        ForeignType::U32 => BCVal::Int(read!(u32) as i64),
+        ForeignType::U64 => widen(what, read!(u64))?,
+        ForeignType::Int => BCVal::Int(read!(c_int) as i64),
+        ForeignType::Long => BCVal::Int(read!(c_long) as i64),
+        ForeignType::SizeT => widen(what, read!(usize) as u64)?,
+        ForeignType::Float => BCVal::Float(read!(f32) as f64),
+        ForeignType::Double => BCVal::Float(read!(f64)),
+        ForeignType::Char => BCVal::Char(read!(u8) as char),
+        ForeignType::Bool => BCVal::Bool(read!(c_int) != 0),
+        ForeignType::Ptr => {
+            let p = read!(*mut c_void);
+            if p.is_null() {
+                BCVal::Nil
+            } else {
+                BCVal::Pointer(p as usize)
+            }
+        }
+        ForeignType::String => {
+            let s = read!(*const c_char);
+            if s.is_null() {
+                BCVal::Nil
+            } else {
+                // the string still belongs to C, so it gets copied
+                BCVal::String(CStr::from_ptr(s).to_string_lossy().as_ref().into())
+            }
+        }
+        ForeignType::Void => unreachable!("void values are rejected by the parser"),
+    };
+    Ok(val)
+}
+
+impl ForeignType {
+    /// Gets the libffi type that values of this type are passed and returned as.
+    pub(in vm) fn ffi_type(&self) -> Type {
+        match *self {
+            ForeignType::I8 => Type::i8(),
+            ForeignType::I16 => Type::i16(),
+            ForeignType::I32 => Type::i32(),
+            ForeignType::I64 => Type::i64(),
+            ForeignType::U8 => Type::u8(),
+            ForeignType::U16 => Type::u16(),
+            ForeignType::U32 => Type::u32(),
+            ForeignType::U64 => Type::u64(),
+            ForeignType::Int | ForeignType::Bool => Type::c_int(),
+            ForeignType::Long => Type::c_long(),
+            ForeignType::SizeT => Type::usize(),
+            ForeignType::Float => Type::f32(),
+            ForeignType::Double => Type::f64(),
+            ForeignType::Char => Type::c_schar(),
+            ForeignType::String | ForeignType::Ptr => Type::pointer(),
+            ForeignType::Void => Type::void(),
+        }
+    }
+}
+
+/// Whether an int fits in a type with the given bounds.
+fn in_range(i: i64, min: i64, max: u64) -> bool {
+    i >= min && (i < 0 || i as u64 <= max)
+}
+
+/// Narrows an int to the given type, if it fits.
+macro_rules! narrow {
+    ($i:expr, $t:ty) => {
+        if in_range($i, <$t>::min_value() as i64, <$t>::max_value() as u64) {
+            Some($i as $t)
+        } else {
+            None
+        }
+    };
+}
+
+/// Converts a value into the C type that it's being passed or stored as. `what` says what the
+/// value is for any errors, like "argument 1 of `abs`".
+pub(in vm) fn marshal(
+    what: &str,
+    ty: ForeignType,
+    val: &BCVal,
+    string_pool: &mut Vec<CString>,
+) -> Result<FfiVal> {
+    let ffi_val = match (ty, val) {
+        (ForeignType::I8, &BCVal::Int(i)) => narrow!(i, i8).map(FfiVal::I8),
+        (ForeignType::I16, &BCVal::Int(i)) => narrow!(i, i16).map(FfiVal::I16),
+        (ForeignType::I32, &BCVal::Int(i)) => narrow!(i, i32).map(FfiVal::I32),
+        (ForeignType::I64, &BCVal::Int(i)) => Some(FfiVal::I64(i)),
+        (ForeignType::U8, &BCVal::Int(i)) => narrow!(i, u8).map(FfiVal::U8),
+        (ForeignType::U16, &BCVal::Int(i)) => narrow!(i, u16).map(FfiVal::U16),
+        (ForeignType::U32, &BCVal::Int(i)) => narrow!(i, u32).map(FfiVal::U32),
+        (ForeignType::U64, &BCVal::Int(i)) => narrow!(i, u64).map(FfiVal::U64),
+        (ForeignType::Int, &BCVal::Int(i)) => narrow!(i, c_int).map(FfiVal::Int),
+        (ForeignType::Long, &BCVal::Int(i)) => narrow!(i, c_long).map(FfiVal::Long),
+        (ForeignType::SizeT, &BCVal::Int(i)) => narrow!(i, usize).map(FfiVal::SizeT),
+        (ForeignType::Float, &BCVal::Float(f)) => Some(FfiVal::Float(f as f32)),
+        (ForeignType::Double, &BCVal::Float(f)) => Some(FfiVal::Double(f)),
+        (ForeignType::Char, &BCVal::Char(c)) => narrow!(c as i64, u8).map(|c| FfiVal::Char(c as c_char)),
+        (ForeignType::Bool, &BCVal::Bool(b)) => Some(FfiVal::Int(if b { 1 } else { 0 })),
+        (ForeignType::String, &BCVal::String(ref s)) => {
+            let c_str = CString::new(&s[..]).map_err(|_| {
+                Error::from(format!("{} is a string with a NUL character in it", what))
+            })?;
+            let c_ptr = c_str.as_ptr();
+            // moving the CString doesn't move the buffer that it points to
+            string_pool.push(c_str);
+            Some(FfiVal::String(c_ptr))
+        }
+        // null pointers are nil, and buffers are passed as a pointer to their bytes
+        (ForeignType::Ptr, &BCVal::Pointer(p)) => Some(FfiVal::Ptr(p as *mut c_void)),
+        (ForeignType::Ptr, &BCVal::Buffer(ref b)) => Some(FfiVal::Ptr(b.as_ptr() as *mut c_void)),
+        (ForeignType::Ptr, &BCVal::Nil) => Some(FfiVal::Ptr(ptr::null_mut())),
+        _ => {
+            return Err(
+                format!(
+                    "expected {} to be of type {}; instead got {}",
+                    what,
+                    ty.name(),
+                    val.type_string()
+                ).into(),
+            )
+        }
+    };
+    ffi_val.ok_or_else(|| format!("{} is out of range for {}: {}", what, ty.name(), val).into())
+}
+
+/// Converts an extra argument of a variadic function, picking the C type that it's passed as from
+/// its value. Ints are passed as `long` and floats as `double`, while chars and bools are promoted
+/// to `int` the way that C promotes them.
+fn marshal_extra(
+    what: &str,
+    val: &BCVal,
+    string_pool: &mut Vec<CString>,
+) -> Result<(ForeignType, FfiVal)> {
+    let ty = match *val {
+        BCVal::Int(_) => ForeignType::Long,
+        BCVal::Float(_) => ForeignType::Double,
+        BCVal::Char(c) => return Ok((ForeignType::Int, FfiVal::Int(c as u32 as c_int))),
+        BCVal::Bool(b) => return Ok((ForeignType::Int, FfiVal::Int(b as c_int))),
+        BCVal::String(_) => ForeignType::String,
+        BCVal::Pointer(_) | BCVal::Buffer(_) | BCVal::Nil => ForeignType::Ptr,
+        _ => {
+            return Err(
+                format!("{} can't be passed to C; got {}", what, val.type_string()).into(),
+            )
+        }
+    };
+    Ok((ty, marshal(what, ty, val, string_pool)?))
+}
+
+/// Converts an unsigned value from C into an int, if it fits in one.
+fn widen(what: &str, value: u64) -> Result<BCVal> {
+    if value > i64::max_value() as u64 {
+        Err(format!("{} is too large for an int: {}", what, value).into())
+    } else {
+        Ok(BCVal::Int(value as i64))
+    }
+}
+
+/// A call into a foreign function, with its arguments already popped and converted. Nothing in
+/// this is borrowed from the VM, so C can call back into the VM while the call is being made.
+pub(in vm) struct ForeignCall {
+    name: String,
+    return_type: ForeignType,
+    cif: Cif,
+    code_ptr: CodePtr,
+    args: Vec<FfiVal>,
+    /// The strings and values that the arguments point into, which have to outlive the call.
+    _string_pool: Vec<CString>,
+    _vals: Vec<BCVal>,
+}
+
+impl ForeignCall {
+    /// Makes the call, and pushes its return value.
+    ///
+    /// C may call back into the VM while it's running, so the VM is only reached through `vm`
+    /// until C returns; see `CURRENT_VM` in callback.rs. If a callback fails while the function
+    /// is running, the callback's error is returned.
+    pub(in vm) unsafe fn call(self, vm: *mut VM) -> Result<()> {
+        let (result, errno) = {
+            let _current = CurrentVM::enter(vm);
+            self.call_raw()
+        };
+        let state = (*vm).state_mut();
+        state.errno = errno;
+        if let Some(err) = take_callback_error() {
+            return Err(err);
+        }
+        if let Some(val) = result? {
+            state.push(val);
+        }
+        Ok(())
+    }
+
+    /// Calls into C, returning the converted return value and the value of `errno` right after
+    /// the call.
+    unsafe fn call_raw(&self) -> (Result<Option<BCVal>>, i32) {
+        let args = self.args.iter().map(FfiVal::to_arg).collect::<Vec<_>>();
+        let mut errno = 0;
+        // errno is cleared right before the call and saved right after it, before anything else
+        // gets a chance to change it
+        macro_rules! call {
+            ($t:ty) => {{
+                set_errno(0);
+                let result = self.cif.call::<$t>(self.code_ptr, &args);
+                errno = io::Error::last_os_error().raw_os_error().unwrap_or(0);
+                result
+            }};
+        }
+        let result = (|| {
+            // libffi widens integer return values to a full register, so they're all read as u64
+            // and then cut back down to the size that the function actually returns.
+            let mut int = || call!(u64);
+            let what = || format!("the return value of `{}`", self.name);
+            let val = match self.return_type {
+                ForeignType::I8 => BCVal::Int(int() as i8 as i64),
+                ForeignType::I16 => BCVal::Int(int() as i16 as i64),
+                ForeignType::I32 => BCVal::Int(int() as i32 as i64),
+                ForeignType::I64 => BCVal::Int(int() as i64),
+                ForeignType::U8 => BCVal::Int(int() as u8 as i64),
+                ForeignType::U16 => BCVal::Int(int() as u16 as i64),
+                ForeignType::U32 => BCVal::Int(int() as u32 as i64),
+                ForeignType::U64 => widen(&what(), int())?,
+                ForeignType::Int => BCVal::Int(int() as c_int as i64),
+                ForeignType::Long => BCVal::Int(int() as c_long as i64),
+                ForeignType::SizeT => widen(&what(), int() as usize as u64)?,
+                ForeignType::Char => BCVal::Char(int() as u8 as char),
+                ForeignType::Bool => BCVal::Bool(int() as c_int != 0),
+                ForeignType::Float => BCVal::Float(call!(f32) as f64),
+                ForeignType::Double => BCVal::Float(call!(f64)),
+                ForeignType::String => {
+                    let s = call!(*const c_char);
+                    if s.is_null() {
+                        BCVal::Nil
+                    } else {
+                        // the string still belongs to the foreign library, so it gets copied
+                        BCVal::String(CStr::from_ptr(s).to_string_lossy().as_ref().into())
+                    }
+                }
+                ForeignType::Ptr => {
+                    let p = call!(*mut c_void);
+                    if p.is_null() {
+                        BCVal::Nil
+                    } else {
+                        BCVal::Pointer(p as usize)
+                    }
+                }
+                ForeignType::Void => {
+                    call!(());
+                    return Ok(None);
+                }
+            };
+            Ok(Some(val))
+        })();
+        (result, errno)
+    }
 }
 
-fn ff_key(lib: &str, name: &str) -> String {
-    format!("{}_{}", lib, name)
+/// Sets the calling thread's `errno`.
+unsafe fn set_errno(value: c_int) {
+    #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+    let location = libc::__error();
+    #[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "freebsd")))]
+    let location = libc::__errno_location();
+    *location = value;
 }
 
 impl ForeignFun {
-    /// Makes a call into a foreign function.
-    pub(in vm) fn call(&self, state: &mut State) -> Result<()> {
-        // string pool holds all of the strings that we have to re-allocate as CStrings
+    /// Pops the arguments of a call into this function and converts them to C types, getting the
+    /// call ready to be made.
+    pub(in vm) fn prepare(&self, state: &mut State) -> Result<ForeignCall> {
+        // string pool holds all of the strings that we have to re-allocate as CStrings. It and the
+        // popped values have to outlive the call, since the arguments only hold pointers into
+        // them.
         let mut string_pool = vec![];
         let mut val_args = vec![];
-        for p in &self.params {
-            let arg = state.pop()?;
-            let matches = match *p {
-                ItemType::Int(_) => arg.is_int(),
-                ItemType::Char(_) => arg.is_char(),
-                ItemType::String(_) => arg.is_string(),
-                ItemType::Bool(_) => arg.is_bool(),
-                //ItemType::Stack(_) => arg.is_stack(),
-                ItemType::Nil => arg.is_nil(),
-                _ => unreachable!(),
-            };
-            if !matches {
-                return Err(
-                    format!(
-                        "expected argument of type {}; instead got {}",
-                        p.type_string(),
-                        arg.type_string()
-                    ).into(),
-                );
-            }
-            val_args.push(arg);
+        for _ in &self.params {
+            val_args.push(state.pop()?);
         }
-
-        // convert the values into FFI arguments, which own copies of their appropriate types
-        // NOTE: ffi_args needs to live while val_args does, because of string pointers. I'm not
-        // sure how string pointers don't need a lifetime, but I'm not going to take risks.
-        let ffi_args = val_args
+        let mut ffi_args = self.params
             .iter()
-            .map(|v| match v {
-                &BCVal::Int(i) => FfiVal::Int(i),
-                &BCVal::Char(c) => FfiVal::Char(c as u8),
-                &BCVal::String(ref s) => {
-                    let c_str = CString::new(s.as_str()).unwrap();
-                    string_pool.push(c_str);
-                    FfiVal::String(string_pool.last().as_ref().unwrap().as_ptr())
-                }
-                &BCVal::Bool(b) => FfiVal::Bool(if b { 1 } else { 0 }),
-                &BCVal::Nil => FfiVal::Nil(()),
-                _ => unreachable!(),
+            .zip(val_args.iter())
+            .enumerate()
+            .map(|(index, (p, val))| {
+                let what = format!("argument {} of `{}`", index + 1, self.name);
+                marshal(&what, *p, val, &mut string_pool)
             })
-            .collect::<Vec<_>>();
-
-        let args = ffi_args.iter().map(|ref v| v.to_arg()).collect::<Vec<_>>();
-
-        let result = {
-            // doing the function gathering second (rather than first) because we need an immutable
-            // borrow of the VM state, and we can't pop things off while immutably borrowed.
-            let foreign = state
-                .foreign_functions
-                .get(&ff_key(&self.lib, &self.name))
-                .unwrap();
-            let code_ptr = CodePtr::from_ptr(*foreign as *const _);
-            match self.return_type {
-                ItemType::Int(_) => BCVal::Int(
-                    unsafe { call::<i32>(code_ptr, args.as_slice()) } as i64,
-                ),
-                ItemType::Char(_) => BCVal::Char(
-                    unsafe { call::<u8>(code_ptr, args.as_slice()) } as char,
-                ),
-                ItemType::Bool(_) => BCVal::Bool(
-                    unsafe { call::<i32>(code_ptr, args.as_slice()) } != 0,
-                ),
-                ItemType::Nil => {
-                    unsafe { call::<()>(code_ptr, args.as_slice()) };
-                    BCVal::Nil
+            .collect::<Result<Vec<_>>>()?;
+        // extra arguments come from a local stack below the rest of the arguments
+        let mut arg_types = self.params.clone();
+        if self.variadic {
+            let extras = state.pop()?;
+            {
+                let extras = match extras {
+                    BCVal::Stack(ref extras) => extras,
+                    ref val => {
+                        return Err(
+                            format!(
+                                "expected local stack of extra arguments for `{}`; instead got {}",
+                                self.name,
+                                val.type_string()
+                            ).into(),
+                        )
+                    }
+                };
+                for (index, val) in extras.iter().enumerate() {
+                    let what = format!("extra argument {} of `{}`", index + 1, self.name);
+                    let (ty, ffi_val) = marshal_extra(&what, val, &mut string_pool)?;
+                    arg_types.push(ty);
+                    ffi_args.push(ffi_val);
                 }
-                _ => unreachable!(),
             }
-        };
-
-        // don't push nil values
-        if !result.is_nil() {
-            state.push(result);
+            val_args.push(extras);
         }
-        Ok(())
+        let cif = Cif::new(
+            arg_types.iter().map(ForeignType::ffi_type),
+            self.return_type.ffi_type(),
+        );
+        if self.variadic {
+            // some platforms pass extra arguments differently, so libffi has to know where the
+            // fixed ones stop
+            unsafe {
+                let raw = cif.as_raw_ptr();
+                low::prep_cif_var(
+                    raw,
+                    ffi_abi_FFI_DEFAULT_ABI,
+                    self.params.len(),
+                    arg_types.len(),
+                    (*raw).rtype,
+                    (*raw).arg_types,
+                ).map_err(|_| {
+                    Error::from(format!("could not set up a call to variadic function `{}`", self.name))
+                })?;
+            }
+        }
+        let library = state.libraries.get(&self.lib).expect("foreign function was not loaded");
+        let code_ptr = CodePtr::from_ptr(library.symbol(&self.name)? as *const _);
+        Ok(ForeignCall {
+            name: self.name.clone(),
+            return_type: self.return_type,
+            cif,
+            code_ptr,
+            args: ffi_args,
+            _string_pool: string_pool,
+            _vals: val_args,
+        })
     }
 
+    /// Loads this function's library into the current state, if it hasn't been loaded already,
+    /// and makes sure that the function can be found in it.
     pub(in vm) fn load(&self, state: &mut State) -> Result<()> {
-        self.load_handle(state)?;
-        self.load_function(state)?;
+        if !state.libraries.contains_key(&self.lib) {
+            let library = ForeignLibrary::open(&self.lib)?;
+            state.libraries.insert(self.lib.clone(), Arc::new(library));
+        }
+        state.libraries[&self.lib].symbol(&self.name)?;
         Ok(())
     }
+}
 
-    /// Loads a function pointer into the current state.
-    fn load_function(&self, state: &mut State) -> Result<()> {
-        if !state.foreign_functions.contains_key(
-            &ff_key(&self.lib, &self.name),
+/// Makes sure that the library of each foreign function can be opened, and that each function can
+/// be found in its library. Errors point at the declaration of the function that failed.
+pub fn check_foreign_funs<'a, I>(funs: I) -> Result<()>
+where
+    I: IntoIterator<Item = &'a ForeignFun>,
+{
+    // the libraries are closed again once they've been checked
+    let mut libraries = BTreeMap::new();
+    for fun in funs {
+        if !libraries.contains_key(&fun.lib) {
+            let library = ForeignLibrary::open(&fun.lib).chain_err(|| fun.range())?;
+            libraries.insert(fun.lib.clone(), library);
+        }
+        libraries[&fun.lib].symbol(&fun.name).chain_err(|| fun.range())?;
+    }
+    Ok(())
+}
+
+impl StructFun {
+    /// Gets where the struct that a pointer or buffer holds starts, making sure that a buffer is
+    /// big enough to hold it.
+    fn struct_ptr(&self, val: &BCVal) -> Result<*mut u8> {
+        let struct_type = &self.struct_type;
+        match *val {
+            BCVal::Pointer(p) => Ok(p as *mut u8),
+            BCVal::Buffer(ref b) if b.len() >= struct_type.size => Ok(b.as_ptr()),
+            BCVal::Buffer(ref b) => Err(
+                format!(
+                    "a buffer of {} bytes is too small for struct `{}`, which takes {} bytes",
+                    b.len(),
+                    struct_type.name,
+                    struct_type.size
+                ).into(),
+            ),
+            _ => Err(
+                format!(
+                    "expected pointer or buffer for struct `{}`; instead got {}",
+                    struct_type.name,
+                    val.type_string()
+                ).into(),
+            ),
+        }
+    }
+
+    fn describe_field(&self, index: usize) -> String {
+        format!(
+            "field `{}` of struct `{}`",
+            self.struct_type.fields[index].name,
+            self.struct_type.name
         )
-        {
-            let handle = state.dl_handles.get(&self.lib).unwrap();
-            let fname = CString::new(self.name.clone()).unwrap();
-            unsafe {
-                let fun = libc::dlsym(*handle, fname.as_ptr());
-                if fun.is_null() {
-                    return Err(
-                        format!(
-                            "could not find symbol `{}` in dynamic library `{}`",
-                            &self.name,
-                            &self.lib
-                        ).into(),
-                    );
-                }
-                state.foreign_functions.insert(
-                    ff_key(&self.lib, &self.name),
-                    fun,
-                );
+    }
+
+    fn read_field(&self, index: usize, target: &BCVal) -> Result<BCVal> {
+        let field = &self.struct_type.fields[index];
+        let base = self.struct_ptr(target)?;
+        unsafe {
+            let src = base.offset(field.offset as isize);
+            // bools in structs are a single byte, rather than the int that they're passed as
+            if field.field_type == ForeignType::Bool {
+                return Ok(BCVal::Bool(*src != 0));
             }
+            read_value(&self.describe_field(index), field.field_type, src)
         }
+    }
+
+    fn write_field(&self, index: usize, target: &BCVal, val: &BCVal) -> Result<()> {
+        let field = &self.struct_type.fields[index];
+        let base = self.struct_ptr(target)?;
+        // strings can't be stored in structs, so nothing ends up in the pool
+        let ffi_val = match marshal(&self.describe_field(index), field.field_type, val, &mut vec![])? {
+            FfiVal::Int(b) if field.field_type == ForeignType::Bool => FfiVal::U8(b as u8),
+            ffi_val => ffi_val,
+        };
+        unsafe { ffi_val.write_to(base.offset(field.offset as isize)) };
         Ok(())
     }
 
-    /// Loads a handle into the current state.
-    fn load_handle(&self, state: &mut State) -> Result<()> {
-        if !state.dl_handles.contains_key(&self.lib) {
-            let lib_str = CString::new(self.lib.clone()).unwrap();
-            unsafe {
-                let handle = libc::dlopen(lib_str.as_ptr(), RTLD_NOW);
-                if handle.is_null() {
-                    return Err(
-                        format!("could not open dynamic library `{}`", &self.lib).into(),
-                    );
-                }
-                state.dl_handles.insert(self.lib.clone(), handle);
+    /// Makes a call into a struct function. This only reads and writes struct memory, and never
+    /// calls into C, so unlike a foreign function call it can't be reentered by a callback and
+    /// may borrow the state for the whole call.
+    pub(in vm) fn call(&self, state: &mut State) -> Result<()> {
+        match self.kind {
+            StructFunKind::New => state.push(BCVal::Buffer(Buffer::new(self.struct_type.size))),
+            StructFunKind::Size => state.push(BCVal::Int(self.struct_type.size as i64)),
+            StructFunKind::Get(index) => {
+                let target = state.pop()?;
+                let val = self.read_field(index, &target)?;
+                state.push(target);
+                state.push(val);
             }
-        };
+            StructFunKind::Take(index) => {
+                let target = state.pop()?;
+                let val = self.read_field(index, &target)?;
+                state.push(val);
+            }
+            StructFunKind::Set(index) => {
+                let val = state.pop()?;
+                let target = state.pop()?;
+                self.write_field(index, &target, &val)?;
+                state.push(target);
+            }
+        }
         Ok(())
     }
 }
@@ -0,0 +1,159 @@
+use prelude::*;
+use std::cmp;
+use std::collections::HashSet;
+use std::mem;
+
+/// The smallest number of live values that the heap will grow to before collecting garbage.
+const MIN_THRESHOLD: usize = 1024;
+
+/// The values that `BCVal::Ref` values point to.
+///
+/// Memory is reclaimed with a simple mark and sweep collector, which is handed the values that are
+/// reachable by the program (the roots) when it runs. Refs that are only reachable through other
+/// heap values, including cycles of refs, are collected once the roots can no longer reach them.
+/// Values on the stacks of suspended coroutines are reachable through the coroutine.
+#[derive(Clone, Debug)]
+pub struct Heap {
+    slots: Vec<Option<BCVal>>,
+    free: Vec<usize>,
+    threshold: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            slots: vec![],
+            free: vec![],
+            threshold: MIN_THRESHOLD,
+        }
+    }
+
+    /// Gets the number of values that are currently allocated.
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    /// Whether no values are currently allocated.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether enough values have been allocated since the last collection that it's time for
+    /// another one.
+    pub fn should_collect(&self) -> bool {
+        self.len() >= self.threshold
+    }
+
+    /// Allocates a new value, returning its address.
+    pub fn alloc(&mut self, val: BCVal) -> usize {
+        if let Some(addr) = self.free.pop() {
+            self.slots[addr] = Some(val);
+            addr
+        } else {
+            self.slots.push(Some(val));
+            self.slots.len() - 1
+        }
+    }
+
+    /// Gets the value at the given address.
+    pub fn get(&self, addr: usize) -> Result<&BCVal> {
+        self.slots
+            .get(addr)
+            .and_then(Option::as_ref)
+            .ok_or_else(|| format!("attempted to use a ref that has been freed (0x{:X})", addr).into())
+    }
+
+    /// Replaces the value at the given address, returning the old value.
+    pub fn set(&mut self, addr: usize, val: BCVal) -> Result<BCVal> {
+        match self.slots.get_mut(addr) {
+            Some(&mut Some(ref mut slot)) => Ok(mem::replace(slot, val)),
+            _ => Err(format!("attempted to use a ref that has been freed (0x{:X})", addr).into()),
+        }
+    }
+
+    /// Frees every value that can't be reached from the given roots.
+    pub fn collect<'a, I>(&mut self, roots: I)
+    where
+        I: IntoIterator<Item = &'a BCVal>,
+    {
+        // mark; values are copied into the pending list, since a suspended coroutine's values can
+        // only be looked at while it's borrowed
+        let mut marked = vec![false; self.slots.len()];
+        let mut coroutines = HashSet::new();
+        let mut pending = roots.into_iter().cloned().collect::<Vec<_>>();
+        while let Some(val) = pending.pop() {
+            match val {
+                BCVal::Ref(addr) => {
+                    if addr < marked.len() && !marked[addr] {
+                        marked[addr] = true;
+                        if let Some(ref val) = self.slots[addr] {
+                            pending.push(val.clone());
+                        }
+                    }
+                }
+                BCVal::Stack(ref vals) => pending.extend(vals.iter().cloned()),
+                BCVal::PushAll(ref vals) => pending.extend(vals.iter().cloned()),
+                BCVal::Map(ref map) => pending.extend(map.values().cloned()),
+                BCVal::Record(ref record) => pending.extend(record.fields.iter().cloned()),
+                BCVal::Coroutine(ref coroutine) => {
+                    if coroutines.insert(coroutine.addr()) {
+                        pending.extend(coroutine.vals());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // sweep
+        for (addr, slot) in self.slots.iter_mut().enumerate() {
+            if slot.is_some() && !marked[addr] {
+                *slot = None;
+                self.free.push(addr);
+            }
+        }
+        self.threshold = cmp::max(MIN_THRESHOLD, self.len() * 2);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+    use super::*;
+
+    #[test]
+    fn test_heap_collect() {
+        let mut heap = Heap::new();
+        let kept = heap.alloc(BCVal::Int(1));
+        let inner = heap.alloc(BCVal::Int(2));
+        let outer = heap.alloc(BCVal::Stack(Rc::new(vec![BCVal::Ref(inner)])));
+        heap.alloc(BCVal::Int(3));
+        heap.collect(&[BCVal::Ref(kept), BCVal::Ref(outer)]);
+        assert_eq!(heap.len(), 3);
+        assert_eq!(heap.get(inner).unwrap(), &BCVal::Int(2));
+
+        // freed slots get reused
+        heap.collect(Vec::new());
+        assert_eq!(heap.len(), 0);
+        assert!(heap.get(kept).is_err());
+        let addr = heap.alloc(BCVal::Nil);
+        assert!(addr < 4);
+    }
+
+    #[test]
+    fn test_heap_collect_cycles() {
+        let mut heap = Heap::new();
+        let a = heap.alloc(BCVal::Nil);
+        let b = heap.alloc(BCVal::Ref(a));
+        heap.set(a, BCVal::Ref(b)).unwrap();
+        heap.collect(&[BCVal::Ref(a)]);
+        assert_eq!(heap.len(), 2);
+        heap.collect(Vec::new());
+        assert_eq!(heap.len(), 0);
+    }
+}
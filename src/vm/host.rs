@@ -0,0 +1,144 @@
+use prelude::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::{self, Formatter, Debug};
+use std::rc::Rc;
+
+/// A closure that has been registered as a builtin by the program that SBL is embedded in.
+pub type HostClosure = Box<dyn FnMut(&mut State) -> Result<()>>;
+
+/// A builtin function that was registered by the program that SBL is embedded in. Unlike the
+/// standard builtins, these may hold on to whatever context they need.
+#[derive(Clone)]
+pub struct HostFun {
+    pub name: String,
+    /// How many items the function takes off of the stack.
+    pub arity: usize,
+    fun: Rc<RefCell<HostClosure>>,
+}
+
+impl HostFun {
+    pub fn new(name: String, arity: usize, fun: HostClosure) -> Self {
+        HostFun {
+            name,
+            arity,
+            fun: Rc::new(RefCell::new(fun)),
+        }
+    }
+
+    /// Makes a call into a host function, making sure that there's enough on the stack for it
+    /// first, and that it didn't take more than its arity off of the stack.
+    pub(in vm) fn call(&self, state: &mut State) -> Result<()> {
+        if state.stack_size() < self.arity {
+            return Err(
+                format!(
+                    "`{}` takes {} items off of the stack, but the stack only has {}",
+                    self.name,
+                    self.arity,
+                    state.stack_size()
+                ).into(),
+            );
+        }
+        let mut fun = self.fun.try_borrow_mut().map_err(|_| {
+            Error::from(format!("`{}` was called while it was already running", self.name))
+        })?;
+        let floor = state.stack_size() - self.arity;
+        (*fun)(state)?;
+        if state.stack_size() < floor {
+            return Err(
+                format!(
+                    "`{}` takes {} items off of the stack, but it took {}",
+                    self.name,
+                    self.arity,
+                    floor + self.arity - state.stack_size()
+                ).into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Debug for HostFun {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "HostFun {{ name: {} arity: {} }}", self.name, self.arity)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Builtin {
+    Standard(&'static BuiltinFun),
+    Host(HostFun),
+}
+
+/// A set of builtin functions that SBL code is compiled against. This may hold any of the
+/// standard builtins, as well as closures that the host program registers.
+#[derive(Clone, Debug)]
+pub struct Builtins {
+    builtins: BTreeMap<String, Builtin>,
+}
+
+impl Builtins {
+    /// Creates an empty set of builtins.
+    pub fn new() -> Self {
+        Builtins {
+            builtins: BTreeMap::new(),
+        }
+    }
+
+    /// Creates a set of builtins holding all of the standard builtins.
+    pub fn standard() -> Self {
+        Builtins::from(&*BUILTINS)
+    }
+
+    /// Registers a closure as a builtin, which takes the given number of items off of the stack.
+    /// Calling it is an error if the stack has fewer items than that, or if the closure takes more
+    /// than that. This replaces any builtin that has the same name.
+    pub fn register<F>(&mut self, name: &str, arity: usize, fun: F)
+    where
+        F: FnMut(&mut State) -> Result<()> + 'static,
+    {
+        let host_fun = HostFun::new(name.to_string(), arity, Box::new(fun));
+        self.builtins.insert(name.to_string(), Builtin::Host(host_fun));
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.builtins.contains_key(name)
+    }
+
+    /// Gets the functions of all of these builtins, along with their names.
+    pub fn funs<U: UserFun>(&self) -> Vec<(String, Fun<U>)> {
+        self.builtins
+            .iter()
+            .map(|(name, builtin)| {
+                let fun = match *builtin {
+                    Builtin::Standard(fun) => Fun::BuiltinFun(fun),
+                    Builtin::Host(ref fun) => Fun::HostFun(fun.clone()),
+                };
+                (name.clone(), fun)
+            })
+            .collect()
+    }
+}
+
+impl Default for Builtins {
+    fn default() -> Self {
+        Builtins::new()
+    }
+}
+
+impl From<&'static BTreeMap<&'static str, BuiltinFun>> for Builtins {
+    fn from(other: &'static BTreeMap<&'static str, BuiltinFun>) -> Self {
+        Builtins {
+            builtins: other
+                .iter()
+                .map(|(name, fun)| (name.to_string(), Builtin::Standard(fun)))
+                .collect(),
+        }
+    }
+}
+
+impl<'a> From<&'a Builtins> for Builtins {
+    fn from(other: &'a Builtins) -> Self {
+        other.clone()
+    }
+}
@@ -0,0 +1,207 @@
+use prelude::*;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+/// The name that snippets passed to `eval` are compiled under. This isn't a valid identifier, so
+/// it can't clash with any function that's been loaded.
+const EVAL_FUN: &str = "<eval>";
+
+/// A VM that SBL code can be loaded into and run from, for programs that embed SBL.
+///
+/// The VM state is kept between calls, so values that one call leaves on the stack are still
+/// there for the next one. Code that is loaded or evaluated is compiled on its own, against the
+/// functions that have been loaded so far; those aren't compiled, baked, or checked again.
+pub struct Interpreter {
+    builtins: Builtins,
+    search_dirs: Vec<PathBuf>,
+    optimizations: Optimizations,
+    check_foreign: bool,
+    /// The IR of every function that has been loaded so far.
+    ir_funs: IRFunTable,
+    /// The bytecode of every function that has been loaded so far, before it was optimized.
+    bc_funs: BCFunTable,
+    vm: VM,
+}
+
+/// Code that has been compiled, and can be installed in an interpreter's VM.
+struct Compiled {
+    ir_funs: IRFunTable,
+    bc_funs: BCFunTable,
+    optimized: BCFunTable,
+}
+
+impl Interpreter {
+    /// Creates an interpreter with the standard builtins and all optimizations enabled.
+    pub fn new() -> Self {
+        Interpreter {
+            builtins: Builtins::standard(),
+            search_dirs: vec![],
+            optimizations: Optimizations::default(),
+            check_foreign: true,
+            ir_funs: IRFunTable::new(),
+            bc_funs: BCFunTable::new(),
+            vm: VM::new(BCFunTable::new()),
+        }
+    }
+
+    /// Sets the builtins that code is compiled against.
+    pub fn builtins<B: Into<Builtins>>(mut self, builtins: B) -> Self {
+        self.builtins = builtins.into();
+        self
+    }
+
+    /// Sets the directories that imports are searched for in.
+    pub fn search_dirs<P: AsRef<Path>>(mut self, search_dirs: &[P]) -> Self {
+        self.search_dirs = search_dirs.iter().map(|p| p.as_ref().to_path_buf()).collect();
+        self
+    }
+
+    /// Sets which optimizations are applied. `Optimizations::empty()` turns them all off.
+    pub fn optimizations(mut self, optimizations: Optimizations) -> Self {
+        self.optimizations = optimizations;
+        self
+    }
+
+    /// Sets whether foreign libraries and functions are looked up while compiling.
+    pub fn check_foreign(mut self, check_foreign: bool) -> Self {
+        self.check_foreign = check_foreign;
+        self
+    }
+
+    /// Loads the functions in a source file, along with anything that it imports.
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let ast = process_source_path(path, &self.search_dirs)?;
+        self.load(ast)
+    }
+
+    /// Loads the functions in a string of source code. The name is used in error messages.
+    pub fn load_str(&mut self, name: &str, code: &str) -> Result<()> {
+        let tokenizer = Tokenizer::new(name, code);
+        let mut parser = Parser::new(tokenizer);
+        let ast = AST {
+            ast: parser.parse()?,
+            path: name.to_string(),
+        }.preprocess(&self.search_dirs)?;
+        self.load(ast)
+    }
+
+    fn load(&mut self, ast: AST) -> Result<()> {
+        let compiled = self.compile(&ast)?;
+        self.install(compiled)
+    }
+
+    /// Calls a function that has been loaded (or a builtin). The arguments are pushed so that the
+    /// first one ends up on top of the stack, and anything that the function returns is left on
+    /// the stack.
+    pub fn call(&mut self, name: &str, args: &[BCVal]) -> Result<()> {
+        self.vm.state_mut().push_all(
+            &args.iter().rev().cloned().collect::<Vec<_>>(),
+        );
+        let result = self.vm.invoke(name);
+        self.finish(result)
+    }
+
+    /// Runs a snippet of code as though it were the body of a function. The snippet can call
+    /// anything that has been loaded, and works on the same stack; its locals go away once it's
+    /// done.
+    pub fn eval(&mut self, code: &str) -> Result<()> {
+        let tokenizer = Tokenizer::new(EVAL_FUN, code);
+        let mut parser = Parser::new(tokenizer);
+        let stmts = parser.parse_stmts()?;
+        if stmts.is_empty() {
+            return Ok(());
+        }
+        let mut tokens = vec![];
+        for stmt in &stmts {
+            tokens.append_node(stmt);
+        }
+        let block = Block::new(tokens.clone(), stmts);
+        let ast = AST {
+            ast: vec![TopLevel::BCFunDef(BCFunDef::new(tokens, EVAL_FUN.to_string(), block))],
+            path: EVAL_FUN.to_string(),
+        };
+        let mut compiled = self.compile(&ast)?;
+        compiled.ir_funs.remove(EVAL_FUN);
+        compiled.bc_funs.remove(EVAL_FUN);
+        let eval_fun = match compiled.optimized.remove(EVAL_FUN) {
+            Some(Fun::UserFun(fun)) => fun,
+            _ => unreachable!(),
+        };
+        self.install(compiled)?;
+        let result = self.vm.inject_user_fun(eval_fun);
+        self.finish(result)
+    }
+
+    /// Gets the stack, from bottom to top.
+    pub fn stack(&self) -> &[BCVal] {
+        &self.vm.state().stack
+    }
+
+    /// Takes everything off of the stack, from bottom to top.
+    pub fn take_stack(&mut self) -> Vec<BCVal> {
+        mem::replace(&mut self.vm.state_mut().stack, vec![])
+    }
+
+    pub fn state(&self) -> &State {
+        self.vm.state()
+    }
+
+    pub fn state_mut(&mut self) -> &mut State {
+        self.vm.state_mut()
+    }
+
+    /// Compiles code against the functions that have already been loaded.
+    fn compile(&self, ast: &AST) -> Result<Compiled> {
+        let ir_compiler = CompileIR::new(ast)
+            .builtins(&self.builtins)
+            .defined(&self.ir_funs)
+            .check_foreign(self.check_foreign);
+        let ir_funs = ir_compiler.compile()?;
+        let compiler = CompileBytes::new(ir_funs.clone()).defined(&self.bc_funs);
+        let bc_funs = compiler.compile().chain_err(|| "Compile error")?;
+        // the functions that were loaded before are optimized along with the new ones, so they
+        // can be inlined, but they've already been installed
+        let mut all = self.bc_funs.clone();
+        all.extend(bc_funs.iter().map(|(k, v)| (k.clone(), v.clone())));
+        let mut pipeline = OptimizePipeline::new(all);
+        pipeline.flags = self.optimizations;
+        let optimized = pipeline.optimize()
+            .into_iter()
+            .filter(|&(ref k, _)| bc_funs.contains_key(k))
+            .collect();
+        Ok(Compiled {
+            ir_funs,
+            bc_funs,
+            optimized,
+        })
+    }
+
+    /// Loads the foreign functions of compiled code, and adds all of its functions to the VM.
+    /// Functions that the VM already has keep their place in its function table, so anything that
+    /// was linked against them still works.
+    fn install(&mut self, compiled: Compiled) -> Result<()> {
+        for fun in compiled.optimized.values() {
+            if let &Fun::ForeignFun(ref fun) = fun {
+                fun.load(self.vm.state_mut())?;
+            }
+        }
+        self.vm.add_funs(compiled.optimized);
+        self.ir_funs.extend(compiled.ir_funs);
+        self.bc_funs.extend(compiled.bc_funs);
+        Ok(())
+    }
+
+    /// Cleans up after a call, so the next one starts from the main program's context.
+    fn finish(&mut self, result: Result<()>) -> Result<()> {
+        if result.is_err() {
+            self.vm.state_mut().unwind();
+        }
+        result
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
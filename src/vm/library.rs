@@ -0,0 +1,204 @@
+use prelude::*;
+use libc::{self, RTLD_NOW, c_void};
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::{CStr, CString};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The versions that are tried for a library that's given by its short name.
+const SONAME_VERSIONS: Range<u32> = 0..10;
+
+/// A dynamic library that foreign functions are loaded from. The library is closed once this is
+/// dropped, and symbols are cached as they're looked up.
+#[derive(Debug)]
+pub struct ForeignLibrary {
+    /// The name of the library, as it was written in the foreign block.
+    name: String,
+    handle: *mut c_void,
+    symbols: Mutex<BTreeMap<String, *mut c_void>>,
+}
+
+// library handles and the symbols in them aren't tied to the thread that opened them, and looking
+// up symbols is thread safe; the symbol cache is behind a lock
+unsafe impl Send for ForeignLibrary {}
+unsafe impl Sync for ForeignLibrary {}
+
+impl ForeignLibrary {
+    /// Opens a dynamic library, looking for it in the directories listed in `SBL_LIB_PATH`
+    /// before the places that the system looks.
+    pub fn open(name: &str) -> Result<Self> {
+        let search_dirs = match env::var_os("SBL_LIB_PATH") {
+            Some(p) => env::split_paths(&p).collect::<Vec<_>>(),
+            None => vec![],
+        };
+        ForeignLibrary::open_in(name, &search_dirs)
+    }
+
+    /// Opens a dynamic library, looking for it in the given directories before the places that the
+    /// system looks.
+    ///
+    /// A name without a `.so` in it may also be given without its `lib` prefix and extension, so
+    /// `m` finds `libm.so`. Since `libm.so` is often a linker script, or isn't installed at all
+    /// without development packages, versioned names like `libm.so.6` are tried after it. Names
+    /// with a `/` in them are opened as-is.
+    pub fn open_in<P: AsRef<Path>>(name: &str, search_dirs: &[P]) -> Result<Self> {
+        let mut file_names = vec![name.to_string()];
+        if !name.contains(".so") {
+            file_names.push(format!("lib{}.so", name));
+            file_names.extend(SONAME_VERSIONS.map(|v| format!("lib{}.so.{}", name, v)));
+        }
+        let mut paths = vec![];
+        if !name.contains('/') {
+            paths.extend(
+                search_dirs
+                    .iter()
+                    .flat_map(|dir| file_names.iter().map(move |f| dir.as_ref().join(f)))
+                    .filter(|path| path.is_file()),
+            );
+        }
+        paths.extend(file_names.iter().map(PathBuf::from));
+        let mut errors = vec![];
+        for path in &paths {
+            match ForeignLibrary::open_path(name, path) {
+                Ok(library) => return Ok(library),
+                Err(e) => errors.push(format!("`{}` ({})", path.display(), e)),
+            }
+        }
+        Err(
+            format!(
+                "could not open dynamic library `{}`: tried {}",
+                name,
+                errors.join(", ")
+            ).into(),
+        )
+    }
+
+    fn open_path(name: &str, path: &Path) -> Result<Self> {
+        let path_str = path.to_str()
+            .and_then(|p| CString::new(p).ok())
+            .ok_or_else(|| Error::from("invalid path"))?;
+        let handle = unsafe { libc::dlopen(path_str.as_ptr(), RTLD_NOW) };
+        if handle.is_null() {
+            Err(dl_error().into())
+        } else {
+            Ok(ForeignLibrary {
+                name: name.to_string(),
+                handle,
+                symbols: Mutex::new(BTreeMap::new()),
+            })
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Looks up a symbol in this library.
+    pub fn symbol(&self, symbol: &str) -> Result<*mut c_void> {
+        let mut symbols = self.symbols.lock().unwrap();
+        if let Some(ptr) = symbols.get(symbol) {
+            return Ok(*ptr);
+        }
+        let symbol_str = CString::new(symbol)
+            .map_err(|_| Error::from(format!("symbol `{}` has a NUL character in it", symbol)))?;
+        let ptr = unsafe { libc::dlsym(self.handle, symbol_str.as_ptr()) };
+        if ptr.is_null() {
+            return Err(
+                format!(
+                    "could not find symbol `{}` in dynamic library `{}`",
+                    symbol,
+                    self.name
+                ).into(),
+            );
+        }
+        symbols.insert(symbol.to_string(), ptr);
+        Ok(ptr)
+    }
+}
+
+impl Drop for ForeignLibrary {
+    fn drop(&mut self) {
+        unsafe { libc::dlclose(self.handle) };
+    }
+}
+
+/// Gets the text of the most recent dynamic linking error.
+fn dl_error() -> String {
+    let err = unsafe { libc::dlerror() };
+    if err.is_null() {
+        "unknown error".to_string()
+    } else {
+        unsafe { CStr::from_ptr(err) }.to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use vm::library::*;
+    use std::fs;
+    use std::os::unix;
+    use std::process;
+    use std::sync::Arc;
+
+    /// Gets the path of a library that this process has loaded, by the start of its file name.
+    fn loaded_library_path(prefix: &str) -> Option<PathBuf> {
+        let maps = fs::read_to_string("/proc/self/maps").ok()?;
+        maps.lines()
+            .filter_map(|line| line.split_whitespace().nth(5))
+            .map(PathBuf::from)
+            .find(|path| {
+                path.file_name()
+                    .and_then(|f| f.to_str())
+                    .map(|f| f.starts_with(prefix))
+                    .unwrap_or(false)
+            })
+    }
+
+    #[test]
+    fn test_library_search_path() {
+        let libc_path = loaded_library_path("libc.so").or_else(|| loaded_library_path("libc-"))
+            .expect("libc is not loaded");
+        let dir = env::temp_dir().join(format!("sbl-library-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let link = dir.join("libsbltest.so");
+        let _ = fs::remove_file(&link);
+        unix::fs::symlink(&libc_path, &link).unwrap();
+        fs::write(dir.join("libbroken.so"), b"not a library").unwrap();
+
+        // libraries are found in the search path by their file name, or their short name
+        let library = ForeignLibrary::open_in("libsbltest.so", &[&dir]).unwrap();
+        assert_eq!(library.name(), "libsbltest.so");
+        assert!(!library.symbol("abs").unwrap().is_null());
+        // symbols are cached
+        assert_eq!(library.symbol("abs").unwrap(), library.symbol("abs").unwrap());
+        assert!(library.symbol("abz").is_err());
+        assert!(ForeignLibrary::open_in("sbltest", &[&dir]).is_ok());
+        assert!(ForeignLibrary::open_in("libsbltest.so", &[] as &[PathBuf]).is_err());
+        // the system's search still works, and failures say why
+        assert!(ForeignLibrary::open_in("libc.so.6", &[&dir]).is_ok());
+        let message = ForeignLibrary::open_in("libbroken.so", &[&dir]).unwrap_err().to_string();
+        let prefix = "could not open dynamic library `libbroken.so`: tried ";
+        assert!(message.starts_with(prefix), "{}", message);
+        let broken_path = dir.join("libbroken.so");
+        assert!(message.contains(&format!("`{}` (", broken_path.display())), "{}", message);
+        assert!(message.contains("`libbroken.so` ("), "{}", message);
+        // short names fall back to versioned file names, and report every one that they tried
+        assert!(ForeignLibrary::open_in("m", &[&dir]).is_ok());
+        let message = ForeignLibrary::open_in("broken", &[&dir]).unwrap_err().to_string();
+        for path in &["libbroken.so", "libbroken.so.0", "libbroken.so.9"] {
+            assert!(message.contains(&format!("`{}` (", path)), "{}", message);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_library_is_send() {
+        // this only has to compile; the libraries that a VM has loaded can go to another thread
+        fn check<T: Send + Sync>() {}
+        check::<ForeignLibrary>();
+        check::<BTreeMap<String, Arc<ForeignLibrary>>>();
+    }
+}
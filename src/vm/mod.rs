@@ -1,6 +1,24 @@
 mod vm;
+mod buffer;
 mod builtins;
+mod callback;
+mod coroutine;
+mod file;
 mod foreign;
+mod heap;
+mod host;
+mod interpreter;
+mod library;
+mod record;
 
 pub use self::vm::*;
+pub use self::buffer::*;
 pub use self::builtins::*;
+pub use self::callback::CallbackPtr;
+pub use self::coroutine::*;
+pub use self::file::*;
+pub use self::foreign::check_foreign_funs;
+pub use self::heap::*;
+pub use self::host::*;
+pub use self::interpreter::*;
+pub use self::library::*;
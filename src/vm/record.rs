@@ -0,0 +1,58 @@
+use prelude::*;
+use std::rc::Rc;
+
+impl RecordFun {
+    /// Pops a record of this function's record type off of the stack.
+    fn pop_record(&self, state: &mut State) -> Result<Rc<Record>> {
+        match state.pop()? {
+            BCVal::Record(ref record) if record.record_type == self.record_type => Ok(record.clone()),
+            val => Err(
+                format!(
+                    "expected {} record; instead got {}",
+                    self.record_type.name,
+                    val.type_string()
+                ).into(),
+            ),
+        }
+    }
+
+    /// Makes a call into a record function.
+    pub(in vm) fn call(&self, state: &mut State) -> Result<()> {
+        match self.kind {
+            RecordFunKind::Construct => {
+                let field_count = self.record_type.fields.len();
+                if state.stack.len() < field_count {
+                    return Err(
+                        format!(
+                            "{} record has {} fields, but there are only {} items on the stack",
+                            self.record_type.name,
+                            field_count,
+                            state.stack.len()
+                        ).into(),
+                    );
+                }
+                let fields = state.pop_vals(field_count)?;
+                let record = Record::new(self.record_type.clone(), fields);
+                state.push(BCVal::Record(Rc::new(record)));
+            }
+            RecordFunKind::Get(index) => {
+                let record = self.pop_record(state)?;
+                let val = record.fields[index].clone();
+                state.push(BCVal::Record(record));
+                state.push(val);
+            }
+            RecordFunKind::Take(index) => {
+                let record = self.pop_record(state)?;
+                state.push(record.fields[index].clone());
+            }
+            RecordFunKind::Set(index) => {
+                let val = state.pop()?;
+                let mut record = self.pop_record(state)?;
+                // this only copies the record if some other value is also using it
+                Rc::make_mut(&mut record).fields[index] = val;
+                state.push(BCVal::Record(record));
+            }
+        }
+        Ok(())
+    }
+}
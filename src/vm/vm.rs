@@ -1,8 +1,9 @@
 use prelude::*;
-use libc::c_void;
 use std::collections::BTreeMap;
-use std::cell::RefCell;
+use std::mem;
 use std::rc::Rc;
+use std::sync::Arc;
+use super::builtins::*;
 
 #[derive(Clone, Debug)]
 pub struct BCFunState {
@@ -39,12 +40,58 @@ impl From<Rc<BCUserFun>> for BCFunState {
     }
 }
 
+/// A `try` block that is currently running.
+#[derive(Clone, Debug)]
+pub struct Handler {
+    /// The size of the call stack when the try block was entered.
+    pub call_depth: usize,
+    /// The size of the stack when the try block was entered.
+    pub stack_depth: usize,
+    /// The values that the try block has popped from below where it started, in the order that
+    /// they were popped.
+    pub popped: Vec<BCVal>,
+    /// The address of the catch block.
+    pub catch_addr: usize,
+}
+
+impl Handler {
+    /// The lowest that the stack has been since the try block was entered.
+    fn low(&self) -> usize {
+        self.stack_depth - self.popped.len()
+    }
+}
+
+/// The state of the VM.
+///
+/// The stack, call stack, and handlers belong to whichever context is currently running: either
+/// the main program's, or a coroutine's. When a coroutine is resumed, the context of whatever
+/// resumed it is set aside until the coroutine yields or returns.
+///
+/// A state can't be sent to another thread, since values, frames, coroutines, and callbacks are
+/// all reference counted with `Rc`. The libraries that it has loaded can be, though.
 #[derive(Clone, Debug)]
 pub struct State {
+    /// The stack. Values should only be taken off of it with `pop` and `popn`, which keep track
+    /// of what has to be put back if a try block catches an error.
     pub stack: Vec<BCVal>,
     pub call_stack: Vec<BCFunState>,
-    pub dl_handles: BTreeMap<String, *mut c_void>,
-    pub foreign_functions: BTreeMap<String, *mut c_void>,
+    pub handlers: Vec<Handler>,
+    /// The contexts that are waiting for a coroutine to yield, innermost last.
+    pub suspended: Vec<Context>,
+    /// The coroutines that are currently running, innermost last.
+    pub running: Vec<Coroutine>,
+    /// The most recently thrown value, which is kept here until it's caught.
+    pub thrown: Option<BCVal>,
+    /// The libraries that foreign functions have been loaded from, by the name that they were
+    /// declared with.
+    pub libraries: BTreeMap<String, Arc<ForeignLibrary>>,
+    /// The value of `errno` right after the most recent foreign function call.
+    pub errno: i32,
+    /// The callbacks that C has been given pointers to, by the name of the function they call.
+    /// These are never freed before the state is, since there's no telling when C is done with
+    /// them.
+    pub callbacks: BTreeMap<String, Rc<CallbackPtr>>,
+    pub heap: Heap,
 }
 
 impl State {
@@ -52,8 +99,14 @@ impl State {
         State {
             stack: vec![],
             call_stack: vec![],
-            dl_handles: BTreeMap::new(),
-            foreign_functions: BTreeMap::new(),
+            handlers: vec![],
+            suspended: vec![],
+            running: vec![],
+            thrown: None,
+            libraries: BTreeMap::new(),
+            errno: 0,
+            callbacks: BTreeMap::new(),
+            heap: Heap::new(),
         }
     }
 
@@ -70,9 +123,72 @@ impl State {
         }
     }
 
+    /// Clears everything but the libraries and callbacks. C may still hold pointers to callbacks
+    /// that it was given, so they live as long as the state does.
     pub fn clear(&mut self) {
         self.stack.clear();
         self.call_stack.clear();
+        self.handlers.clear();
+        self.suspended.clear();
+        self.running.clear();
+        self.thrown = None;
+        self.errno = 0;
+        self.heap = Heap::new();
+    }
+
+    /// Frees all heap values that can't be reached from the stack, any function's locals, values
+    /// that a try block will put back, a thrown value, or a context that's waiting on a coroutine.
+    pub fn collect_garbage(&mut self) {
+        let locals = self.call_stack
+            .iter()
+            .flat_map(|f| f.locals.iter().filter_map(Option::as_ref));
+        let handlers = self.handlers.iter().flat_map(|h| h.popped.iter());
+        let suspended = self.suspended.iter().flat_map(Context::vals);
+        self.heap.collect(
+            self.stack
+                .iter()
+                .chain(locals)
+                .chain(handlers)
+                .chain(self.thrown.iter())
+                .chain(suspended),
+        );
+    }
+
+    /// Drops everything that was running, going back to the main program's context. Values that
+    /// were left on the stack are kept. This gets the state ready to run something else after an
+    /// error.
+    pub fn unwind(&mut self) {
+        for coroutine in self.running.drain(..) {
+            coroutine.finish();
+        }
+        if !self.suspended.is_empty() {
+            let main = self.suspended.remove(0);
+            self.swap_context(main);
+            self.suspended.clear();
+        }
+        self.call_stack.clear();
+        self.handlers.clear();
+        self.thrown = None;
+    }
+
+    /// Replaces the current context with another one, returning the old one.
+    pub fn swap_context(&mut self, context: Context) -> Context {
+        Context {
+            stack: mem::replace(&mut self.stack, context.stack),
+            call_stack: mem::replace(&mut self.call_stack, context.call_stack),
+            handlers: mem::replace(&mut self.handlers, context.handlers),
+        }
+    }
+
+    /// Allocates a value on the heap, collecting garbage first if the heap has grown enough.
+    pub fn alloc(&mut self, mut val: BCVal) -> BCVal {
+        if self.heap.should_collect() {
+            // keep anything that the new value refers to alive
+            self.stack.push(val);
+            self.collect_garbage();
+            val = self.stack.pop().unwrap();
+        }
+        BCVal::Ref(self.heap.alloc(val))
     }
 
     pub fn load(&self, varnum: usize) -> Result<&BCVal> {
@@ -103,12 +219,24 @@ impl State {
 
     pub fn pop(&mut self) -> Result<BCVal> {
         if let Some(val) = self.stack.pop() {
+            self.save_popped(&val);
             Ok(val)
         } else {
             Err("attempted to pop an empty stack".into())
         }
     }
 
+    /// Keeps a value that was just popped for each try block that it was below the start of, so
+    /// it can be put back if the try block catches an error.
+    fn save_popped(&mut self, val: &BCVal) {
+        let len = self.stack.len();
+        for handler in &mut self.handlers {
+            if len < handler.low() {
+                handler.popped.push(val.clone());
+            }
+        }
+    }
+
     pub fn popn(&mut self, n: i64) -> Result<()> {
         let len = self.stack.len();
         if n < 0 {
@@ -129,12 +257,24 @@ impl State {
                     len
                 ).into(),
             )
-        } else {
+        } else if self.handlers.is_empty() {
             self.stack.truncate(len - n);
             Ok(())
+        } else {
+            for _ in 0..n {
+                self.pop()?;
+            }
+            Ok(())
         }
     }
 
+    /// Pops the top `n` values off of the stack, returning them from bottom to top.
+    pub fn pop_vals(&mut self, n: usize) -> Result<Vec<BCVal>> {
+        let mut vals = (0..n).map(|_| self.pop()).collect::<Result<Vec<_>>>()?;
+        vals.reverse();
+        Ok(vals)
+    }
+
     pub fn current_fun(&self) -> &BCFunState {
         self.call_stack.last().unwrap()
     }
@@ -168,253 +308,478 @@ impl State {
 
 impl From<VM> for State {
     fn from(other: VM) -> Self {
-        other.state.into_inner()
+        other.state
     }
 }
 
 #[derive(Clone)]
 pub struct VM {
-    fun_table: BCFunRcTable,
-    state: RefCell<State>,
-    user_fun_cache: BTreeMap<String, Rc<BCUserFun>>,
+    fun_table: LinkedFunTable,
+    state: State,
 }
 
 impl VM {
-    pub fn new(fun_table: BCFunTable) -> Self {
-        let mut rc_table = BCFunRcTable::new();
-        // TODO : itertools RC iterator
-        for (k, v) in fun_table {
-            rc_table.insert(k, Rc::new(v));
-        }
+    pub fn new<T: Into<LinkedFunTable>>(fun_table: T) -> Self {
         VM {
-            fun_table: rc_table,
-            state: RefCell::new(State::new()),
-            user_fun_cache: BTreeMap::new(),
+            fun_table: fun_table.into(),
+            state: State::new(),
         }
     }
 
     pub fn add_fun(&mut self, name: String, fun: BCFun) {
         // XXX - I don't like this function, is there a better way we can update a funtable owned
         // by a VM? (probably not)
-        self.fun_table.insert(name, Rc::new(fun));
+        self.fun_table.insert(name, fun);
+    }
+
+    /// Adds all of the functions in a function table, linking them against each other and against
+    /// the functions that are already here.
+    pub fn add_funs(&mut self, fun_table: BCFunTable) {
+        self.fun_table.extend(fun_table);
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
     }
 
     pub fn run(&mut self) -> Result<()> {
         // Load all of the foreign functions
-        for f in self.fun_table.iter().filter_map(|(_, f)| {
-            if let &Fun::ForeignFun(ref f) = f as &BCFun {
+        for f in self.fun_table.funs().iter().filter_map(|f| {
+            if let &Fun::ForeignFun(ref f) = f {
                 Some(f)
             } else {
                 None
             }
         })
         {
-            f.load(&mut self.state.borrow_mut())?;
+            f.load(&mut self.state)?;
         }
         self.invoke("main")
     }
 
+    /// Calls a function by its name, taking its arguments from the stack.
     pub fn invoke(&mut self, fun_name: &str) -> Result<()> {
-        if let Some(fun) = self.user_fun_cache.get(fun_name).map(Rc::clone) {
-            {
-                let mut state = self.state.borrow_mut();
-                state.push_fun(fun.into());
-            }
-            self.invoke_user_fun()?;
-            {
-                let mut state = self.state.borrow_mut();
-                state.pop_fun();
-            }
-            Ok(())
-        }
-        else {
-            let fun = self.fun_table
-                .get(fun_name)
-                .expect(&format!(
-                    "expected function `{}` but none was found; compiler should have caught this",
-                    fun_name
-                ))
-                .clone();
-            match &fun as &BCFun {
-                &Fun::UserFun(ref fun) => {
-                    // new user fun cache entry
-                    let ptr = Rc::new(fun.clone());
-                    self.user_fun_cache.insert(fun_name.to_string(), ptr.clone());
-                    {
-                        let mut state = self.state.borrow_mut();
-                        state.push_fun(ptr.clone().into());
-                    }
-                    self.invoke_user_fun()?;
-                    {
-                        let mut state = self.state.borrow_mut();
-                        state.pop_fun();
-                    }
-                    Ok(())
-                }
-                &Fun::BuiltinFun(fun) => fun(&mut self.state.borrow_mut()),
-                &Fun::ForeignFun(ref fun) => fun.call(&mut self.state.borrow_mut()),
-            }
+        let index = self.fun_index(fun_name)?;
+        if let &Fun::UserFun(ref fun) = self.fun_table.get(index) {
+            let fun = fun.clone();
+            return self.invoke_user_fun(fun);
         }
+        self.call_frameless(index)
     }
 
-    pub fn inject_user_fun(&mut self, fun: BCUserFun) -> Result<()> {
-        let rc = Rc::new(fun);
-        {
-            let mut state = self.state.borrow_mut();
-            state.push_fun(rc.into());
-        }
-        self.invoke_user_fun()?;
-        {
-            let mut state = self.state.borrow_mut();
-            state.pop_fun();
+    pub fn inject_user_fun(&mut self, mut fun: BCUserFun) -> Result<()> {
+        self.fun_table.link_body(&mut fun.body);
+        self.invoke_user_fun(Rc::new(fun))
+    }
+
+    /// Runs a user function that C has called back into. The arguments are pushed so that the
+    /// first one ends up on top of the stack, and the function's return value is popped if it
+    /// has one.
+    pub(in vm) fn call_back(&mut self, fun: Rc<BCUserFun>, args: Vec<BCVal>, returns: bool) -> Result<Option<BCVal>> {
+        self.state.push_all(&args.into_iter().rev().collect::<Vec<_>>());
+        self.invoke_user_fun(fun)?;
+        if returns {
+            Ok(Some(self.state.pop()?))
+        } else {
+            Ok(None)
         }
-        Ok(())
     }
 
     /// Clears the VM state. This is primarily used by bake blocks so they can reuse the same VM
     /// without retaining the effects of the previous bake block.
-    pub fn clear_state(&self) {
-        let mut state = self.state.borrow_mut();
-        state.clear();
+    pub fn clear_state(&mut self) {
+        self.state.clear();
     }
 
     /// Prints out the VM state to the command line.
     /// Useful for crash reports.
     pub fn dump_state(&self) {
-        let state = self.state.borrow();
-        state.dump();
+        self.state.dump();
     }
 
-    fn invoke_user_fun(&mut self) -> Result<()> {
+    /// Gets the index of the function that a call instruction refers to. Calls that couldn't be
+    /// linked ahead of time are looked up by name.
+    fn call_index(&self, val: &BCVal) -> Result<usize> {
+        match val {
+            &BCVal::Address(index) => Ok(index),
+            &BCVal::Ident(ref fun_name) => self.fun_index(fun_name),
+            _ => unreachable!(),
+        }
+    }
+
+    fn fun_index(&self, fun_name: &str) -> Result<usize> {
+        self.fun_table
+            .index_of(fun_name)
+            .ok_or_else(|| format!("attempted to call unknown function `{}`", fun_name).into())
+    }
+
+    /// Calls a function that doesn't get a frame of its own, which is any function but a user
+    /// function.
+    fn call_frameless(&mut self, index: usize) -> Result<()> {
+        match self.fun_table.get(index) {
+            &Fun::UserFun(_) => unreachable!("user functions are called with a frame of their own"),
+            &Fun::BuiltinFun(callee) => callee(&mut self.state),
+            &Fun::HostFun(ref callee) => callee.call(&mut self.state),
+            &Fun::ForeignFun(ref callee) => {
+                let call = callee.prepare(&mut self.state)?;
+                // C may call back into this VM, so it's only reached through a pointer until C
+                // returns
+                unsafe { call.call(self) }
+            }
+            &Fun::RecordFun(ref callee) => callee.call(&mut self.state),
+            &Fun::StructFun(ref callee) => callee.call(&mut self.state),
+            &Fun::CallbackFun(ref callee) => callee.call(&self.fun_table, &mut self.state),
+        }
+    }
+
+    /// Pushes a new frame for the given user function, and runs it until it returns.
+    ///
+    /// If an error occurs, the call stack is left as-is so it can be dumped.
+    fn invoke_user_fun(&mut self, fun: Rc<BCUserFun>) -> Result<()> {
+        self.state.push_fun(fun.clone().into());
+        let depth = self.state.call_stack.len();
+        let mut fun = fun;
+        let mut pc = 0;
+        let result = self.dispatch(depth, &mut fun, &mut pc);
+        if result.is_ok() {
+            self.state.pop_fun();
+        } else {
+            // the program counter only lives in the frame while another function is being called
+            self.state.set_pc(pc);
+        }
+        result
+    }
+
+    /// Runs instructions until the frame at the given call stack depth returns, handling any
+    /// errors with the `try` blocks that were entered along the way.
+    fn dispatch(&mut self, depth: usize, fun: &mut Rc<BCUserFun>, pc: &mut usize) -> Result<()> {
+        // coroutines that were resumed before this point are left alone
+        let base = self.state.running.len();
+        loop {
+            match self.execute(depth, base, fun, pc) {
+                Ok(()) => return Ok(()),
+                Err(e) => self.catch(depth, base, fun, pc, e)?,
+            }
+        }
+    }
+
+    /// Switches back to the context that resumed the innermost running coroutine, picking up
+    /// where it left off. If the coroutine isn't done, its context is kept so it can be resumed
+    /// again.
+    fn leave_coroutine(&mut self, done: bool, fun: &mut Rc<BCUserFun>, pc: &mut usize) {
+        let coroutine = self.state.running.pop().unwrap();
+        let resumer = self.state.suspended.pop().unwrap();
+        let context = self.state.swap_context(resumer);
+        if done {
+            coroutine.finish();
+        } else {
+            coroutine.suspend(context);
+        }
+        let frame = self.state.current_fun();
+        *fun = frame.fun.clone();
+        *pc = frame.pc;
+    }
+
+    /// Unwinds to the innermost `try` block that's still running, and jumps to its catch block
+    /// with the error on top of the stack. Values that were thrown are pushed as-is; all other
+    /// errors are turned into error values.
+    ///
+    /// The call stack is truncated to its size from when the try block was entered, and the stack
+    /// is put back the way it was, including anything that the try block popped. Only try blocks
+    /// that were entered at or above the given call stack depth are used; if there aren't any,
+    /// the error is returned.
+    ///
+    /// A coroutine that doesn't catch an error is finished, and the error is passed on to
+    /// whatever resumed it.
+    fn catch(&mut self, depth: usize, base: usize, fun: &mut Rc<BCUserFun>, pc: &mut usize, err: Error) -> Result<()> {
+        let range = fun.body
+            .get(*pc)
+            .filter(|bc| !bc.tokens.is_empty())
+            .map(|bc| bc.tokens.range());
+        loop {
+            let in_coroutine = self.state.running.len() > base;
+            match self.state.handlers.last() {
+                Some(handler) if in_coroutine || handler.call_depth >= depth => break,
+                _ if in_coroutine => self.leave_coroutine(true, fun, pc),
+                _ => return Err(err),
+            }
+        }
+        let handler = self.state.handlers.pop().unwrap();
+        let val = match *err.kind() {
+            ErrorKind::Thrown(_) => self.state
+                .thrown
+                .take()
+                .expect("thrown value was not stored"),
+            _ => BCVal::Error(Rc::new(ErrorVal::new(err.to_string(), range))),
+        };
+        self.state.call_stack.truncate(handler.call_depth);
+        self.state.stack.truncate(handler.low());
+        self.state.stack.extend(handler.popped.into_iter().rev());
+        self.state.push(val);
+        *fun = self.state.current_fun().fun.clone();
+        *pc = handler.catch_addr;
+        Ok(())
+    }
+
+    /// The interpreter loop. This runs instructions until the frame at the given call stack depth
+    /// returns, or an error occurs.
+    ///
+    /// Calls to user functions push a new frame rather than recursing; the caller's frame keeps
+    /// the address of the call so execution can pick up after it once the callee returns.
+    ///
+    /// Resuming a coroutine switches to its context, and the resumer's frame keeps the address
+    /// to pick up from once the coroutine yields or returns. Coroutines may only yield back to
+    /// contexts that were running when this was called, which is given by `base`.
+    fn execute(&mut self, depth: usize, base: usize, fun: &mut Rc<BCUserFun>, pc: &mut usize) -> Result<()> {
         loop {
-            let (bc_type, target, val, fun) = {
-                let state = self.state.borrow();
-                let fun = state.current_fun();
-                let ref bc = fun.fun.body[fun.pc];
-                (bc.bc_type, bc.target.clone(), bc.val.clone(), fun.fun.clone())
-            };
-
-            {
-                match bc_type {
-                    BCType::Push => {
-                        let mut state = self.state.borrow_mut();
-                        state.push_all(val.unwrap().as_push_all());
-                        state.increment_pc();
+            let bc = &fun.body[*pc];
+            match bc.bc_type {
+                BCType::Push => {
+                    self.state.push_all(bc.val.as_ref().unwrap().as_push_all());
+                    *pc += 1;
+                }
+                BCType::PushL => {
+                    let item = self.state.pop()?;
+                    let mut stack = self.state.pop()?;
+                    if let BCVal::Stack(ref mut st) = stack {
+                        Rc::make_mut(st).push(item);
+                    } else {
+                        // This should - for now - never occur
+                        unreachable!();
                     }
-                    BCType::PushL => {
-                        let mut state = self.state.borrow_mut();
-                        let item = state.pop()?;
-                        let mut stack = state.pop()?;
-                        if let BCVal::Stack(ref mut st) = stack {
-                            st.push(item);
-                        } else {
-                            // This should - for now - never occur
-                            unreachable!();
+                    self.state.push(stack);
+                    *pc += 1;
+                }
+                BCType::IsType => {
+                    let val = self.state.pop()?;
+                    let is_type = bc.val
+                        .as_ref()
+                        .unwrap()
+                        .as_stack()
+                        .iter()
+                        .any(|t| val.has_type(t.as_string()));
+                    self.state.push(BCVal::Bool(is_type));
+                    *pc += 1;
+                }
+                BCType::Try | BCType::SymTry => {
+                    let catch_addr = if bc.bc_type == BCType::Try {
+                        *bc.val.as_ref().unwrap().as_address()
+                    } else {
+                        fun.get_label_address(*bc.val.as_ref().unwrap().as_int())
+                    };
+                    self.state.handlers.push(Handler {
+                        call_depth: self.state.call_stack.len(),
+                        stack_depth: self.state.stack.len(),
+                        popped: vec![],
+                        catch_addr,
+                    });
+                    *pc += 1;
+                }
+                BCType::EndTry => {
+                    self.state.handlers.pop();
+                    *pc += 1;
+                }
+                BCType::Spawn => {
+                    let index = self.call_index(bc.val.as_ref().unwrap())?;
+                    let callee = match self.fun_table.get(index) {
+                        &Fun::UserFun(ref callee) => callee.clone(),
+                        _ => {
+                            return Err(
+                                format!(
+                                    "attempted to spawn `{}`, which is not a user function",
+                                    self.fun_table.name(index)
+                                ).into(),
+                            )
                         }
-                        state.push(stack);
-                        state.increment_pc();
-                    }
-                    BCType::Pop => {
-                        // TODO : change POP to use 'target' instead of 'val'
-                        // TODO : change POP to use 'target' instead of 'val'
-                        // TODO : change POP to use 'target' instead of 'val'
-                        // TODO : change POP to use 'target' instead of 'val'
-                        // TODO : change POP to use 'target' instead of 'val'
-                        let mut state = self.state.borrow_mut();
-                        let tos = state.pop()?;
-                        let varnum = *val.unwrap()
-                            .as_address();
-                        state.store(varnum, tos);
-                        state.increment_pc();
-                    }
-                    BCType::PopN => {
-                        let mut state = self.state.borrow_mut();
-                        let i = *val.unwrap().as_int();
-                        state.popn(i)?;
-                        state.increment_pc();
-                    }
-                    BCType::PopDiscard => {
-                        let mut state = self.state.borrow_mut();
-                        state.pop()?;
-                        state.increment_pc();
+                    };
+                    self.state.push(BCVal::Coroutine(Coroutine::new(callee)));
+                    *pc += 1;
+                }
+                BCType::Resume => {
+                    let coroutine = match self.state.pop()? {
+                        BCVal::Coroutine(coroutine) => coroutine,
+                        val => {
+                            return Err(
+                                format!("expected coroutine; instead got {}", val.type_string())
+                                    .into(),
+                            )
+                        }
+                    };
+                    let context = coroutine.start()?;
+                    self.state.set_pc(*pc + 1);
+                    let resumer = self.state.swap_context(context);
+                    self.state.suspended.push(resumer);
+                    self.state.running.push(coroutine);
+                    let frame = self.state.current_fun();
+                    *fun = frame.fun.clone();
+                    *pc = frame.pc;
+                }
+                BCType::Yield => {
+                    if self.state.running.len() <= base {
+                        return Err("attempted to yield outside of a coroutine".into());
                     }
-                    BCType::Load => {
-                        let mut state = self.state.borrow_mut();
-                        let varnum = *val.unwrap()
-                            .as_address();
-                        let val = state.load(varnum)?.clone();
-                        state.push(val);
-                        state.increment_pc();
+                    let val = self.state.pop()?;
+                    self.state.set_pc(*pc + 1);
+                    self.leave_coroutine(false, fun, pc);
+                    self.state.push(val);
+                }
+                BCType::PushM => {
+                    let val = self.state.pop()?;
+                    let key = self.state.pop()?.to_map_key()?;
+                    let mut map = self.state.pop()?;
+                    if let BCVal::Map(ref mut map) = map {
+                        Rc::make_mut(map).insert(key, val);
+                    } else {
+                        // This should - for now - never occur
+                        unreachable!();
                     }
-                    BCType::Store => {
-                        let mut state = self.state.borrow_mut();
-                        let varnum = *target.unwrap()
-                            .as_address();
-                        let val = val.unwrap();
-                        state.store(varnum, val);
-                        state.increment_pc();
+                    self.state.push(map);
+                    *pc += 1;
+                }
+                BCType::Pop => {
+                    // TODO : change POP to use 'target' instead of 'val'
+                    let tos = self.state.pop()?;
+                    let varnum = *bc.val.as_ref().unwrap().as_address();
+                    self.state.store(varnum, tos);
+                    *pc += 1;
+                }
+                BCType::PopN => {
+                    let i = *bc.val.as_ref().unwrap().as_int();
+                    self.state.popn(i)?;
+                    *pc += 1;
+                }
+                BCType::PopDiscard => {
+                    self.state.pop()?;
+                    *pc += 1;
+                }
+                BCType::Load => {
+                    let varnum = *bc.val.as_ref().unwrap().as_address();
+                    let val = self.state.load(varnum)?.clone();
+                    self.state.push(val);
+                    *pc += 1;
+                }
+                BCType::Store => {
+                    let varnum = *bc.target.as_ref().unwrap().as_address();
+                    self.state.store(varnum, bc.val.clone().unwrap());
+                    *pc += 1;
+                }
+                BCType::Jmp => {
+                    *pc = *bc.val.as_ref().unwrap().as_address();
+                }
+                BCType::JmpZ => {
+                    let jump_taken = match self.state.pop()? {
+                        BCVal::Bool(false) |
+                        BCVal::Nil => true,
+                        _ => false,
+                    };
+                    if jump_taken {
+                        *pc = *bc.val.as_ref().unwrap().as_address();
+                    } else {
+                        *pc += 1;
                     }
-                    BCType::Jmp => {
-                        let mut state = self.state.borrow_mut();
-                        let addr = *val.unwrap().as_address();
-                        state.set_pc(addr);
+                }
+                BCType::SymJmp => {
+                    let symbol = *bc.val.as_ref().unwrap().as_int();
+                    *pc = fun.get_label_address(symbol);
+                }
+                BCType::SymJmpZ => {
+                    let jump_taken = match self.state.pop()? {
+                        BCVal::Bool(false) |
+                        BCVal::Nil => true,
+                        _ => false,
+                    };
+                    if jump_taken {
+                        let symbol = *bc.val.as_ref().unwrap().as_int();
+                        *pc = fun.get_label_address(symbol);
+                    } else {
+                        *pc += 1;
                     }
-                    BCType::JmpZ => {
-                        let mut state = self.state.borrow_mut();
-                        let jump_taken = {
-                            let tos = state.pop()?;
-                            match tos {
-                                BCVal::Bool(false) |
-                                BCVal::Nil => true,
-                                _ => false,
-                            }
-                        };
-                        if jump_taken {
-                            let addr = *val.unwrap().as_address();
-                            state.set_pc(addr);
-                        } else {
-                            state.increment_pc();
-                        }
+                }
+                BCType::Call => {
+                    let index = self.call_index(bc.val.as_ref().unwrap())?;
+                    if let &Fun::UserFun(ref callee) = self.fun_table.get(index) {
+                        self.state.set_pc(*pc);
+                        self.state.push_fun(callee.clone().into());
+                        *fun = callee.clone();
+                        *pc = 0;
+                    } else {
+                        self.call_frameless(index)?;
+                        *pc += 1;
                     }
-                    BCType::SymJmp => {
-                        let mut state = self.state.borrow_mut();
-                        let symbol = *val.unwrap().as_int();
-                        let addr = fun.get_label_address(symbol);
-                        state.set_pc(addr);
+                }
+                BCType::TailCall => {
+                    let index = self.call_index(bc.val.as_ref().unwrap())?;
+                    if let &Fun::UserFun(ref callee) = self.fun_table.get(index) {
+                        // replace the current frame with the callee, starting it from scratch
+                        *self.state.current_fun_mut() = callee.clone().into();
+                        *fun = callee.clone();
+                        *pc = 0;
+                    } else {
+                        // anything but a user function doesn't get a frame to begin with
+                        self.call_frameless(index)?;
+                        *pc += 1;
                     }
-                    BCType::SymJmpZ => {
-                        let mut state = self.state.borrow_mut();
-                        let jump_taken = {
-                            let tos = state.pop()?;
-                            match tos {
-                                BCVal::Bool(false) |
-                                BCVal::Nil => true,
-                                _ => false,
-                            }
-                        };
-                        if jump_taken {
-                            let symbol = *val.unwrap().as_int();
-                            let addr = fun.get_label_address(symbol);
-                            state.set_pc(addr);
-                        } else {
-                            state.increment_pc();
+                }
+                BCType::Ret => {
+                    if self.state.running.len() > base {
+                        if self.state.call_stack.len() == 1 {
+                            // a coroutine's function has returned, so it's finished
+                            self.leave_coroutine(true, fun, pc);
+                            self.state.push(BCVal::Nil);
+                            continue;
                         }
+                    } else if self.state.call_stack.len() == depth {
+                        break;
                     }
-                    BCType::Call => {
-                        let val = val.unwrap();
-                        let fun_name = val.as_ident();
-                        self.invoke(fun_name)?;
-                        let mut state = self.state.borrow_mut();
-                        state.increment_pc();
-                    }
-                    BCType::Ret => break,
-                    BCType::Nop | BCType::Label => {
-                        let mut state = self.state.borrow_mut();
-                        state.increment_pc();
-                    },
+                    self.state.pop_fun();
+                    let caller = self.state.current_fun();
+                    *fun = caller.fun.clone();
+                    *pc = caller.pc + 1;
+                }
+                BCType::Add | BCType::Sub | BCType::Mul | BCType::Div | BCType::Eq |
+                BCType::Ne | BCType::Lt | BCType::Gt | BCType::Le | BCType::Ge |
+                BCType::Dup | BCType::Depth => {
+                    let op: BuiltinFun = match bc.bc_type {
+                        BCType::Add => plus,
+                        BCType::Sub => minus,
+                        BCType::Mul => times,
+                        BCType::Div => divide,
+                        BCType::Eq => equals,
+                        BCType::Ne => not_equals,
+                        BCType::Lt => less_than,
+                        BCType::Gt => greater_than,
+                        BCType::Le => lt_equals,
+                        BCType::Ge => gt_equals,
+                        BCType::Dup => tos,
+                        BCType::Depth => stack_size,
+                        _ => unreachable!(),
+                    };
+                    op(&mut self.state)?;
+                    *pc += 1;
+                }
+                BCType::Nop | BCType::Label => {
+                    *pc += 1;
                 }
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use prelude::*;
+
+    #[test]
+    fn test_invoke_unknown_fun() {
+        let mut vm = VM::new(BCFunTable::new());
+        let err = vm.invoke("foo").unwrap_err();
+        assert_eq!(err.to_string(), "attempted to call unknown function `foo`");
+        // a program without a main function can't be run either
+        assert!(vm.run().is_err());
+    }
+}
@@ -0,0 +1,77 @@
+//! Setup that's shared by the integration tests. Each test only uses some of this.
+#![allow(dead_code, unused_macros)]
+
+use sbl::prelude::*;
+
+/// Parses and preprocesses a code string. Code that doesn't parse is a bug in the test, so that
+/// panics.
+pub fn parse(code: &str) -> AST {
+    let tokenizer = Tokenizer::new("test", code);
+    let mut parser = Parser::new(tokenizer);
+    AST {
+        ast: parser.parse().expect("Parse error"),
+        path: "test".into(),
+    }.preprocess::<&str>(&[]).expect("Preprocess error")
+}
+
+/// Compiles a code string to IR with the standard builtins, optionally looking up its foreign
+/// functions while compiling.
+pub fn compile_ir(code: &str, check_foreign: bool) -> Result<IRFunTable> {
+    CompileIR::new(&parse(code))
+        .builtins(Builtins::standard())
+        .check_foreign(check_foreign)
+        .compile()
+}
+
+/// Compiles a code string against a set of builtins, optionally running all optimizations on it.
+/// Code that doesn't parse is a bug in the test, so that panics; compile errors are returned.
+pub fn compile_with(code: &str, builtins: &Builtins, optimize: bool) -> Result<BCFunTable> {
+    let ast = parse(code);
+    let ir_compiler = CompileIR::new(&ast).builtins(builtins);
+    let bc_compiler = CompileBytes::new(ir_compiler.compile()?);
+    let fun_table = bc_compiler.compile()?;
+    if optimize {
+        Ok(OptimizePipeline::new(fun_table).optimize())
+    } else {
+        Ok(fun_table)
+    }
+}
+
+/// Compiles a code string with all of the standard builtins available.
+pub fn compile(code: &str, optimize: bool) -> Result<BCFunTable> {
+    compile_with(code, &Builtins::standard(), optimize)
+}
+
+/// Runs a function table, returning the VM state afterwards.
+pub fn run_fun_table(fun_table: BCFunTable) -> Result<State> {
+    let mut vm = VM::new(fun_table);
+    vm.run()?;
+    Ok(vm.into())
+}
+
+/// Compiles and runs a code string against a set of builtins, returning the VM state afterwards.
+pub fn run_with(code: &str, builtins: &Builtins, optimize: bool) -> Result<State> {
+    run_fun_table(compile_with(code, builtins, optimize)?)
+}
+
+/// Compiles and runs a code string with all of the standard builtins available, returning the VM
+/// state afterwards.
+pub fn run(code: &str, optimize: bool) -> Result<State> {
+    run_with(code, &Builtins::standard(), optimize)
+}
+
+/// Runs a code string both with and without optimizations, and makes sure that the resultant
+/// stacks match the expected value. Nothing should be left running afterwards either: no try
+/// blocks, and no coroutines.
+macro_rules! stack_test {
+    ($code:expr, $expected:expr) => {{
+        let expected: Vec<BCVal> = $expected;
+        for &optimize in &[false, true] {
+            let state = ::common::run($code, optimize).expect("Runtime error");
+            assert_eq!(state.stack, expected, "optimize: {}", optimize);
+            assert!(state.handlers.is_empty());
+            assert!(state.suspended.is_empty());
+            assert!(state.running.is_empty());
+        }
+    }}
+}
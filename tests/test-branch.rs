@@ -121,3 +121,69 @@ fn test_br_elbr_el() {
     state_test!(r#"main { br 1111 { 5678 br T { 2222 } elbr T { 4444 } elbr F { 4444 } el { 3333 } 8765 } elbr T { 4444 } elbr F { 4444 } el { 5678 br T { 2222 } elbr T { 4444 } elbr F { 4444 } 8765 } }"#, vec![BCVal::Int(5678), BCVal::Int(2222), BCVal::Int(8765)]);
     state_test!(r#"main { br 1111 { 5678 br T { 2222 } elbr T { 4444 } elbr F { 4444 } el { 3333 } 8765 } elbr T { 4444 } elbr F { 4444 } el { 5678 br T { 2222 } elbr T { 4444 } elbr F { 4444 } 8765 } }"#, vec![BCVal::Int(5678), BCVal::Int(2222), BCVal::Int(8765)]);
 }
+
+/// Compiles a code string to IR, returning the compiler's warnings.
+fn match_warnings(code: &str) -> Result<Vec<String>> {
+    let tokenizer = Tokenizer::new("test", code);
+    let mut parser = Parser::new(tokenizer);
+    let ast = AST {
+        ast: parser.parse().expect("Parse error"),
+        path: "test".into(),
+    };
+    let ir_compiler = CompileIR::new(&ast);
+    let warnings = ir_compiler.warnings().into_iter().map(|w| w.message).collect();
+    ir_compiler.compile()?;
+    Ok(warnings)
+}
+
+#[test]
+fn test_match() {
+    let code = |val: &str| format!(r#"
+        record point {{ x y }}
+        main {{
+            {}
+            match .v {{
+                int {{ v 1 }}
+                string char {{ v 2 }}
+                point {{ 3 }}
+                nil {{ 4 }}
+                el {{ v 5 }}
+            }}
+        }}
+    "#, val);
+    state_test!(code("10"), vec![BCVal::Int(10), BCVal::Int(1)]);
+    state_test!(code(r#""s""#), vec![BCVal::String("s".into()), BCVal::Int(2)]);
+    state_test!(code("'c"), vec![BCVal::Char('c'), BCVal::Int(2)]);
+    state_test!(code("1 2 point"), vec![BCVal::Int(3)]);
+    state_test!(code("@"), vec![BCVal::Int(4)]);
+    state_test!(code("T"), vec![BCVal::Bool(true), BCVal::Int(5)]);
+    state_test!(code("1.5"), vec![BCVal::Float(1.5), BCVal::Int(5)]);
+    // without an el arm, unmatched values fall through
+    state_test!(r#"main { 1 [ 2 ] match .v { map { 3 } } }"#, vec![BCVal::Int(1)]);
+    // nested matches
+    state_test!(r#"main { 1 'a match .v { int { match .v { char { 2 } } } char { v match .v { char { 3 } } } } }"#,
+                vec![BCVal::Int(1), BCVal::Int(3)]);
+}
+
+#[test]
+fn test_match_errors() {
+    assert!(match_warnings("main { 1 match .v { double { } } }").is_err());
+    assert!(match_warnings("main { 1 match .v { int { } int char { } } }").is_err());
+    assert!(match_warnings("record int { } main { }").is_err());
+}
+
+#[test]
+fn test_match_warnings() {
+    assert_eq!(
+        match_warnings("main { 1 match .v { int { } el { } } }").unwrap(),
+        Vec::<String>::new()
+    );
+    assert_eq!(
+        match_warnings("main { 1 match .v { int float char string bool stack map ref error coroutine handle pointer buffer nil { } } }").unwrap(),
+        Vec::<String>::new()
+    );
+    assert_eq!(
+        match_warnings("record point { x } main { br T { 1 match .v { int string char bool { } } } }").unwrap(),
+        vec!["match has no `el` arm, and does not cover these types: float, stack, map, ref, error, coroutine, handle, pointer, buffer, nil, point".to_string()]
+    );
+}
@@ -0,0 +1,102 @@
+extern crate sbl;
+#[macro_use]
+mod common;
+
+use sbl::prelude::*;
+use common::*;
+
+#[test]
+fn test_generators() {
+    // coroutines finish by pushing nil
+    stack_test!(
+        r#"
+        counter { 0 .i loop i 3 < { i yield i 1 + .i } }
+        main { spawn counter .c c !done c resume c resume c resume c resume c !done }
+        "#,
+        vec![
+            BCVal::Bool(false),
+            BCVal::Int(0),
+            BCVal::Int(1),
+            BCVal::Int(2),
+            BCVal::Nil,
+            BCVal::Bool(true),
+        ]
+    );
+    // blocks can be spawned, too
+    stack_test!(
+        "main { spawn { 1 .n loop T { n yield n 2 * .n } } .g g resume g resume g resume }",
+        vec![BCVal::Int(1), BCVal::Int(2), BCVal::Int(4)]
+    );
+    // copies of a coroutine are the same coroutine
+    stack_test!(
+        "main { spawn { 1 yield 2 yield } ^ resume .a resume a }",
+        vec![BCVal::Int(2), BCVal::Int(1)]
+    );
+}
+
+#[test]
+fn test_coroutine_contexts() {
+    // coroutines have their own stack
+    stack_test!("main { 5 6 spawn { # yield } resume }", vec![BCVal::Int(5), BCVal::Int(6), BCVal::Int(0)]);
+    // ...and their own call stack, so they can yield from inside of a call
+    stack_test!(
+        r#"
+        emit { 10 * yield }
+        gen { 1 emit 2 emit }
+        main { spawn gen .g g resume g resume g resume }
+        "#,
+        vec![BCVal::Int(10), BCVal::Int(20), BCVal::Nil]
+    );
+    // coroutines may resume other coroutines, which makes for lazy pipelines
+    stack_test!(
+        r#"
+        naturals { 1 .n loop T { n yield n 1 + .n } }
+        doubled { spawn naturals .src loop T { src resume 2 * yield } }
+        main {
+            spawn doubled .g
+            [ ] .out
+            loop out !len 4 < { out g resume ^push .out }
+            out
+        }
+        "#,
+        vec![BCVal::Stack(vec![BCVal::Int(2), BCVal::Int(4), BCVal::Int(6), BCVal::Int(8)].into())]
+    );
+    // refs that only a suspended coroutine can reach aren't collected
+    stack_test!(
+        r#"
+        main {
+            spawn { 42 ref .r 0 yield r !deref yield } .g
+            g resume .@
+            0 .i loop i 3000 < { i ref .x i 1 + .i }
+            g resume
+        }
+        "#,
+        vec![BCVal::Int(42)]
+    );
+}
+
+#[test]
+fn test_coroutine_errors() {
+    // errors that a coroutine doesn't catch finish it, and go to whatever resumed it
+    stack_test!(
+        r#"main { spawn { 1 yield "bad" throw } .g g resume try { g resume } catch { g !done } }"#,
+        vec![BCVal::Int(1), BCVal::String("bad".into()), BCVal::Bool(true)]
+    );
+    // coroutines can catch their own errors
+    stack_test!(
+        "main { spawn { try { 1 throw } catch { yield } } resume }",
+        vec![BCVal::Int(1)]
+    );
+    for &optimize in &[false, true] {
+        assert!(run("main { spawn { } .g g resume g resume }", optimize).is_err());
+        assert!(run("main { 1 yield }", optimize).is_err());
+        assert!(run("main { 1 resume }", optimize).is_err());
+    }
+    assert!(compile("yield { } main { }", false).is_err());
+    // locals can't be named after coroutine instructions either, since they'd never be loaded
+    assert!(compile("main { 1 .resume }", false).is_err());
+    assert!(compile("main { 1 .yield }", false).is_err());
+    assert!(compile("main { spawn nothing }", false).is_err());
+    assert!(compile("main { spawn ^print }", false).is_err());
+    assert!(compile("main { bake { spawn { } } }", false).is_err());
+}
@@ -0,0 +1,92 @@
+extern crate sbl;
+#[macro_use]
+mod common;
+
+use sbl::prelude::*;
+use common::*;
+
+#[test]
+fn test_throw() {
+    // thrown values are caught as-is
+    stack_test!("main { try { 1 2 throw 3 } catch { 4 } }", vec![BCVal::Int(2), BCVal::Int(4)]);
+    // the stack is restored to its size from the start of the try block
+    stack_test!("main { 1 try { 2 3 [ 4 ] throw } catch { .e e !len } }", vec![BCVal::Int(1), BCVal::Int(1)]);
+    // including values that the try block popped from below where it started
+    stack_test!(
+        "main { 1 2 3 try { .@ .@ .@ 1 0 / } catch { .e } # }",
+        vec![BCVal::Int(1), BCVal::Int(2), BCVal::Int(3), BCVal::Int(3)]
+    );
+    stack_test!(
+        "pop-two { .@ .@ 1 0 / } main { 1 2 try { pop-two } catch { .e } }",
+        vec![BCVal::Int(1), BCVal::Int(2)]
+    );
+    // and values that were popped by a try block inside of it
+    stack_test!(
+        "main { 1 2 3 try { .@ try { .@ .@ 1 0 / } catch { .e } 5 0 / } catch { .e } # }",
+        vec![BCVal::Int(1), BCVal::Int(2), BCVal::Int(3), BCVal::Int(3)]
+    );
+    stack_test!(
+        "main { 1 2 3 try { .@ try { .@ } catch { } .@ 1 0 / } catch { .e } # }",
+        vec![BCVal::Int(1), BCVal::Int(2), BCVal::Int(3), BCVal::Int(3)]
+    );
+    // try blocks that finish don't run the catch block
+    stack_test!("main { try { 1 } catch { 2 } 3 }", vec![BCVal::Int(1), BCVal::Int(3)]);
+    // uncaught values are errors
+    match run(r#"main { "oops" throw }"#, true) {
+        Err(Error(ErrorKind::Thrown(ref val), _)) => assert_eq!(val, "oops"),
+        _ => panic!("expected a thrown error"),
+    }
+}
+
+#[test]
+fn test_catch_unwinds_calls() {
+    let code = r#"
+        inner { 1 2 "deep" throw }
+        middle { 0 inner 5 }
+        main { 9 try { middle 6 } catch { "caught" } 7 }
+    "#;
+    stack_test!(code, vec![
+        BCVal::Int(9),
+        BCVal::String("deep".into()),
+        BCVal::String("caught".into()),
+        BCVal::Int(7),
+    ]);
+    // functions that catch their own errors return normally; the arguments that `/` popped are
+    // put back, so the catch block drops them along with the error
+    stack_test!(
+        "safe-div { try { / } catch { .@ .@ .@ 0 } } main { 6 0 safe-div 6 3 safe-div }",
+        vec![BCVal::Int(0), BCVal::Int(2)]
+    );
+}
+
+#[test]
+fn test_nested_try() {
+    // inner catch blocks may rethrow to outer ones
+    stack_test!(
+        r#"main { try { try { 1 throw } catch { 1 + throw } } catch { 10 * } }"#,
+        vec![BCVal::Int(20)]
+    );
+    // a try block that has finished doesn't catch errors that come after it
+    stack_test!(
+        r#"main { try { try { 1 } catch { 2 } "after" throw } catch { } }"#,
+        vec![BCVal::String("after".into())]
+    );
+}
+
+#[test]
+fn test_builtin_errors() {
+    // builtin failures are caught as error values, which know where they came from
+    for &optimize in &[false, true] {
+        let state = run("main { try { 1 @ + } catch { ^error-range .r !error-message r } }", optimize)
+            .expect("Runtime error");
+        assert_eq!(state.stack.len(), 2);
+        assert!(state.stack[0].as_string().contains("non-integers"));
+        assert!(state.stack[1].as_string().contains("1:18-19"), "range: {}", state.stack[1]);
+    }
+    stack_test!("main { try { .@ } catch { match .e { error { T } el { F } } } }", vec![BCVal::Bool(true)]);
+    // errors can be made and thrown by programs, too
+    stack_test!(
+        r#"main { try { "bad input" error throw } catch { ^error-range .r !error-message r } }"#,
+        vec![BCVal::String("bad input".into()), BCVal::Nil]
+    );
+}
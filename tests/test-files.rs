@@ -0,0 +1,88 @@
+extern crate sbl;
+mod common;
+
+use sbl::prelude::*;
+use common::*;
+use std::env;
+use std::fs;
+
+/// Gets a path in the temp directory for a test to use, removing anything that's already there.
+fn temp_path(name: &str) -> String {
+    let path = env::temp_dir().join(format!("sbl-test-{}", name));
+    let _ = fs::remove_file(&path);
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn test_read_write() {
+    let path = temp_path("read-write");
+    let code = format!(r#"
+        main {{
+            "{0}" .path
+            path "w" open "first\n" ^write "second\n" ^write "third" !write
+            path "a" open "!" ^write close
+            path "r" open .h
+            h ^read-line .a ^read-all .rest close
+            h !read-line
+        }}
+    "#, path);
+    let result = run(&code, true);
+    // the handle was closed, so the last read fails
+    assert!(result.unwrap_err().to_string().contains("closed"));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "first\nsecond\nthird!");
+
+    let code = format!(r#"
+        main {{
+            "{0}" "r" open .h
+            h !read-line h !read-line h !read-line h !read-line
+            h !read-all
+            h close
+        }}
+    "#, path);
+    let state = run(&code, true).expect("Runtime error");
+    assert_eq!(state.stack, vec![
+        BCVal::String("first".into()),
+        BCVal::String("second".into()),
+        BCVal::String("third!".into()),
+        BCVal::Nil,
+        BCVal::String("".into()),
+    ]);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_exists_delete() {
+    let path = temp_path("exists-delete");
+    let code = format!(r#"
+        main {{
+            "{0}" .path
+            path exists
+            path "w" open close
+            path exists
+            path delete
+            path exists
+        }}
+    "#, path);
+    let state = run(&code, true).expect("Runtime error");
+    assert_eq!(state.stack, vec![BCVal::Bool(false), BCVal::Bool(true), BCVal::Bool(false)]);
+}
+
+#[test]
+fn test_file_errors() {
+    let path = temp_path("errors");
+    // errors carry the message from the OS
+    let err = run(&format!(r#"main {{ "{}" "r" open }}"#, path), true).unwrap_err();
+    assert!(err.to_string().contains(&path), "{}", err);
+    assert!(err.to_string().contains("No such file"), "{}", err);
+    assert!(run(&format!(r#"main {{ "{}" delete }}"#, path), true).is_err());
+    assert!(run(&format!(r#"main {{ "{}" "rw" open }}"#, path), true).is_err());
+    // they can be caught like any other error
+    let state = run(&format!(r#"main {{ try {{ "{}" delete }} catch {{ match .e {{ error {{ T }} }} }} }}"#, path), true)
+        .expect("Runtime error");
+    assert_eq!(state.stack, vec![BCVal::Bool(true)]);
+    // handles may only be used the way that they were opened
+    assert!(run(&format!(r#"main {{ "{}" "w" open ^read-line }}"#, path), true).is_err());
+    assert!(run(&format!(r#"main {{ "{}" "r" open "x" ^write }}"#, path), true).is_err());
+    assert!(run(&format!(r#"main {{ "{}" "w" open ^ close close }}"#, path), true).is_err());
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,282 @@
+extern crate libc;
+extern crate sbl;
+#[macro_use]
+mod common;
+
+use sbl::prelude::*;
+use common::*;
+use std::ffi::CStr;
+
+/// Gets the code for a program that runs some code after a foreign block with the given
+/// declarations from libc.
+fn libc(decls: &str, code: &str) -> String {
+    format!(r#"foreign "libc.so.6" {{ {} }} main {{ {} }}"#, decls, code)
+}
+
+/// Runs some code after a foreign block with the given declarations from libc.
+fn run_libc(decls: &str, code: &str) -> Result<State> {
+    run(&libc(decls, code), true)
+}
+
+/// Gets the message that the C library has for an errno value.
+fn strerror(errnum: libc::c_int) -> String {
+    unsafe { CStr::from_ptr(libc::strerror(errnum)) }.to_string_lossy().into_owned()
+}
+
+macro_rules! foreign_test {
+    ($decls:expr, $code:expr, $expected:expr) => {
+        stack_test!(&libc($decls, $code), $expected)
+    }
+}
+
+#[test]
+fn test_foreign_ints() {
+    foreign_test!("int abs [ int ]", "-12 abs", vec![BCVal::Int(12)]);
+    // longs are wider than ints
+    foreign_test!("long labs [ long ]", "-12345678901 labs", vec![BCVal::Int(12345678901)]);
+    foreign_test!("size_t strlen [ string ]", r#""hello" strlen"#, vec![BCVal::Int(5)]);
+    // returns are cut down to the width that they're declared with
+    foreign_test!("u8 abs [ int ]", "-258 abs", vec![BCVal::Int(2)]);
+    foreign_test!("i8 abs [ int ]", "-255 abs", vec![BCVal::Int(-1)]);
+    foreign_test!("char toupper [ int ]", "97 toupper", vec![BCVal::Char('A')]);
+    foreign_test!("bool isdigit [ char ]", "'7 isdigit", vec![BCVal::Bool(true)]);
+    foreign_test!("void srand [ u32 ]", "1 srand", vec![]);
+}
+
+#[test]
+fn test_foreign_floats() {
+    let state = run(r#"
+        foreign "libm.so.6" {
+            double pow [ double double ]
+            float sqrtf [ float ]
+        }
+        main { 10.0 2.0 pow 2.25 sqrtf }
+    "#, true).expect("Runtime error");
+    // arguments are popped in order, so the top of the stack is the first argument
+    assert_eq!(state.stack, vec![BCVal::Float(1024.0), BCVal::Float(1.5)]);
+}
+
+#[test]
+fn test_foreign_range_checks() {
+    let err = run_libc("int abs [ int ]", "4294967296 abs").unwrap_err();
+    assert!(err.to_string().contains("argument 1 of `abs` is out of range for int"), "{}", err);
+    assert!(run_libc("void srand [ u32 ]", "-1 srand").is_err());
+    assert!(run_libc("u8 abs [ u8 ]", "256 abs").is_err());
+    assert!(run_libc("size_t strlen [ string ]", "1 strlen").is_err());
+    assert!(run_libc("int abs [ int ]", "1.0 abs").is_err());
+    // range errors can be caught
+    foreign_test!(
+        "i8 abs [ i8 ]",
+        "try { 128 abs } catch { match .e { error { T } } }",
+        vec![BCVal::Bool(true)]
+    );
+}
+
+#[test]
+fn test_foreign_pointers() {
+    // returned strings are copied, and null pointers are nil
+    foreign_test!(
+        "string strchr [ string int ]",
+        r#"108 "hello" strchr 122 "hello" strchr"#,
+        vec![BCVal::String("llo".into()), BCVal::Nil]
+    );
+    foreign_test!(
+        "ptr malloc [ size_t ] void free [ ptr ]",
+        "16 malloc .p p free @ free p match .p { pointer { T } }",
+        vec![BCVal::Bool(true)]
+    );
+    // buffers are written to in place
+    foreign_test!(
+        "ptr memset [ ptr int size_t ]",
+        "4 buffer .b 3 120 b memset .p b !buffer-string",
+        vec![BCVal::String("xxx".into())]
+    );
+    foreign_test!(
+        "size_t strlen [ ptr ]",
+        r#""hello" string-buffer strlen"#,
+        vec![BCVal::Int(5)]
+    );
+    assert!(run_libc("size_t strlen [ ptr ]", r#""hello" strlen"#).is_err());
+}
+
+#[test]
+fn test_buffers() {
+    foreign_test!(
+        "",
+        "3 buffer 0 104 ^set-byte 1 105 ^set-byte ^len .n ^buffer-string .s 1 !byte n s",
+        vec![BCVal::Int(105), BCVal::Int(3), BCVal::String("hi".into())]
+    );
+    // buffers are shared between copies
+    foreign_test!("", "2 buffer .a a .b b 0 1 ^set-byte .b a 0 !byte", vec![BCVal::Int(1)]);
+    foreign_test!("", "2 buffer .a a 1 7 !set-byte a 1 !byte", vec![BCVal::Int(7)]);
+    assert!(run_libc("", "2 buffer 2 !byte").is_err());
+    assert!(run_libc("", "2 buffer 0 256 ^set-byte").is_err());
+    assert!(run_libc("", "2 buffer 2 0 !set-byte").is_err());
+    assert!(run_libc("", "-1 buffer").is_err());
+    // buffers can't be baked
+    assert!(run("main { bake { 1 buffer } }", true).is_err());
+}
+
+#[test]
+fn test_foreign_structs() {
+    let decls = "
+        struct timeval { long tv_sec long tv_usec }
+        struct mixed { char c double d u16 s ptr p }
+        struct flags { bool a bool b int c }
+        int gettimeofday [ ptr ptr ]
+        ptr malloc [ size_t ]
+        void free [ ptr ]
+    ";
+    // structs are filled in by C through a buffer
+    foreign_test!(
+        decls,
+        "timeval .tv @ tv gettimeofday tv !timeval-tv_sec 1500000000 >",
+        vec![BCVal::Int(0), BCVal::Bool(true)]
+    );
+    // fields are laid out the way that C does it
+    foreign_test!(
+        decls,
+        "mixed 'x set-mixed-c 2.5 set-mixed-d 65535 set-mixed-s ^mixed-c .c ^mixed-d .d ^mixed-s .s !mixed-p c d s mixed-size",
+        vec![BCVal::Nil, BCVal::Char('x'), BCVal::Float(2.5), BCVal::Int(65535), BCVal::Int(32)]
+    );
+    foreign_test!(
+        decls,
+        "mixed .m m 0 !byte m 2 !byte m 16 255 ^set-byte 17 255 ^set-byte !mixed-s",
+        vec![BCVal::Int(0), BCVal::Int(0), BCVal::Int(65535)]
+    );
+    // bools take up a single byte
+    foreign_test!(
+        decls,
+        "flags T set-flags-a T set-flags-b 7 set-flags-c .f f 0 !byte f 1 !byte f !flags-b flags-size",
+        vec![BCVal::Int(1), BCVal::Int(1), BCVal::Bool(true), BCVal::Int(8)]
+    );
+    // and can be read and written through pointers, too
+    foreign_test!(
+        decls,
+        "timeval-size malloc .m m 7 set-timeval-tv_usec !timeval-tv_usec m free",
+        vec![BCVal::Int(7)]
+    );
+    assert!(run_libc(decls, "4 buffer !timeval-tv_sec").is_err());
+    assert!(run_libc(decls, "1 !timeval-tv_sec").is_err());
+    assert!(run_libc(decls, "timeval 1.0 set-timeval-tv_sec").is_err());
+    let err = run_libc(decls, "mixed 70000 set-mixed-s").unwrap_err();
+    assert!(err.to_string().contains("field `s` of struct `mixed` is out of range for u16"), "{}", err);
+    // generated functions can't be defined twice
+    assert!(run_libc("struct timeval { long tv_sec } struct timeval { int x }", "").is_err());
+}
+
+#[test]
+fn test_foreign_callbacks() {
+    let decls = r#"
+        foreign "libc.so.6" {
+            struct cell { u8 value }
+            callback int compare-cells [ ptr ptr ]
+            callback int refuse [ ptr ptr ]
+            void qsort [ ptr size_t size_t ptr ]
+        }
+        compare-cells { !cell-value .a !cell-value .b a b - }
+        refuse { "refused" throw }
+    "#;
+    // C calls back into the VM, with the first argument on top of the stack
+    let state = run(&format!(
+        r#"{} main {{ "dcab" string-buffer .buf &compare-cells 1 4 buf qsort buf !buffer-string }}"#,
+        decls
+    ), true).expect("Runtime error");
+    assert_eq!(state.stack, vec![BCVal::String("abcd".into())]);
+    // errors in a callback come out of the foreign function that called it, and can be caught
+    let state = run(&format!(
+        r#"{} main {{
+            "dcab" string-buffer .buf
+            try {{ &refuse 1 4 buf qsort }} catch {{ .e e }}
+            &compare-cells 1 4 buf qsort buf !buffer-string
+        }}"#,
+        decls
+    ), true).expect("Runtime error");
+    assert_eq!(state.stack, vec![BCVal::String("refused".into()), BCVal::String("abcd".into())]);
+    let err = run(&format!(r#"{} main {{ "ba" string-buffer .buf &refuse 1 2 buf qsort }}"#, decls), true)
+        .unwrap_err();
+    assert!(err.to_string().contains("thrown"), "{}", err);
+    // the return value has to fit the declared type
+    let err = run(r#"
+        foreign "libc.so.6" {
+            callback int compare [ ptr ptr ]
+            void qsort [ ptr size_t size_t ptr ]
+        }
+        compare { .a .b 1.5 }
+        main { "ba" string-buffer .buf &compare 1 2 buf qsort }
+    "#, true).unwrap_err();
+    assert!(err.to_string().contains("the return value of callback `compare`"), "{}", err);
+    // callbacks have to refer to user functions
+    assert!(run_libc("callback int missing [ ]", "").is_err());
+    assert!(run_libc("int abs [ int ] callback int abs [ int ]", "").is_err());
+}
+
+#[test]
+fn test_foreign_variadic() {
+    let decls = "int snprintf [ ptr size_t string ... ]";
+    // extra arguments come from a local stack below the rest, and their types come from their
+    // values
+    foreign_test!(
+        decls,
+        r#"64 buffer .buf [ 42 "x" 2.5 'c T @ ] "%ld %s %.1f %c %d %p" 64 buf snprintf buf !buffer-string"#,
+        vec![BCVal::Int(18), BCVal::String("42 x 2.5 c 1 (nil)".into())]
+    );
+    foreign_test!(
+        decls,
+        r#"8 buffer .buf [ ] "hi" 8 buf snprintf buf !buffer-string"#,
+        vec![BCVal::Int(2), BCVal::String("hi".into())]
+    );
+    let err = run_libc(decls, r#"8 buffer .buf 1 "%ld" 8 buf snprintf"#).unwrap_err();
+    assert!(err.to_string().contains("local stack of extra arguments"), "{}", err);
+    let err = run_libc(decls, r#"8 buffer .buf [ [ 1 ] ] "%p" 8 buf snprintf"#).unwrap_err();
+    assert!(err.to_string().contains("extra argument 1 of `snprintf`"), "{}", err);
+}
+
+#[test]
+fn test_foreign_check() {
+    let ranged = |err: &Error| matches!(*err.kind(), ErrorKind::Ranged(_));
+
+    let good = r#"foreign "libc.so.6" { int abs [ int ] } main { }"#;
+    assert!(compile_ir(good, true).is_ok());
+    // missing symbols and libraries are reported at their declaration
+    let missing_symbol = r#"foreign "libc.so.6" { int abs [ int ] int abz [ int ] } main { }"#;
+    let err = compile_ir(missing_symbol, true).unwrap_err();
+    assert!(ranged(&err), "{:?}", err);
+    assert!(err.iter().any(|e| e.to_string().contains("could not find symbol `abz`")), "{:?}", err);
+    let missing_lib = r#"foreign "libnothere.so" { int abs [ int ] } main { }"#;
+    let err = compile_ir(missing_lib, true).unwrap_err();
+    assert!(ranged(&err), "{:?}", err);
+    assert!(err.iter().any(|e| e.to_string().contains("`libnothere.so`")), "{:?}", err);
+    // structs and callbacks don't come from their libraries, so they're left alone
+    let no_funs = r#"
+        foreign "libnothere.so" { struct point { int x int y } callback int f [ ] }
+        f { 0 }
+        main { }
+    "#;
+    assert!(compile_ir(no_funs, true).is_ok());
+    // the check is opt-in
+    assert!(compile_ir(missing_symbol, false).is_ok());
+    assert!(compile_ir(missing_lib, false).is_ok());
+}
+
+#[test]
+fn test_foreign_errno() {
+    let decls = "int open [ string int ] int close [ int ] long labs [ long ]";
+    // errno is saved after each call, and sticks around until the next one
+    foreign_test!(
+        decls,
+        r#"0 "/nonexistent/file" open errno ^ strerror"#,
+        vec![
+            BCVal::Int(-1),
+            BCVal::Int(libc::ENOENT as i64),
+            BCVal::String(strerror(libc::ENOENT).into()),
+        ]
+    );
+    foreign_test!(decls, "errno", vec![BCVal::Int(0)]);
+    let code = format!("-1 close errno {} ==", libc::EBADF);
+    foreign_test!(decls, &code, vec![BCVal::Int(-1), BCVal::Bool(true)]);
+    // a call that doesn't set errno leaves it at 0, rather than whatever it was before
+    foreign_test!(decls, r#"0 "/nonexistent/file" open .@ -5 labs .@ errno"#, vec![BCVal::Int(0)]);
+    assert!(run_libc(decls, r#""2" strerror"#).is_err());
+    assert!(run_libc(decls, "4294967296 strerror").is_err());
+}
@@ -0,0 +1,83 @@
+extern crate sbl;
+mod common;
+
+use sbl::prelude::*;
+use common::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn test_host_funs() {
+    let seen = Rc::new(RefCell::new(vec![]));
+    let mut builtins = Builtins::standard();
+    {
+        let seen = seen.clone();
+        builtins.register("keep", 1, move |state| {
+            let val = state.pop()?;
+            seen.borrow_mut().push(val);
+            Ok(())
+        });
+    }
+    let mut answer = 41;
+    builtins.register("answer", 0, move |state| {
+        answer += 1;
+        state.push(BCVal::Int(answer));
+        Ok(())
+    });
+
+    let stack = run_with("main { 1 2 + keep answer answer \"x\" keep }", &builtins, false).unwrap().stack;
+    assert_eq!(stack, vec![BCVal::Int(42), BCVal::Int(43)]);
+    assert_eq!(*seen.borrow(), vec![BCVal::Int(3), BCVal::String("x".into())]);
+    // host functions can be called from user functions, and the tail calls in them
+    seen.borrow_mut().clear();
+    let stack = run_with("f { answer keep } main { f f }", &builtins, true).unwrap().stack;
+    assert!(stack.is_empty());
+    assert_eq!(*seen.borrow(), vec![BCVal::Int(44), BCVal::Int(45)]);
+}
+
+#[test]
+fn test_host_funs_replace_builtins() {
+    let mut builtins = Builtins::standard();
+    builtins.register("+", 2, |state| {
+        let a = state.pop()?;
+        let b = state.pop()?;
+        match (a, b) {
+            (BCVal::Int(a), BCVal::Int(b)) => state.push(BCVal::Int(a * b)),
+            _ => return Err("expected two ints".into()),
+        }
+        Ok(())
+    });
+    // the optimizer leaves host functions alone, even when they have a builtin's name
+    for &optimize in &[false, true] {
+        assert_eq!(run_with("main { 3 4 + }", &builtins, optimize).unwrap().stack, vec![BCVal::Int(12)]);
+    }
+    // builtins that aren't in the set aren't defined
+    let err = run_with("main { 3 4 - }", &Builtins::new(), false).unwrap_err();
+    assert!(err.to_string().contains("-"), "{}", err);
+}
+
+#[test]
+fn test_host_fun_errors() {
+    let mut builtins = Builtins::new();
+    builtins.register("take-two", 2, |state| {
+        state.popn(2)?;
+        Ok(())
+    });
+    builtins.register("take-three", 2, |state| {
+        state.popn(3)?;
+        Ok(())
+    });
+    builtins.register("fail", 0, |_| Err("host function failed".into()));
+
+    assert!(run_with("main { 1 2 take-two }", &builtins, false).unwrap().stack.is_empty());
+    let err = run_with("main { 1 take-two }", &builtins, false).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "`take-two` takes 2 items off of the stack, but the stack only has 1"
+    );
+    // host functions can't take more than they say they will
+    let err = run_with("main { 1 2 3 take-three }", &builtins, false).unwrap_err();
+    assert_eq!(err.to_string(), "`take-three` takes 2 items off of the stack, but it took 3");
+    let err = run_with("main { fail }", &builtins, false).unwrap_err();
+    assert_eq!(err.to_string(), "host function failed");
+}
@@ -0,0 +1,146 @@
+extern crate sbl;
+use sbl::prelude::*;
+use std::env;
+use std::fs;
+use std::process;
+use std::cell::Cell;
+use std::rc::Rc;
+
+const LIB: &str = "
+    square { ^ * }
+    sum-to { .n 0 .total loop n 0 > { n total + .total n 1 - .n } total }
+";
+
+#[test]
+fn test_interpreter_calls() {
+    let mut interp = Interpreter::new();
+    interp.load_str("lib", LIB).unwrap();
+    interp.call("square", &[BCVal::Int(7)]).unwrap();
+    interp.call("sum-to", &[BCVal::Int(4)]).unwrap();
+    assert_eq!(interp.stack(), &[BCVal::Int(49), BCVal::Int(10)]);
+    // the first argument ends up on top of the stack, so this is `10 1 -`
+    interp.call("-", &[BCVal::Int(1), BCVal::Int(10)]).unwrap();
+    assert_eq!(interp.take_stack(), vec![BCVal::Int(49), BCVal::Int(10), BCVal::Int(9)]);
+    assert!(interp.stack().is_empty());
+    // builtins can be called directly, unknown functions can't
+    let err = interp.call("cube", &[BCVal::Int(2)]).unwrap_err();
+    assert_eq!(err.to_string(), "attempted to call unknown function `cube`");
+}
+
+#[test]
+fn test_interpreter_eval() {
+    for &optimizations in &[Optimizations::default(), Optimizations::empty()] {
+        let mut interp = Interpreter::new().optimizations(optimizations);
+        interp.load_str("lib", LIB).unwrap();
+        interp.eval("3 square 1 +").unwrap();
+        // the stack is kept between calls, and locals stay in the snippet that made them
+        interp.eval(".x x x +").unwrap();
+        interp.eval("").unwrap();
+        assert_eq!(interp.stack(), &[BCVal::Int(20)]);
+        // more code can be loaded later, and it can use what was loaded before
+        interp.load_str("more", "cube { ^ square * }").unwrap();
+        interp.eval("cube").unwrap();
+        assert_eq!(interp.take_stack(), vec![BCVal::Int(8000)]);
+    }
+}
+
+#[test]
+fn test_interpreter_state() {
+    let mut interp = Interpreter::new();
+    interp.load_str("counter", "incr { ^deref 1 + !assign }").unwrap();
+    interp.eval("0 ref").unwrap();
+    let counter = interp.stack()[0].clone();
+    interp.call("incr", &[counter.clone()]).unwrap();
+    interp.call("incr", &[counter.clone()]).unwrap();
+    // functions that are loaded later work on the same heap
+    interp.load_str("get", "get { !deref }").unwrap();
+    interp.take_stack();
+    interp.call("get", &[counter]).unwrap();
+    assert_eq!(interp.stack(), &[BCVal::Int(2)]);
+}
+
+#[test]
+fn test_interpreter_errors() {
+    let mut interp = Interpreter::new();
+    interp.load_str("lib", LIB).unwrap();
+    // code that doesn't compile isn't loaded
+    assert!(interp.load_str("bad", "square { 2 * }").is_err());
+    assert!(interp.load_str("bad", "half { 2 / } main { 1 2").is_err());
+    assert!(interp.call("half", &[BCVal::Int(4)]).is_err());
+    // errors leave the interpreter ready to run something else
+    assert!(interp.eval("1 0 /").is_err());
+    interp.take_stack();
+    let err = interp.eval("[] .s try { s ^pop } catch { 5 square .y 1 0 / }").unwrap_err();
+    assert!(err.to_string().contains("divide by zero"), "{}", err);
+    assert!(interp.state().call_stack.is_empty());
+    interp.take_stack();
+    interp.eval("4 square").unwrap();
+    assert_eq!(interp.stack(), &[BCVal::Int(16)]);
+}
+
+#[test]
+fn test_interpreter_files() {
+    let dir = env::temp_dir().join(format!("sbl-interpreter-test-{}", process::id()));
+    let lib_dir = dir.join("lib");
+    fs::create_dir_all(&lib_dir).unwrap();
+    fs::write(lib_dir.join("lib.sbl"), LIB).unwrap();
+    let main = dir.join("main.sbl");
+    fs::write(&main, "import \"lib.sbl\"\nmain { 5 sum-to square }").unwrap();
+
+    let mut interp = Interpreter::new().search_dirs(&[&lib_dir]);
+    interp.load_file(&main).unwrap();
+    interp.call("main", &[]).unwrap();
+    interp.eval("2 square").unwrap();
+    assert_eq!(interp.stack(), &[BCVal::Int(225), BCVal::Int(4)]);
+    // imports are only found in the search dirs
+    assert!(Interpreter::new().load_file(&main).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_interpreter_builtins() {
+    let mut builtins = Builtins::standard();
+    builtins.register("host-answer", 0, |state| {
+        state.push(BCVal::Int(42));
+        Ok(())
+    });
+    let mut interp = Interpreter::new().builtins(&builtins);
+    interp.load_str("lib", "answer { host-answer }").unwrap();
+    interp.call("answer", &[]).unwrap();
+    interp.eval("host-answer +").unwrap();
+    assert_eq!(interp.stack(), &[BCVal::Int(84)]);
+}
+
+#[test]
+fn test_interpreter_compiles_new_code_only() {
+    let bakes = Rc::new(Cell::new(0));
+    let mut builtins = Builtins::standard();
+    {
+        let bakes = bakes.clone();
+        builtins.register("count-bake", 0, move |_| {
+            bakes.set(bakes.get() + 1);
+            Ok(())
+        });
+    }
+    let mut interp = Interpreter::new().builtins(&builtins);
+    interp.load_str("lib", "seven { bake { count-bake 7 } } record point { x y }").unwrap();
+    assert_eq!(bakes.get(), 1);
+    // earlier bake blocks aren't run again, but new ones can call what was loaded before
+    interp.load_str("more", "fourteen { bake { count-bake seven seven + } }").unwrap();
+    interp.eval("seven fourteen").unwrap();
+    interp.eval("bake { seven 1 + }").unwrap();
+    assert_eq!(bakes.get(), 2);
+    assert_eq!(interp.take_stack(), vec![BCVal::Int(7), BCVal::Int(14), BCVal::Int(8)]);
+    // records can be matched on after they were loaded, and spawned blocks can be evaluated again
+    for _ in 0..2 {
+        interp.eval("1 2 point match .p { point { 1 } el { 2 } }").unwrap();
+        interp.eval("spawn { 3 yield } resume").unwrap();
+    }
+    assert_eq!(interp.take_stack(), vec![BCVal::Int(1), BCVal::Int(3), BCVal::Int(1), BCVal::Int(3)]);
+    // functions that were loaded before can't be defined again
+    assert!(interp.load_str("again", "seven { 8 }").is_err());
+    assert!(interp.load_str("again", "point { 8 }").is_err());
+    interp.call("seven", &[]).unwrap();
+    assert_eq!(interp.stack(), &[BCVal::Int(7)]);
+}
@@ -0,0 +1,109 @@
+extern crate sbl;
+#[macro_use]
+mod common;
+
+use sbl::prelude::*;
+use common::*;
+
+/// Gets the body of the `main` function.
+fn main_body(fun_table: &BCFunTable) -> &BCBody {
+    &fun_table["main"].as_user_fun().body
+}
+
+#[test]
+fn test_const_fold() {
+    let fun_table = compile("main { 1 2 + 3 * 4 - 9 == }", true).expect("Compile error");
+    assert!(!main_body(&fun_table).iter().any(|bc| bc.bc_type == BCType::Call));
+    assert_eq!(run_fun_table(fun_table).unwrap().stack, vec![BCVal::Bool(false)]);
+
+    stack_test!("main { 1 2 + 3 * 4 - 9 == }", vec![BCVal::Bool(false)]);
+    stack_test!("main { 10 3 - 2 / 'a 'a == }", vec![BCVal::Int(3), BCVal::Bool(true)]);
+    stack_test!("main { 5 .x x 1 + }", vec![BCVal::Int(6)]);
+}
+
+#[test]
+fn test_const_fold_user_override() {
+    // a user function named `+` is not a builtin, so it can't be folded
+    stack_test!("+ { .@ .@ 0 } main { 1 2 + }", vec![BCVal::Int(0)]);
+}
+
+#[test]
+fn test_const_fold_keeps_errors() {
+    let fun_table = compile("main { 1 0 / }", true).expect("Compile error");
+    assert!(main_body(&fun_table).iter().any(|bc| bc.bc_type == BCType::Div));
+    assert!(run_fun_table(fun_table).is_err());
+}
+
+#[test]
+fn test_const_fold_branches() {
+    let fun_table = compile("main { br 1 2 < { 5 } el { 6 } }", true).expect("Compile error");
+    assert!(!main_body(&fun_table).iter().any(|bc| bc.bc_type == BCType::JmpZ));
+    assert_eq!(run_fun_table(fun_table).unwrap().stack, vec![BCVal::Int(5)]);
+
+    stack_test!("main { br T { 1 } elbr T { 2 } el { 3 } }", vec![BCVal::Int(1)]);
+    stack_test!("main { br F { 1 } elbr @ { 2 } el { 3 } }", vec![BCVal::Int(3)]);
+    stack_test!("main { br 2 2 == { 1 } elbr F { 2 } }", vec![BCVal::Int(1)]);
+    stack_test!("main { 3 .n loop n 0 > { n n 1 - .n } }", vec![BCVal::Int(3), BCVal::Int(2), BCVal::Int(1)]);
+}
+
+#[test]
+fn test_peephole() {
+    let fun_table = compile("main { 1 2 .@ ^ .@ br F { 3 } }", true).expect("Compile error");
+    assert!(!main_body(&fun_table).iter().any(|bc| bc.bc_type == BCType::PopDiscard));
+    stack_test!("main { 1 2 .@ ^ .@ br F { 3 } }", vec![BCVal::Int(1)]);
+    stack_test!("main { 1 .x x .x x }", vec![BCVal::Int(1)]);
+    stack_test!("main { 0 .n loop n 3 < { br n 1 == { 10 } n 1 + .n } }", vec![BCVal::Int(10)]);
+}
+
+#[test]
+fn test_tail_call() {
+    let code = "count { .n br n 0 > { n 1 - count } } main { 100000 count }";
+    let fun_table = compile(code, true).expect("Compile error");
+    let count_body = &fun_table["count"].as_user_fun().body;
+    assert!(count_body.iter().any(|bc| {
+        bc.bc_type == BCType::TailCall && bc.val == Some(BCVal::Ident("count".to_string()))
+    }));
+    // this would overflow the native stack without tail calls
+    assert_eq!(run_fun_table(fun_table).unwrap().stack, vec![]);
+
+    stack_test!(
+        "fact { .n .acc br n 1 <= { acc } el { acc n * n 1 - fact } } main { 1 10 fact }",
+        vec![BCVal::Int(3628800)]
+    );
+    // calls that aren't in tail position are left alone
+    stack_test!(
+        "sum { .n br n 0 == { 0 } el { n 1 - sum n + } } main { 100 sum }",
+        vec![BCVal::Int(5050)]
+    );
+    let code = "sum { .n br n 0 == { 0 } el { n 1 - sum n + } } main { 100 sum }";
+    let fun_table = compile(code, true).expect("Compile error");
+    assert!(fun_table["sum"].as_user_fun().body.iter().any(|bc| {
+        bc.bc_type == BCType::Call && bc.val == Some(BCVal::Ident("sum".to_string()))
+    }));
+}
+
+#[test]
+fn test_native_ops() {
+    let code = "main { 6 .a 3 .b a b + a b - a b * a b / a b == a b != a b < a b > a b <= a b >= ^ # }";
+    let fun_table = compile(code, true).expect("Compile error");
+    assert!(!main_body(&fun_table).iter().any(|bc| bc.bc_type == BCType::Call));
+    stack_test!(code, vec![
+        BCVal::Int(9),
+        BCVal::Int(3),
+        BCVal::Int(18),
+        BCVal::Int(2),
+        BCVal::Bool(false),
+        BCVal::Bool(true),
+        BCVal::Bool(false),
+        BCVal::Bool(true),
+        BCVal::Bool(false),
+        BCVal::Bool(true),
+        BCVal::Bool(true),
+        BCVal::Int(11),
+    ]);
+
+    // a user function that overrides a builtin is still called
+    let fun_table = compile("+ { .@ .@ 0 } main { 1 .a a a + }", true).expect("Compile error");
+    assert!(!main_body(&fun_table).iter().any(|bc| bc.bc_type == BCType::Add));
+    stack_test!("+ { .@ .@ 0 } main { 1 .a a a + }", vec![BCVal::Int(0)]);
+}
@@ -0,0 +1,151 @@
+extern crate sbl;
+#[macro_use]
+mod common;
+
+use sbl::prelude::*;
+use common::*;
+use std::rc::Rc;
+
+fn stack(vals: &[i64]) -> BCVal {
+    BCVal::Stack(Rc::new(vals.iter().map(|i| BCVal::Int(*i)).collect()))
+}
+
+#[test]
+fn test_stack_value_semantics() {
+    // pushing to a copy of a stack doesn't change the original
+    stack_test!("main { [ 1 2 ] .a a .b b 3 ^push .b a b }", vec![stack(&[1, 2]), stack(&[1, 2, 3])]);
+    // neither does popping
+    stack_test!("main { [ 1 2 ] .a a ^pop .x .b a b x }", vec![stack(&[1, 2]), stack(&[1]), BCVal::Int(2)]);
+    // or pushing to a copy that was made with ^
+    stack_test!("main { [ 1 ] ^ 2 ^push }", vec![stack(&[1]), stack(&[1, 2])]);
+    // a stack that was passed to a function and changed there stays the same for the caller
+    stack_test!("add-one { 1 ^push } main { [] .a a add-one a }", vec![stack(&[1]), stack(&[])]);
+}
+
+#[test]
+fn test_string_value_semantics() {
+    stack_test!(
+        r#"main { "hello" .s s ^ == s !len }"#,
+        vec![BCVal::Bool(true), BCVal::Int(5)]
+    );
+}
+
+#[test]
+fn test_refs() {
+    // a counter that is shared between functions
+    stack_test!(
+        "incr { ^deref 1 + !assign } main { 0 ref .c c incr c incr c !deref }",
+        vec![BCVal::Int(2)]
+    );
+    stack_test!("main { 1 ref 2 ^xchg .old !deref old }", vec![BCVal::Int(2), BCVal::Int(1)]);
+    // copies of a ref point at the same value
+    stack_test!("main { 1 ref ^ 5 !assign !deref }", vec![BCVal::Int(5)]);
+    stack_test!("main { 1 ref 1 ref == }", vec![BCVal::Bool(false)]);
+    assert!(run("main { 1 !deref }", false).is_err());
+}
+
+#[test]
+fn test_refs_collected() {
+    // each ref is unreachable once the next one is made, including refs that point to themselves
+    let state = run(r#"
+        main {
+            100000 .n
+            loop n 0 > {
+                @ ref .r
+                r r !assign
+                n 1 - .n
+            }
+            r ^deref ==
+        }
+    "#, false).expect("Runtime error");
+    assert_eq!(state.stack, vec![BCVal::Bool(true)]);
+    assert!(state.heap.len() < 10000);
+}
+
+#[test]
+fn test_maps() {
+    stack_test!(r#"main { %[ 1 "one" 2 "two" ] 2 !get }"#, vec![BCVal::String("two".into())]);
+    stack_test!(r#"main { %[ 'a 1 ] 'b 2 ^set !len }"#, vec![BCVal::Int(2)]);
+    stack_test!(r#"main { %[ T 1 F 2 ] T ^remove T !has }"#, vec![BCVal::Bool(false)]);
+    stack_test!(r#"main { %[ "x" 1 ] "x" !has }"#, vec![BCVal::Bool(true)]);
+    stack_test!(r#"main { %[ 3 'c 1 'a 2 'b ] ^keys .k !values k }"#, vec![
+        BCVal::Stack(Rc::new(vec![BCVal::Char('a'), BCVal::Char('b'), BCVal::Char('c')])),
+        stack(&[1, 2, 3]),
+    ]);
+    // entries that aren't constant are built at runtime
+    stack_test!(r#"main { 5 .k "v" .v %[ k v ] 5 !get }"#, vec![BCVal::String("v".into())]);
+    // missing keys and keys of the wrong type are errors
+    assert!(run(r#"main { %[ 1 2 ] 3 !get }"#, false).is_err());
+    assert!(run(r#"main { [ 1 ] .k %[ k 2 ] }"#, false).is_err());
+}
+
+#[test]
+fn test_map_value_semantics() {
+    stack_test!(
+        r#"main { %[ 1 2 ] .a a .b b 3 4 ^set .b a !len b !len }"#,
+        vec![BCVal::Int(1), BCVal::Int(2)]
+    );
+}
+
+#[test]
+fn test_map_ordering() {
+    // equality and display don't depend on insertion order
+    let state = run(r#"main { %[ "b" 2 "a" 1 ] %[ "a" 1 ] "b" 2 ^set ^ .m == m }"#, false)
+        .expect("Runtime error");
+    assert_eq!(state.stack[0], BCVal::Bool(true));
+    assert_eq!(state.stack[1].to_string(), "%[a:1,b:2]");
+}
+
+#[test]
+fn test_records() {
+    let point = "record point { x y }";
+    stack_test!(&format!("{} main {{ 1 2 point ^point-x .a !point-y a }}", point), vec![
+        BCVal::Int(2),
+        BCVal::Int(1),
+    ]);
+    stack_test!(&format!("{} main {{ 1 2 point 3 set-point-x !point-x }}", point), vec![BCVal::Int(3)]);
+    // updating a copy of a record doesn't change the original
+    stack_test!(
+        &format!("{} main {{ 1 2 point .p p 5 set-point-y .q p !point-y q !point-y }}", point),
+        vec![BCVal::Int(2), BCVal::Int(5)]
+    );
+    // records know their type
+    let state = run(&format!("{} main {{ 1 2 point ^ == 1 2 point }}", point), false).expect("Runtime error");
+    assert_eq!(state.stack[0], BCVal::Bool(true));
+    assert_eq!(state.stack[1].type_string(), "point");
+    assert_eq!(state.stack[1].to_string(), "point{x:1,y:2}");
+    // baked records are kept as-is
+    stack_test!(&format!("{} main {{ bake {{ 1 2 point }} !point-y }}", point), vec![BCVal::Int(2)]);
+    // accessors check the record type
+    let err = run(&format!("{} record size {{ x y }} main {{ 1 2 size !point-x }}", point), false)
+        .unwrap_err();
+    assert!(err.to_string().contains("expected point record; instead got size"));
+    assert!(run(&format!("{} main {{ 1 point }}", point), false).is_err());
+}
+
+#[test]
+fn test_record_redefinition() {
+    let tokenizer = Tokenizer::new("test", "record point { x } point { 1 } main { }");
+    let mut parser = Parser::new(tokenizer);
+    let ast = AST {
+        ast: parser.parse().expect("Parse error"),
+        path: "test".into(),
+    };
+    assert!(CompileIR::new(&ast).builtins(&*BUILTINS).compile().is_err());
+}
+
+#[test]
+fn test_floats() {
+    stack_test!(
+        "main { 1.5 2.25 + 5.0 1.5 - 0.5 4.0 * 1.0 4.0 / }",
+        vec![BCVal::Float(3.75), BCVal::Float(3.5), BCVal::Float(2.0), BCVal::Float(0.25)]
+    );
+    stack_test!(
+        "main { 1.5 2.5 < -1.5 -1.5 == 2.0 1.0 >= }",
+        vec![BCVal::Bool(true), BCVal::Bool(true), BCVal::Bool(true)]
+    );
+    stack_test!(r#"main { 2.0 !println 0.1 }"#, vec![BCVal::Float(0.1)]);
+    // ints and floats don't mix
+    assert!(run("main { 1 1.0 + }", false).is_err());
+    assert!(run("main { 1.0 1 < }", false).is_err());
+}
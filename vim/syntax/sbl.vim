@@ -11,8 +11,9 @@ syn match sblFunction '[a-zA-Z_!$%^&|*\-+/=<>]\+' nextgroup=sblBlock skipwhite
 " Foreign block
 syn keyword sblForeign foreign nextgroup=sblForeignLib skipwhite
 syn match sblForeignLib "\"[^\"]*\"" contains=sblEscapes nextgroup=sblForeignBlock skipwhite
-syn region sblForeignBlock start='{' end='}' fold contains=sblForeignKeywords,sblForeignFunction,sblComment
-syn keyword sblForeignKeywords containedin=sblForeignBlock int string stack
+syn region sblForeignBlock start='{' end='}' fold contains=sblForeignKeywords,sblForeignFunction,sblForeignStruct,sblComment
+syn region sblForeignStruct start='{' end='}' contained contains=sblForeignKeywords,sblForeignFunction,sblComment
+syn keyword sblForeignKeywords containedin=sblForeignBlock struct callback i8 i16 i32 i64 u8 u16 u32 u64 int long size_t float double char bool string ptr void
 syn match sblForeignFunction '[a-zA-Z_][a-zA-Z_0-9]*' containedin=sblForeignBlock
 
 " Code blocks